@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::memory::{Memory, MutatorView};
+use iris::safeptr::*;
+use iris::vm::Thread;
+
+const BLOCKS: usize = 200;
+const ROUNDS: usize = 2000;
+
+fn op(op: u8) -> DecodedInstr {
+    DecodedInstr::new(encode_i(op, 0).unwrap())
+}
+
+// nat <-> nat, going through both kinds of combinator
+fn block(instrs: &mut Vec<DecodedInstr>) {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let prods = encode_i(OP_PRODS, 0).unwrap();
+    let prode = encode_i(OP_PRODE, 0).unwrap();
+
+    instrs.extend([
+        op(OP_ZEROI),
+        DecodedInstr::with_pair(sums, 1, 1),
+        op(OP_UNITI),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 1, 1),
+        op(OP_ZEROE),
+        op(OP_SWAPP),
+        DecodedInstr::with_nat(prods, 2),
+        op(OP_UNITI),
+        op(OP_UNITI),
+        DecodedInstr::with_nat(prode, 2),
+        DecodedInstr::with_nat(prods, 2),
+        op(OP_UNITE),
+        op(OP_UNITE),
+        DecodedInstr::with_nat(prode, 2),
+        op(OP_SWAPP),
+        op(OP_UNITE),
+    ]);
+}
+
+// runs the same program forwards and backwards in turn, timing each
+// direction separately
+fn main() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let mut instrs = vec![op(OP_START)];
+    for _ in 0..BLOCKS {
+        block(&mut instrs);
+    }
+    instrs.push(op(OP_END));
+
+    let function = alloc_function(&mem, &instrs).unwrap();
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(function),
+        CellPtr::new_with(mem.alloc(7 as Nat).unwrap().as_untyped(&mem)),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+
+    let mut forward = Duration::ZERO;
+    let mut backward = Duration::ZERO;
+
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        thread.run(&mem).unwrap();
+        forward += start.elapsed();
+        thread.reverse(&mem);
+
+        let start = Instant::now();
+        thread.run(&mem).unwrap();
+        backward += start.elapsed();
+        thread.reverse(&mem);
+    }
+
+    let steps = thread.steps() as f64 / 2.0;
+    println!("forward:  {:>8.2} ns/instr", forward.as_nanos() as f64 / steps);
+    println!("backward: {:>8.2} ns/instr", backward.as_nanos() as f64 / steps);
+}
@@ -0,0 +1,8 @@
+// tests compile the Rust source generated by the AOT backend, so hand
+// them the compiler cargo is building with
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    println!("cargo:rustc-env=IRIS_RUSTC={}", rustc);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
@@ -2,9 +2,58 @@ use std::mem::size_of;
 use std::ptr::NonNull;
 
 use crate::alloc::BlockError;
+use crate::alloc::checked::HeapFault;
 use crate::alloc::constants;
 
-pub trait AllocObject {}
+// the largest object the heap can hand out, since large objects spanning
+// several blocks aren't supported
+pub const MAX_OBJECT_SIZE: usize = constants::MEDIUM_OBJECT_MAX;
+
+pub trait AllocObject {
+    const TYPE_TAG: TypeTag = TypeTag::Untagged;
+}
+
+// coarse runtime type of a heap object, written into its header when
+// the heap is built with type tags
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TypeTag {
+    Untagged,
+    Sum,
+    Product,
+    Nat,
+    Unit,
+    Negative,
+    Fraction,
+    Array,
+    Context,
+    // arrays of plain words rather than pointers
+    Words,
+}
+
+impl TypeTag {
+    pub fn from_byte(byte: u8) -> TypeTag {
+        match byte {
+            1 => TypeTag::Sum,
+            2 => TypeTag::Product,
+            3 => TypeTag::Nat,
+            4 => TypeTag::Unit,
+            5 => TypeTag::Negative,
+            6 => TypeTag::Fraction,
+            7 => TypeTag::Array,
+            8 => TypeTag::Context,
+            9 => TypeTag::Words,
+            _ => TypeTag::Untagged,
+        }
+    }
+
+    // untagged objects are compatible with everything
+    pub fn matches(self, other: TypeTag) -> bool {
+        self == TypeTag::Untagged || other == TypeTag::Untagged || self == other
+    }
+}
+
+pub const HEADER_SIZE: usize = size_of::<usize>();
 
 pub trait AllocRaw {
     fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
@@ -25,6 +74,9 @@ pub trait AllocRaw {
 pub enum AllocError {
     BadRequest,
     OutOfMemory,
+    UseAfterFree(HeapFault),
+    DoubleFree(HeapFault),
+    TypeMismatch,
 }
 
 impl From<BlockError> for AllocError {
@@ -66,6 +118,7 @@ pub fn alloc_size_of(object_size: usize) -> usize {
  * RawPtr API
  */
 #[derive(Debug)]
+#[repr(transparent)]
 pub struct RawPtr<T: Sized> {
     ptr: NonNull<T>
 }
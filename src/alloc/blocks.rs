@@ -73,7 +73,7 @@ impl BumpBlock {
     }
 
     pub fn inner_alloc(&mut self, alloc_size: usize) -> Option<*const u8> {
-        let next_bump = self.cursor - alloc_size;
+        let next_bump = self.cursor.saturating_sub(alloc_size);
 
         if next_bump < self.limit { // size of allocation larger than hole
             if self.limit > constants::FIRST_OBJECT_OFFSET {
@@ -90,6 +90,7 @@ impl BumpBlock {
         } else {
             //let offset = self.cursor;
             self.cursor = next_bump;
+            self.meta.mark_object(next_bump, alloc_size);
             unsafe {
                 Some(self.block.as_ptr().add(next_bump) as *const u8)
             }
@@ -98,10 +99,19 @@ impl BumpBlock {
 
     pub fn inner_dealloc(&mut self, cursor: usize, size: usize) {
         self.meta.unmark_hole(cursor, size);
-        self.cursor = self.cursor + size;
+
+        // space can only be handed back if the object was the
+        // last one bumped, otherwise live objects would be overwritten
+        if cursor == self.cursor {
+            self.cursor = self.cursor + size;
+        }
     }
 
     pub fn current_hole_size(&self) -> usize { self.cursor - self.limit }
+    pub fn live_lines(&self) -> usize {
+        self.meta.line_iter().filter(|marked| **marked).count()
+    }
+
     pub fn as_ptr(&self) -> *const u8 { self.block.as_ptr() }
     pub fn get_lines(&self, cursor: usize, size: usize) -> Vec<bool> {
         self.meta.get_lines(cursor, size)
@@ -125,6 +135,16 @@ impl BlockMeta {
         self.line_mark[index] = true;
     }
 
+    // conservatively marks every line the object touches
+    pub fn mark_object(&mut self, cursor: usize, size: usize) {
+        let first_line = cursor / constants::LINE_SIZE;
+        let last_line = (cursor + size - 1) / constants::LINE_SIZE;
+
+        for i in first_line..=last_line {
+            self.line_mark[i] = true;
+        }
+    }
+
     /*
      * pub fn mark_block(&mut self) {
      *     self.block_mark = true;
@@ -443,4 +463,40 @@ mod tests {
         println!("count={}", count);
         assert!(count == 0);
     }
+
+    #[test]
+    fn test_oversize_alloc() {
+        let mut block = BumpBlock::new().unwrap();
+
+        // a request larger than the cursor must fail instead of underflowing
+        assert!(block.inner_alloc(constants::BLOCK_SIZE * 2).is_none());
+    }
+
+    #[test]
+    fn test_alloc_marks_lines() {
+        let mut block = BumpBlock::new().unwrap();
+
+        let ptr = block.inner_alloc(constants::LINE_SIZE + TEST_UNIT_SIZE).unwrap();
+        let cursor = ptr as usize - block.as_ptr() as usize;
+
+        // the object straddles two lines, both of which are marked
+        assert!(block.get_lines(cursor, 2 * constants::LINE_SIZE) == vec![true, true]);
+    }
+
+    #[test]
+    fn test_dealloc_not_last() {
+        let mut block = BumpBlock::new().unwrap();
+
+        let first = block.inner_alloc(TEST_UNIT_SIZE).unwrap();
+        let second = block.inner_alloc(TEST_UNIT_SIZE).unwrap() as *mut u32;
+        unsafe { *second = 1337 }
+
+        // freeing an object below the cursor keeps the cursor where it is
+        let cursor = first as usize - block.as_ptr() as usize;
+        block.inner_dealloc(cursor, TEST_UNIT_SIZE);
+        let third = block.inner_alloc(TEST_UNIT_SIZE).unwrap() as *mut u32;
+
+        assert!(third != second);
+        unsafe { assert!(*second == 1337) }
+    }
 }
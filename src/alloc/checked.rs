@@ -0,0 +1,122 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::alloc::api::AllocError;
+
+pub const POISON_BYTE: u8 = 0xDE;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HeapMode {
+    Unchecked,
+    // freed objects are poisoned and never reused, so that stale
+    // pointers and double frees can be caught
+    Checked,
+}
+
+// the instruction responsible for an allocation or free
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapSite {
+    pub opcode: u8,
+    pub ip: u32,
+}
+
+impl fmt::Display for HeapSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {} at {}", self.opcode, self.ip)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapFault {
+    pub alloc_site: Option<HeapSite>,
+    pub free_site: Option<HeapSite>,
+}
+
+impl fmt::Display for HeapFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.alloc_site {
+            Some(site) => write!(f, "allocated by {}", site)?,
+            None => write!(f, "allocated outside the VM")?,
+        }
+
+        match self.free_site {
+            Some(site) => write!(f, ", freed by {}", site),
+            None => write!(f, ", freed outside the VM"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ObjectRecord {
+    size: usize,
+    live: bool,
+    alloc_site: Option<HeapSite>,
+    free_site: Option<HeapSite>,
+}
+
+impl ObjectRecord {
+    fn fault(&self) -> HeapFault {
+        HeapFault {
+            alloc_site: self.alloc_site,
+            free_site: self.free_site,
+        }
+    }
+}
+
+/* Checked Heap Bookkeeping */
+pub struct CheckedHeap {
+    site: Cell<Option<HeapSite>>,
+    objects: RefCell<HashMap<usize, ObjectRecord>>,
+}
+
+impl CheckedHeap {
+    pub fn new() -> CheckedHeap {
+        CheckedHeap {
+            site: Cell::new(None),
+            objects: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_site(&self, site: Option<HeapSite>) { self.site.set(site); }
+
+    pub fn record_alloc(&self, addr: usize, size: usize) {
+        self.objects.borrow_mut().insert(addr, ObjectRecord {
+            size,
+            live: true,
+            alloc_site: self.site.get(),
+            free_site: None,
+        });
+    }
+
+    // returns the size the object was allocated with
+    pub fn record_free(&self, addr: usize) -> Result<Option<usize>, AllocError> {
+        match self.objects.borrow_mut().get_mut(&addr) {
+            Some(record) => {
+                if !record.live {
+                    return Err(AllocError::DoubleFree(record.fault()));
+                }
+
+                record.live = false;
+                record.free_site = self.site.get();
+                Ok(Some(record.size))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn check_live(&self, addr: usize) -> Result<(), AllocError> {
+        match self.objects.borrow().get(&addr) {
+            Some(record) if !record.live => {
+                Err(AllocError::UseAfterFree(record.fault()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for CheckedHeap {
+    fn default() -> CheckedHeap {
+        CheckedHeap::new()
+    }
+}
@@ -1,24 +1,190 @@
 use std::cell::UnsafeCell;
 use std::mem::{replace, size_of};
-use std::ptr::write;
+use std::ptr::{read, write};
 use std::slice::{from_raw_parts, from_raw_parts_mut};
 
 use crate::array::ArraySize;
+use crate::alloc::checked::{CheckedHeap, HeapMode, HeapSite, POISON_BYTE};
 use crate::alloc::constants;
 use crate::alloc::blocks::BumpBlock;
 use crate::alloc::api::*;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapOptions {
+    pub mode: HeapMode,
+    // prefix every object with a header holding its TypeTag
+    pub type_tags: bool,
+}
+
+// type tags are only on by default in debug builds, but suspending a
+// thread needs them whatever the build, so a heap whose threads may be
+// suspended must ask for them
+impl Default for HeapOptions {
+    fn default() -> HeapOptions {
+        HeapOptions {
+            mode: HeapMode::Unchecked,
+            type_tags: cfg!(debug_assertions),
+        }
+    }
+}
+
+impl HeapOptions {
+    // the default options, with the type tags suspending needs
+    pub fn suspendable() -> HeapOptions {
+        HeapOptions { type_tags: true, ..HeapOptions::default() }
+    }
+}
+
 pub struct StickyImmixHeap {
     blocks: UnsafeCell<BlockList>,
+    stats: UnsafeCell<HeapStats>,
+    checked: Option<CheckedHeap>,
+    type_tags: bool,
 }
 
 impl StickyImmixHeap {
     pub fn new() -> StickyImmixHeap {
+        StickyImmixHeap::with_options(HeapOptions::default())
+    }
+
+    pub fn with_mode(mode: HeapMode) -> StickyImmixHeap {
+        StickyImmixHeap::with_options(HeapOptions {
+            mode,
+            ..HeapOptions::default()
+        })
+    }
+
+    pub fn with_options(options: HeapOptions) -> StickyImmixHeap {
         StickyImmixHeap {
             blocks: UnsafeCell::new(BlockList::new()),
+            stats: UnsafeCell::new(HeapStats::default()),
+            checked: match options.mode {
+                HeapMode::Unchecked => None,
+                HeapMode::Checked => Some(CheckedHeap::new()),
+            },
+            type_tags: options.type_tags,
+        }
+    }
+
+    pub fn has_type_tags(&self) -> bool { self.type_tags }
+
+    fn header_size(&self) -> usize {
+        if self.type_tags { HEADER_SIZE } else { 0 }
+    }
+
+    // None if the heap was built without type tags
+    pub fn type_tag<T>(&self, object: RawPtr<T>) -> Option<TypeTag> {
+        if self.type_tags {
+            let header = (object.as_word() - HEADER_SIZE) as *const u8;
+            Some(TypeTag::from_byte(unsafe { read(header) }))
+        } else {
+            None
+        }
+    }
+
+    pub fn check_tag<T, U>(&self, object: RawPtr<T>) -> Result<(), AllocError>
+        where U: AllocObject
+    {
+        match self.type_tag(object) {
+            Some(tag) if !tag.matches(U::TYPE_TAG) => Err(AllocError::TypeMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn mode(&self) -> HeapMode {
+        match self.checked {
+            Some(_) => HeapMode::Checked,
+            None => HeapMode::Unchecked,
+        }
+    }
+
+    // attributes subsequent allocations and frees to an instruction
+    pub fn set_site(&self, site: Option<HeapSite>) {
+        if let Some(ref checked) = self.checked {
+            checked.set_site(site);
+        }
+    }
+
+    pub fn check_live<T>(&self, object: RawPtr<T>) -> Result<(), AllocError> {
+        match self.checked {
+            Some(ref checked) => checked.check_live(object.as_word()),
+            None => Ok(()),
+        }
+    }
+
+    // in checked mode, freed objects are poisoned and their lines are
+    // never handed back, so that stale pointers to them stay detectable
+    fn checked_free(
+        &self,
+        checked: &CheckedHeap,
+        addr: usize,
+        size: usize,
+        header_size: usize,
+    ) -> Result<(), AllocError> {
+        let size = checked.record_free(addr)?.unwrap_or(size);
+        let bytes = unsafe { from_raw_parts_mut(addr as *mut u8, size) };
+
+        // the header is left intact so the tag can still be read
+        for byte in bytes {
+            *byte = POISON_BYTE;
+        }
+
+        self.record_free(size + header_size);
+        Ok(())
+    }
+
+    fn record_checked_alloc(&self, addr: *const u8, size: usize) {
+        if let Some(ref checked) = self.checked {
+            checked.record_alloc(addr as usize, size);
         }
     }
 
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = unsafe { (*self.stats.get()).clone() };
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        let block_iter = blocks.head.iter()
+            .chain(blocks.overflow.iter())
+            .chain(blocks.rest.iter());
+
+        for block in block_iter {
+            let live_lines = block.live_lines();
+
+            stats.blocks.push(BlockStats {
+                live_lines,
+                free_lines: constants::LINE_COUNT - live_lines,
+            });
+        }
+
+        stats
+    }
+
+    // cheaper than a full snapshot, for counting allocations per op
+    pub fn alloc_count(&self) -> usize {
+        unsafe { (*self.stats.get()).allocs() }
+    }
+
+    fn record_alloc(&self, alloc_size: usize, size_class: SizeClass) {
+        let stats = unsafe { &mut *self.stats.get() };
+
+        match size_class {
+            SizeClass::Small => stats.small_allocs += 1,
+            SizeClass::Medium | SizeClass::Large => stats.medium_allocs += 1,
+        }
+
+        stats.bytes_allocated += alloc_size;
+        if stats.live_bytes() > stats.peak_bytes {
+            stats.peak_bytes = stats.live_bytes();
+        }
+    }
+
+    fn record_free(&self, size: usize) {
+        let stats = unsafe { &mut *self.stats.get() };
+
+        stats.frees += 1;
+        stats.bytes_freed += size;
+    }
+
     fn find_space(
         &self,
         alloc_size: usize,
@@ -27,13 +193,18 @@ impl StickyImmixHeap {
         let blocks = unsafe { &mut *self.blocks.get() };
 
         if size_class == SizeClass::Large {
+            unsafe { (*self.stats.get()).rejected_allocs += 1 };
             return Err(AllocError::BadRequest);
         }
 
         let result = match blocks.head {
             Some(ref mut head) => {
                 if size_class == SizeClass::Medium && alloc_size > head.current_hole_size() {
-                    return blocks.overflow_alloc(alloc_size);
+                    let space = blocks.overflow_alloc(alloc_size)?;
+
+                    unsafe { (*self.stats.get()).overflow_allocs += 1 };
+                    self.record_alloc(alloc_size, size_class);
+                    return Ok(space);
                 }
 
                 match head.inner_alloc(alloc_size) {
@@ -61,6 +232,7 @@ impl StickyImmixHeap {
             }
         } as *const u8;
 
+        self.record_alloc(alloc_size, size_class);
         Ok(result)
     }
 
@@ -104,11 +276,20 @@ impl StickyImmixHeap {
     pub fn make_copy(&self, ptr: UntypedPtr, size: usize)
         -> Result<UntypedPtr, AllocError>
     {
-        let alloc_size = alloc_size_of(size);
+        let header_size = self.header_size();
+        let alloc_size = alloc_size_of(size) + header_size;
         let size_class = SizeClass::get_for_size(alloc_size)?;
         
         let space = self.find_space(alloc_size, size_class)?;
 
+        // copy carries the same tag as the original
+        if let Some(tag) = self.type_tag(ptr) {
+            unsafe { write(space as *mut u8, tag as u8) };
+        }
+
+        let space = unsafe { space.add(header_size) };
+        self.record_checked_alloc(space, alloc_size - header_size);
+
         // copy data from pointer space to new space
         let u8_ptr = unsafe { ptr.cast::<u8>() };
         let orig = unsafe { from_raw_parts(u8_ptr.as_ptr(), size) };
@@ -128,15 +309,22 @@ impl AllocRaw for StickyImmixHeap {
         where T: AllocObject,
     {
         let total_size = size_of::<T>();
+        let header_size = self.header_size();
 
         // round size to next word boundary for alignment
-        let alloc_size = alloc_size_of(total_size);
+        let alloc_size = alloc_size_of(total_size) + header_size;
         let size_class = SizeClass::get_for_size(alloc_size)?;
 
         let space = self.find_space(alloc_size, size_class)?;
 
+        if self.type_tags {
+            unsafe { write(space as *mut u8, T::TYPE_TAG as u8); }
+        }
+
         // write object into space next to header
+        let space = unsafe { space.add(header_size) };
         unsafe { write(space as *mut T, object); }
+        self.record_checked_alloc(space, alloc_size - header_size);
 
         Ok(RawPtr::new(space as *const T))
     }
@@ -154,12 +342,23 @@ impl AllocRaw for StickyImmixHeap {
         -> Result<(), AllocError>
         where T: AllocObject,
     {
-        // mark block lines as unallocated
-        let obj_ptr = object.as_ptr();
-        let block = self.get_block(object.as_word()).unwrap();
+        if let Some(ref checked) = self.checked {
+            return self.checked_free(
+                checked,
+                object.as_word(),
+                size,
+                self.header_size()
+            );
+        }
+
+        // mark block lines as unallocated, including the header
+        let header_size = self.header_size();
+        let obj_ptr = object.as_word() - header_size;
+        let block = self.get_block(obj_ptr).unwrap();
 
-        let cursor = obj_ptr as usize - block.as_ptr() as usize;
-        block.inner_dealloc(cursor, size);
+        let cursor = obj_ptr - block.as_ptr() as usize;
+        block.inner_dealloc(cursor, size + header_size);
+        self.record_free(size + header_size);
 
         Ok(())
     }
@@ -179,6 +378,7 @@ impl AllocRaw for StickyImmixHeap {
         for byte in array {
             *byte = 0;
         }
+        self.record_checked_alloc(space, alloc_size);
 
         Ok(RawPtr::new(space as *const u8))
     }
@@ -191,12 +391,17 @@ impl AllocRaw for StickyImmixHeap {
         // round size to next word boundary for alignment
         let alloc_size = alloc_size_of(total_size);
 
+        if let Some(ref checked) = self.checked {
+            return self.checked_free(checked, array.as_word(), alloc_size, 0);
+        }
+
         // mark block lines as unallocated
         let array_ptr = array.as_ptr();
         let block = self.get_block(array.as_word()).unwrap();
 
         let cursor = array_ptr as usize - block.as_ptr() as usize;
         block.inner_dealloc(cursor, alloc_size);
+        self.record_free(alloc_size);
 
         Ok(())
     }
@@ -208,6 +413,57 @@ impl Default for StickyImmixHeap {
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockStats {
+    pub live_lines: usize,
+    pub free_lines: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeapStats {
+    pub small_allocs: usize,
+    pub medium_allocs: usize,
+    // large objects have no space of their own and are turned away
+    pub rejected_allocs: usize,
+    pub overflow_allocs: usize,
+    pub frees: usize,
+    pub bytes_allocated: usize,
+    pub bytes_freed: usize,
+    pub peak_bytes: usize,
+    pub blocks: Vec<BlockStats>,
+}
+
+impl HeapStats {
+    pub fn allocs(&self) -> usize {
+        self.small_allocs + self.medium_allocs
+    }
+
+    pub fn live_bytes(&self) -> usize {
+        self.bytes_allocated.saturating_sub(self.bytes_freed)
+    }
+
+    pub fn block_count(&self) -> usize { self.blocks.len() }
+
+    pub fn live_lines(&self) -> usize {
+        self.blocks.iter().map(|block| block.live_lines).sum()
+    }
+
+    pub fn free_lines(&self) -> usize {
+        self.blocks.iter().map(|block| block.free_lines).sum()
+    }
+
+    // share of marked line space not occupied by live objects
+    pub fn fragmentation(&self) -> f64 {
+        let marked_bytes = self.live_lines() * constants::LINE_SIZE;
+
+        if marked_bytes == 0 {
+            0.0
+        } else {
+            1.0 - (self.live_bytes().min(marked_bytes) as f64 / marked_bytes as f64)
+        }
+    }
+}
+
 pub struct BlockList {
     head: Option<BumpBlock>,
     overflow: Option<BumpBlock>,
@@ -453,6 +709,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_stats() {
+        let mem = StickyImmixHeap::new();
+        assert!(mem.stats() == HeapStats::default());
+
+        let ptr = mem.alloc(69 as i32).unwrap();
+        let array = mem.alloc_array(2048).unwrap();
+
+        let stats = mem.stats();
+        assert!(stats.small_allocs == 1);
+        assert!(stats.medium_allocs == 1);
+        assert!(stats.allocs() == mem.alloc_count());
+        assert!(stats.block_count() == 1);
+        assert!(stats.live_lines() > 0);
+        assert!(stats.live_lines() + stats.free_lines() == constants::LINE_COUNT);
+
+        mem.dealloc_array(array, 2048).unwrap();
+        mem.dealloc(ptr).unwrap();
+
+        let stats = mem.stats();
+        assert!(stats.frees == 2);
+        assert!(stats.live_bytes() == 0);
+        assert!(stats.peak_bytes == alloc_size_of(2048)
+                + alloc_size_of(size_of::<i32>()) + mem.header_size());
+    }
+
+    #[test]
+    fn test_type_tags() {
+        let mem = StickyImmixHeap::with_options(HeapOptions {
+            mode: HeapMode::Unchecked,
+            type_tags: true,
+        });
+
+        let ptr = mem.alloc(Tagged(420)).unwrap();
+        let untagged = mem.alloc(69 as i32).unwrap();
+        assert!(mem.type_tag(ptr) == Some(TypeTag::Nat));
+        assert!(mem.type_tag(untagged) == Some(TypeTag::Untagged));
+        assert!(mem.check_tag::<Tagged, Tagged>(ptr).is_ok());
+        assert!(mem.check_tag::<Tagged, Other>(ptr) == Err(AllocError::TypeMismatch));
+        assert!(mem.check_tag::<i32, Other>(untagged).is_ok());
+
+        let copy = mem.make_copy(unsafe { ptr.cast::<()>() }, size_of::<Tagged>()).unwrap();
+        assert!(mem.type_tag(copy) == Some(TypeTag::Nat));
+        assert!(unsafe { copy.cast::<Tagged>() }.as_ref().0 == 420);
+
+        mem.dealloc(ptr).unwrap();
+        mem.dealloc(untagged).unwrap();
+        assert!(mem.stats().frees == 2);
+
+        let untagged_heap = StickyImmixHeap::with_options(HeapOptions {
+            mode: HeapMode::Unchecked,
+            type_tags: false,
+        });
+        let ptr = untagged_heap.alloc(Tagged(1337)).unwrap();
+        assert!(untagged_heap.type_tag(ptr).is_none());
+        assert!(untagged_heap.check_tag::<Tagged, Other>(ptr).is_ok());
+    }
+
+    struct Tagged(u32);
+    struct Other;
+
+    impl AllocObject for Tagged {
+        const TYPE_TAG: TypeTag = TypeTag::Nat;
+    }
+
+    impl AllocObject for Other {
+        const TYPE_TAG: TypeTag = TypeTag::Sum;
+    }
+
     // Testing large allocations
     struct Big {
         _huge: [u8; constants::BLOCK_SIZE + 1],
@@ -472,5 +797,7 @@ mod tests {
     fn test_too_big() {
         let mem = StickyImmixHeap::new();
         assert!(mem.alloc(Big::make()) == Err(AllocError::BadRequest));
+        assert!(mem.stats().rejected_allocs == 1);
+        assert!(mem.stats().allocs() == 0);
     }
 }
@@ -1,4 +1,5 @@
 pub mod api;
+pub mod checked;
 pub mod immix;
 mod blocks;
 mod constants;
@@ -0,0 +1,936 @@
+use std::collections::BTreeMap;
+
+use crate::bytecode::*;
+use crate::constants::*;
+use crate::data::*;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::MutatorScope;
+use crate::safeptr::ScopedPtr;
+use crate::types::IType;
+use crate::value::Value;
+
+/*
+ * Ahead-of-time compilation to Rust source
+ *
+ * The entry function starting at instruction 0, and every CALL/UNCALL
+ * target it reaches, becomes Rust functions, one per direction it runs
+ * in. Types are followed through the program from
+ * the entry function's input type, so the generated code works on plain
+ * Rust data: products are tuples, lists are vectors, and each distinct
+ * list of sum variants gets an enum of its own. Shuffling sums and
+ * products is then just moving data around, and a program which doesn't
+ * type-check fails to compile rather than at runtime. A callee is
+ * compiled once for each type it's called at.
+ *
+ * Only programs with a static type in that sense compile; the rest are
+ * rejected with a CompileError. That rules out recursion, which would
+ * need a callee's type before it's been worked out; EXPN and COLN, and
+ * so the additive trace and every loop in asm.rs; READ and WRITE, whose
+ * channels only exist at runtime; and fractions. Types aren't inferred
+ * either, so inr and inl from asm.rs give 0 + a, which FOLD won't take
+ * as 1 + a. Between them, these rule out all of the stdlib but neg.
+ */
+
+// emitted at the top of every generated module; must match Value
+const PRELUDE: &str = "\
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Unit,
+    Nat(u32),
+    BigNat(Vec<u32>),
+    Sum(u32, Box<Value>),
+    Product(Box<Value>, Box<Value>),
+    Negative(Box<Value>),
+    Inductive(Vec<Value>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    Type,
+    Overflow,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Void {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigNat(pub Vec<u32>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Neg<T>(pub T);
+
+// conversion between Value and the types compiled functions work on
+pub trait Host: Sized {
+    fn from_value(v: Value) -> Result<Self, Error>;
+    fn into_value(self) -> Value;
+}
+
+impl Host for Void {
+    fn from_value(_: Value) -> Result<Self, Error> {
+        Err(Error::Type)
+    }
+
+    fn into_value(self) -> Value {
+        match self {}
+    }
+}
+
+impl Host for () {
+    fn from_value(v: Value) -> Result<Self, Error> {
+        match v {
+            Value::Unit => Ok(()),
+            _ => Err(Error::Type),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Unit
+    }
+}
+
+impl Host for u32 {
+    fn from_value(v: Value) -> Result<Self, Error> {
+        match v {
+            Value::Nat(n) => Ok(n),
+            _ => Err(Error::Type),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Nat(self)
+    }
+}
+
+impl Host for BigNat {
+    fn from_value(v: Value) -> Result<Self, Error> {
+        match v {
+            Value::BigNat(limbs) => Ok(BigNat(limbs)),
+            _ => Err(Error::Type),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::BigNat(self.0)
+    }
+}
+
+impl<A: Host, B: Host> Host for (A, B) {
+    fn from_value(v: Value) -> Result<Self, Error> {
+        match v {
+            Value::Product(a, b) => Ok((A::from_value(*a)?, B::from_value(*b)?)),
+            _ => Err(Error::Type),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Product(Box::new(self.0.into_value()), Box::new(self.1.into_value()))
+    }
+}
+
+impl<T: Host> Host for Neg<T> {
+    fn from_value(v: Value) -> Result<Self, Error> {
+        match v {
+            Value::Negative(x) => Ok(Neg(T::from_value(*x)?)),
+            _ => Err(Error::Type),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Negative(Box::new(self.0.into_value()))
+    }
+}
+
+impl<T: Host> Host for Vec<T> {
+    fn from_value(v: Value) -> Result<Self, Error> {
+        match v {
+            Value::Inductive(items) => items.into_iter().map(T::from_value).collect(),
+            _ => Err(Error::Type),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Inductive(self.into_iter().map(Host::into_value).collect())
+    }
+}
+
+#[allow(dead_code)]
+fn bignat_inc(limbs: &mut Vec<u32>) {
+    for limb in limbs.iter_mut() {
+        if *limb == u32::MAX {
+            *limb = 0;
+        } else {
+            *limb += 1;
+            return;
+        }
+    }
+    limbs.push(1);
+}
+
+#[allow(dead_code)]
+fn bignat_dec(limbs: &mut Vec<u32>) {
+    for limb in limbs.iter_mut() {
+        if *limb == 0 {
+            *limb = u32::MAX;
+        } else {
+            *limb -= 1;
+            break;
+        }
+    }
+    if limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+";
+
+// emitted after the entry function, for hosts holding Values
+const WRAPPERS: &str = "
+pub fn run_forward(v: Value) -> Result<Value, Error> {
+    Ok(entry_forward(Host::from_value(v)?)?.into_value())
+}
+
+pub fn run_backward(v: Value) -> Result<Value, Error> {
+    Ok(entry_backward(Host::from_value(v)?)?.into_value())
+}
+";
+
+impl Value {
+    // Rust expression which constructs this value in generated code
+    pub fn to_rust(&self) -> String {
+        match self {
+            Value::Unit => String::from("Value::Unit"),
+            Value::Nat(nat) => format!("Value::Nat({})", nat),
+            Value::BigNat(limbs) => format!("Value::BigNat(vec!{:?})", limbs),
+            Value::Sum(tag, inner) => {
+                format!("Value::Sum({}, Box::new({}))", tag, inner.to_rust())
+            },
+            Value::Product(fst, snd) => format!(
+                "Value::Product(Box::new({}), Box::new({}))",
+                fst.to_rust(),
+                snd.to_rust()
+            ),
+            Value::Negative(inner) => {
+                format!("Value::Negative(Box::new({}))", inner.to_rust())
+            },
+            Value::Inductive(items) => {
+                let items: Vec<String> = items.iter().map(Value::to_rust).collect();
+                format!("Value::Inductive(vec![{}])", items.join(", "))
+            },
+        }
+    }
+}
+
+pub fn compile_function<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+    input: &IType,
+) -> Result<String, RuntimeError> {
+    compile(&decode_function(guard, function)?, input)
+}
+
+// generates a Rust module with entry_forward/entry_backward for the
+// entry function, typed from its input type, run_forward/run_backward
+// taking and giving Values, and func_<start>_<direction>_<n> for each
+// type a callee is called at
+pub fn compile(instrs: &[DecodedInstr], input: &IType) -> Result<String, RuntimeError> {
+    verify_function(instrs)?;
+
+    if instrs.first().map(DecodedInstr::opcode) != Some(OP_START) {
+        return Err(compile_error("entry function must begin with START"));
+    }
+
+    let entry_end = instrs.iter()
+        .position(|instr| instr.opcode() == OP_END)
+        .ok_or(compile_error("entry function has no END"))?;
+
+    let mut parser = Parser { instrs, callees: BTreeMap::new() };
+    let entry = parser.parse(1, entry_end)?;
+
+    // callees may call further functions, so keep going until every
+    // reachable function has been parsed
+    let mut bodies = BTreeMap::new();
+    while let Some((&start, &end)) = parser.callees.iter()
+        .find(|(start, _)| !bodies.contains_key(*start))
+    {
+        let body = parser.parse(start as usize + 1, end as usize)?;
+        bodies.insert(start, body);
+    }
+
+    let mut compiler = Compiler {
+        bodies: &bodies,
+        sums: BTreeMap::new(),
+        instances: BTreeMap::new(),
+        pending: Vec::new(),
+        types: Emitter::new(0),
+        functions: Emitter::new(0),
+    };
+
+    let input = Ty::from_itype(input)?;
+    let output = compiler.function("entry_forward", &entry, false, &input)?;
+    if compiler.function("entry_backward", &entry, true, &output)? != input {
+        return Err(compile_error("entry function doesn't invert to its input type"));
+    }
+
+    Ok(format!(
+        "{}{}{}{}",
+        PRELUDE,
+        compiler.types.out,
+        compiler.functions.out,
+        WRAPPERS
+    ))
+}
+
+/* Structured functions */
+enum Node {
+    Op(Opcode),
+    Sum {
+        fwd_div: u32,
+        bwd_div: u32,
+        left: Vec<Node>,
+        right: Vec<Node>,
+    },
+    Prod {
+        first: Vec<Node>,
+        second: Vec<Node>,
+    },
+    Call {
+        start: Nat,
+        inverse: bool,
+    },
+}
+
+struct Parser<'a> {
+    instrs: &'a [DecodedInstr],
+    callees: BTreeMap<Nat, Nat>,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(&mut self, lo: usize, hi: usize) -> Result<Vec<Node>, RuntimeError> {
+        let mut nodes = Vec::new();
+        let mut index = lo;
+
+        while index < hi {
+            let instr = self.instrs[index];
+
+            match (instr.opcode(), instr.arg) {
+                (OP_SUMS, InstrArg::Pair(lc, rc)) => {
+                    let split = index + lc as usize + 1;
+                    let end = split + rc as usize;
+                    if end >= hi {
+                        return Err(RuntimeError::new(ErrorKind::BoundsError));
+                    }
+
+                    nodes.push(Node::Sum {
+                        fwd_div: decode_i(instr.op),
+                        bwd_div: decode_i(self.instrs[end].op),
+                        left: self.parse(index + 1, split)?,
+                        right: self.parse(split, end)?,
+                    });
+                    index = end + 1;
+                },
+                (OP_PRODS, InstrArg::Nat(jmp)) => {
+                    let split = index + jmp as usize;
+                    let end = find_prode(self.instrs, split, hi)?;
+
+                    nodes.push(Node::Prod {
+                        first: self.parse(index + 1, split)?,
+                        second: self.parse(split, end)?,
+                    });
+                    index = end + 1;
+                },
+                (opcode @ (OP_CALL | OP_UNCALL), InstrArg::Pair(start, end)) => {
+                    self.callees.insert(start, end);
+                    nodes.push(Node::Call { start, inverse: opcode == OP_UNCALL });
+                    index += 1;
+                },
+                (OP_START, _) => index += 1,
+                (opcode, _) if is_data_op(opcode) => {
+                    nodes.push(Node::Op(instr.op));
+                    index += 1;
+                },
+                (opcode, _) => {
+                    return Err(compile_error(&format!(
+                        "opcode {} at {} has no static translation", opcode, index
+                    )));
+                },
+            }
+        }
+
+        Ok(nodes)
+    }
+}
+
+
+/* Static types */
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Ty {
+    Zero,
+    Unit,
+    Nat,
+    BigNat,
+    // variants in tag order. An IType sum has two, and a sum of sums
+    // nests; more than two only come from S-type instructions, which
+    // work on one flat sum
+    Sum(Vec<Ty>),
+    Prod(Box<Ty>, Box<Ty>),
+    Neg(Box<Ty>),
+    Ind(Box<Ty>),
+}
+
+impl Ty {
+    fn from_itype(ty: &IType) -> Result<Ty, RuntimeError> {
+        Ok(match ty {
+            IType::Zero => Ty::Zero,
+            IType::Unit => Ty::Unit,
+            IType::Nat => Ty::Nat,
+            IType::BigNat => Ty::BigNat,
+            IType::Sum { left, right } => Ty::Sum(vec![
+                Ty::from_itype(left)?,
+                Ty::from_itype(right)?,
+            ]),
+            IType::Prod { fst, snd } => Ty::prod(Ty::from_itype(fst)?, Ty::from_itype(snd)?),
+            IType::Neg(inner) => Ty::Neg(Box::new(Ty::from_itype(inner)?)),
+            IType::Ind(inner) => Ty::Ind(Box::new(Ty::from_itype(inner)?)),
+            IType::Frac(_) => return Err(compile_error("fractions have no static translation")),
+        })
+    }
+
+    fn prod(fst: Ty, snd: Ty) -> Ty {
+        Ty::Prod(Box::new(fst), Box::new(snd))
+    }
+
+    fn as_prod(&self) -> Option<(Ty, Ty)> {
+        match self {
+            Ty::Prod(fst, snd) => Some(((**fst).clone(), (**snd).clone())),
+            _ => None,
+        }
+    }
+
+    fn as_sum(&self, count: usize) -> Option<Vec<Ty>> {
+        match self {
+            Ty::Sum(variants) if variants.len() == count => Some(variants.clone()),
+            _ => None,
+        }
+    }
+
+    // a DIST/FACT operand of count variants, which is only a sum when
+    // there's more than one
+    fn operand(variants: &[Ty]) -> Ty {
+        match variants {
+            [variant] => variant.clone(),
+            _ => Ty::Sum(variants.to_vec()),
+        }
+    }
+
+    fn operand_variants(&self, count: usize) -> Option<Vec<Ty>> {
+        match count {
+            1 => Some(vec![self.clone()]),
+            _ => self.as_sum(count),
+        }
+    }
+}
+
+/* Code generation */
+struct Emitter {
+    out: String,
+    indent: usize,
+}
+
+impl Emitter {
+    fn new(indent: usize) -> Emitter {
+        Emitter { out: String::new(), indent }
+    }
+
+    fn line(&mut self, line: &str) {
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn lines(&mut self, lines: &[&str]) {
+        for line in lines {
+            self.line(line);
+        }
+    }
+}
+
+type Instance = (Nat, bool, Ty);
+
+struct Compiler<'a> {
+    bodies: &'a BTreeMap<Nat, Vec<Node>>,
+    sums: BTreeMap<Vec<Ty>, usize>,
+    // name and output type of each callee compiled so far
+    instances: BTreeMap<Instance, (String, Ty)>,
+    pending: Vec<Instance>,
+    types: Emitter,
+    functions: Emitter,
+}
+
+impl<'a> Compiler<'a> {
+    // emits a function running body from input, giving its output type
+    fn function(
+        &mut self,
+        name: &str,
+        body: &[Node],
+        direction: bool,
+        input: &Ty,
+    ) -> Result<Ty, RuntimeError> {
+        let mut e = Emitter::new(1);
+        let output = self.body(&mut e, body, direction, input.clone())?;
+        e.line("Ok(v)");
+
+        let signature = format!(
+            "pub fn {}(v: {}) -> Result<{}, Error> {{",
+            name,
+            self.rust_type(input),
+            self.rust_type(&output)
+        );
+        self.functions.lines(&["", &signature]);
+        self.functions.out.push_str(&e.out);
+        self.functions.line("}");
+
+        Ok(output)
+    }
+
+    fn instance(
+        &mut self,
+        start: Nat,
+        direction: bool,
+        input: Ty,
+    ) -> Result<(String, Ty), RuntimeError> {
+        let key = (start, direction, input);
+        if let Some(found) = self.instances.get(&key) {
+            return Ok(found.clone());
+        }
+
+        // a function's output type has to be known before it can be
+        // called, so recursion can't be typed this way
+        if self.pending.contains(&key) {
+            return Err(compile_error(&format!("recursive call to {} has no static type", start)));
+        }
+
+        let suffix = if !direction { "forward" } else { "backward" };
+        let name = format!(
+            "func_{}_{}_{}",
+            start,
+            suffix,
+            self.instances.len() + self.pending.len()
+        );
+
+        let bodies = self.bodies;
+        self.pending.push(key.clone());
+        let output = self.function(&name, &bodies[&start], direction, &key.2);
+        self.pending.pop();
+
+        let found = (name, output?);
+        self.instances.insert(key, found.clone());
+        Ok(found)
+    }
+
+    fn body(
+        &mut self,
+        e: &mut Emitter,
+        nodes: &[Node],
+        direction: bool,
+        input: Ty,
+    ) -> Result<Ty, RuntimeError> {
+        let mut order: Vec<&Node> = nodes.iter().collect();
+        if direction {
+            order.reverse();
+        }
+
+        order.into_iter().try_fold(input, |ty, node| self.node(e, node, direction, ty))
+    }
+
+    fn node(
+        &mut self,
+        e: &mut Emitter,
+        node: &Node,
+        direction: bool,
+        ty: Ty,
+    ) -> Result<Ty, RuntimeError> {
+        match node {
+            Node::Op(op) => self.op(e, *op, direction, ty),
+            Node::Sum { fwd_div, bwd_div, left, right } => {
+                let div = if !direction { *fwd_div } else { *bwd_div } as usize;
+                let variants = match &ty {
+                    Ty::Sum(variants) => variants.clone(),
+                    _ => return Err(mismatch("sum combinator", &ty)),
+                };
+
+                // each variant runs its side of the combinator at its
+                // own type, so the branch is emitted once per variant
+                let mut arms = Vec::new();
+                let mut outputs = Vec::new();
+                for (tag, variant) in variants.into_iter().enumerate() {
+                    let mut arm = Emitter::new(e.indent + 2);
+                    let branch = if tag < div { left } else { right };
+                    outputs.push(self.body(&mut arm, branch, direction, variant)?);
+                    arms.push(arm.out);
+                }
+
+                let output = Ty::Sum(outputs);
+                let (input_name, output_name) = (self.rust_type(&ty), self.rust_type(&output));
+
+                e.line("let v = match v {");
+                e.indent += 1;
+                for (tag, arm) in arms.iter().enumerate() {
+                    e.line(&format!("{}::V{}(v) => {{", input_name, tag));
+                    e.out.push_str(arm);
+                    e.line(&format!("    {}::V{}(v)", output_name, tag));
+                    e.line("},");
+                }
+                e.indent -= 1;
+                e.line("};");
+
+                Ok(output)
+            },
+            Node::Prod { first, second } => {
+                let (fst, snd) = ty.as_prod().ok_or(mismatch("product combinator", &ty))?;
+                e.line("let (a, b) = v;");
+
+                // run the halves in the order the interpreter would
+                let (fst, snd) = if !direction {
+                    let fst = self.half(e, "a", first, direction, fst)?;
+                    (fst, self.half(e, "b", second, direction, snd)?)
+                } else {
+                    let snd = self.half(e, "b", second, direction, snd)?;
+                    (self.half(e, "a", first, direction, fst)?, snd)
+                };
+
+                e.line("let v = (a, b);");
+                Ok(Ty::prod(fst, snd))
+            },
+            Node::Call { start, inverse } => {
+                let (name, output) = self.instance(*start, *inverse != direction, ty)?;
+                e.line(&format!("let v = {}(v)?;", name));
+                Ok(output)
+            },
+        }
+    }
+
+    fn half(
+        &mut self,
+        e: &mut Emitter,
+        name: &str,
+        nodes: &[Node],
+        direction: bool,
+        input: Ty,
+    ) -> Result<Ty, RuntimeError> {
+        e.line(&format!("let {} = {{", name));
+        e.indent += 1;
+        e.line(&format!("let v = {};", name));
+        let output = self.body(e, nodes, direction, input)?;
+        e.line("v");
+        e.indent -= 1;
+        e.line("};");
+        Ok(output)
+    }
+
+    fn op(
+        &mut self,
+        e: &mut Emitter,
+        op: Opcode,
+        direction: bool,
+        ty: Ty,
+    ) -> Result<Ty, RuntimeError> {
+        let (lc, rc) = decode_s(op);
+        let (lc, rc) = (lc as usize, rc as usize);
+        let opcode = get_opcode(op, direction);
+        let mismatch = || mismatch(&format!("opcode {}", opcode), &ty);
+
+        match opcode {
+            // a flat sum reads the same however its variants are grouped
+            OP_ID | OP_ID_R | OP_ASSRS | OP_ASSLS => Ok(ty),
+            OP_ZEROI => {
+                let output = Ty::Sum(vec![Ty::Zero, ty]);
+                let output_name = self.rust_type(&output);
+                e.line(&format!("let v = {}::V1(v);", output_name));
+                Ok(output)
+            },
+            OP_ZEROE => {
+                let variants = ty.as_sum(2)
+                    .filter(|variants| variants[0] == Ty::Zero)
+                    .ok_or_else(mismatch)?;
+                let input = self.rust_type(&ty);
+
+                e.lines(&[
+                    "let v = match v {",
+                    &format!("    {}::V0(z) => match z {{}},", input),
+                    &format!("    {}::V1(v) => v,", input),
+                    "};",
+                ]);
+                Ok(variants[1].clone())
+            },
+            OP_UNITI => {
+                e.line("let v = ((), v);");
+                Ok(Ty::prod(Ty::Unit, ty))
+            },
+            OP_UNITE => {
+                let (_, a) = ty.as_prod()
+                    .filter(|(unit, _)| *unit == Ty::Unit)
+                    .ok_or_else(mismatch)?;
+
+                e.line("let (_, v) = v;");
+                Ok(a)
+            },
+            OP_SWAPP | OP_SWAPP_R => {
+                let (a, b) = ty.as_prod().ok_or_else(mismatch)?;
+                e.line("let v = (v.1, v.0);");
+                Ok(Ty::prod(b, a))
+            },
+            OP_ASSRP => {
+                let (ab, c) = ty.as_prod().ok_or_else(mismatch)?;
+                let (a, b) = ab.as_prod().ok_or_else(mismatch)?;
+                e.line("let v = { let ((a, b), c) = v; (a, (b, c)) };");
+                Ok(Ty::prod(a, Ty::prod(b, c)))
+            },
+            OP_ASSLP => {
+                let (a, bc) = ty.as_prod().ok_or_else(mismatch)?;
+                let (b, c) = bc.as_prod().ok_or_else(mismatch)?;
+                e.line("let v = { let (a, (b, c)) = v; ((a, b), c) };");
+                Ok(Ty::prod(Ty::prod(a, b), c))
+            },
+            OP_SWAPS | OP_SWAPS_R => {
+                let variants = ty.as_sum(lc + rc).ok_or_else(mismatch)?;
+                let output = Ty::Sum([&variants[lc..], &variants[..lc]].concat());
+                let (input_name, output_name) = (self.rust_type(&ty), self.rust_type(&output));
+
+                e.line("let v = match v {");
+                for tag in 0..lc + rc {
+                    let to = if tag < lc { tag + rc } else { tag - lc };
+                    e.line(&format!(
+                        "    {}::V{}(x) => {}::V{}(x),", input_name, tag, output_name, to
+                    ));
+                }
+                e.line("};");
+                Ok(output)
+            },
+            OP_DIST => {
+                let (s, c) = ty.as_prod().ok_or_else(mismatch)?;
+                let variants = s.as_sum(lc + rc).ok_or_else(mismatch)?;
+                let (a, b) = (Ty::operand(&variants[..lc]), Ty::operand(&variants[lc..]));
+                let output = Ty::Sum(vec![Ty::prod(a.clone(), c.clone()), Ty::prod(b.clone(), c)]);
+                let (input_name, output_name) = (self.rust_type(&s), self.rust_type(&output));
+
+                e.line("let v = match v {");
+                for tag in 0..lc + rc {
+                    let (side, x) = if tag < lc {
+                        (0, self.operand(lc, &a, tag))
+                    } else {
+                        (1, self.operand(rc, &b, tag - lc))
+                    };
+                    e.line(&format!(
+                        "    ({}::V{}(x), c) => {}::V{}(({}, c)),",
+                        input_name, tag, output_name, side, x
+                    ));
+                }
+                e.line("};");
+                Ok(output)
+            },
+            OP_FACT => {
+                let variants = ty.as_sum(2).ok_or_else(mismatch)?;
+                let (a, c) = variants[0].as_prod().ok_or_else(mismatch)?;
+                let (b, c_b) = variants[1].as_prod()
+                    .filter(|(_, c_b)| *c_b == c)
+                    .ok_or_else(mismatch)?;
+                let s = Ty::Sum([
+                    a.operand_variants(lc).ok_or_else(mismatch)?,
+                    b.operand_variants(rc).ok_or_else(mismatch)?,
+                ].concat());
+                let input_name = self.rust_type(&ty);
+                let s_name = self.rust_type(&s);
+
+                e.line("let v = match v {");
+                for tag in 0..lc + rc {
+                    let (side, x) = if tag < lc {
+                        (0, self.operand(lc, &a, tag))
+                    } else {
+                        (1, self.operand(rc, &b, tag - lc))
+                    };
+                    e.line(&format!(
+                        "    {}::V{}(({}, c)) => ({}::V{}(x), c),",
+                        input_name, side, x, s_name, tag
+                    ));
+                }
+                e.line("};");
+                Ok(Ty::prod(s, c_b))
+            },
+            OP_FOLD => {
+                let variants = ty.as_sum(2)
+                    .filter(|variants| variants[0] == Ty::Unit)
+                    .ok_or_else(mismatch)?;
+                let input = self.rust_type(&ty);
+                let base = format!("    {}::V0(()) =>", input);
+
+                match (decode_i(op), &variants[1]) {
+                    (FOLD_NAT, Ty::Nat) => {
+                        e.lines(&[
+                            "let v = match v {",
+                            &format!("{} 0,", base),
+                            &format!(
+                                "    {}::V1(n) => n.checked_add(1).ok_or(Error::Overflow)?,",
+                                input
+                            ),
+                            "};",
+                        ]);
+                        Ok(Ty::Nat)
+                    },
+                    (FOLD_BIGNAT, Ty::BigNat) => {
+                        e.lines(&[
+                            "let v = match v {",
+                            &format!("{} BigNat(Vec::new()),", base),
+                            &format!("    {}::V1(mut n) => {{", input),
+                            "        bignat_inc(&mut n.0);",
+                            "        n",
+                            "    },",
+                            "};",
+                        ]);
+                        Ok(Ty::BigNat)
+                    },
+                    (FOLD_NAT | FOLD_BIGNAT, _) => Err(mismatch()),
+                    (_, cons) => {
+                        let (head, _) = cons.as_prod()
+                            .filter(|(head, tail)| *tail == Ty::Ind(Box::new(head.clone())))
+                            .ok_or_else(mismatch)?;
+
+                        e.lines(&[
+                            "let v = match v {",
+                            &format!("{} Vec::new(),", base),
+                            &format!("    {}::V1((head, mut items)) => {{", input),
+                            "        items.push(head);",
+                            "        items",
+                            "    },",
+                            "};",
+                        ]);
+                        Ok(Ty::Ind(Box::new(head)))
+                    },
+                }
+            },
+            OP_UFOLD => {
+                let succ = match (decode_i(op), &ty) {
+                    (FOLD_NAT, Ty::Nat) | (FOLD_BIGNAT, Ty::BigNat) => ty.clone(),
+                    (FOLD_NAT | FOLD_BIGNAT, _) => return Err(mismatch()),
+                    (_, Ty::Ind(head)) => Ty::prod((**head).clone(), ty.clone()),
+                    _ => return Err(mismatch()),
+                };
+                let output = Ty::Sum(vec![Ty::Unit, succ]);
+                let output_name = self.rust_type(&output);
+                let base = format!("{}::V0(())", output_name);
+
+                match decode_i(op) {
+                    FOLD_NAT => e.lines(&[
+                        "let v = match v {",
+                        &format!("    0 => {},", base),
+                        &format!("    n => {}::V1(n - 1),", output_name),
+                        "};",
+                    ]),
+                    FOLD_BIGNAT => e.lines(&[
+                        "let v = match v {",
+                        &format!("    n if n.0.is_empty() => {},", base),
+                        "    mut n => {",
+                        "        bignat_dec(&mut n.0);",
+                        &format!("        {}::V1(n)", output_name),
+                        "    },",
+                        "};",
+                    ]),
+                    _ => e.lines(&[
+                        "let v = {",
+                        "    let mut items = v;",
+                        "    match items.pop() {",
+                        &format!("        None => {},", base),
+                        &format!("        Some(head) => {}::V1((head, items)),", output_name),
+                        "    }",
+                        "};",
+                    ]),
+                }
+                Ok(output)
+            },
+            OP_EXPF | OP_COLF => Err(compile_error("fractions have no static translation")),
+            _ => Err(compile_error(&format!("opcode {} has no static translation", opcode))),
+        }
+    }
+
+    // a variant of a DIST/FACT operand of count variants, as a pattern
+    // or expression over x
+    fn operand(&mut self, count: usize, ty: &Ty, tag: usize) -> String {
+        match count {
+            1 => String::from("x"),
+            _ => format!("{}::V{}(x)", self.rust_type(ty), tag),
+        }
+    }
+
+    fn rust_type(&mut self, ty: &Ty) -> String {
+        match ty {
+            Ty::Zero => String::from("Void"),
+            Ty::Unit => String::from("()"),
+            Ty::Nat => String::from("u32"),
+            Ty::BigNat => String::from("BigNat"),
+            Ty::Sum(variants) => self.sum_type(variants),
+            Ty::Prod(fst, snd) => {
+                format!("({}, {})", self.rust_type(fst), self.rust_type(snd))
+            },
+            Ty::Neg(inner) => format!("Neg<{}>", self.rust_type(inner)),
+            Ty::Ind(inner) => format!("Vec<{}>", self.rust_type(inner)),
+        }
+    }
+
+    // declares an enum for a list of variants the first time it's used
+    fn sum_type(&mut self, variants: &[Ty]) -> String {
+        if let Some(index) = self.sums.get(variants) {
+            return format!("Sum{}", index);
+        }
+
+        let fields: Vec<String> = variants.iter().map(|ty| self.rust_type(ty)).collect();
+        let name = format!("Sum{}", self.sums.len());
+        self.sums.insert(variants.to_vec(), self.sums.len());
+
+        let t = &mut self.types;
+        t.lines(&["", "#[derive(Clone, Debug, PartialEq)]", &format!("pub enum {} {{", name)]);
+        for (tag, field) in fields.iter().enumerate() {
+            t.line(&format!("    V{}({}),", tag, field));
+        }
+        t.lines(&[
+            "}",
+            "",
+            &format!("impl Host for {} {{", name),
+            "    fn from_value(v: Value) -> Result<Self, Error> {",
+            "        match v {",
+        ]);
+        for tag in 0..fields.len() {
+            t.line(&format!(
+                "            Value::Sum({}, x) => Ok({}::V{}(Host::from_value(*x)?)),",
+                tag, name, tag
+            ));
+        }
+        t.lines(&[
+            "            _ => Err(Error::Type),",
+            "        }",
+            "    }",
+            "",
+            "    fn into_value(self) -> Value {",
+            "        match self {",
+        ]);
+        for tag in 0..fields.len() {
+            t.line(&format!(
+                "            {}::V{}(x) => Value::Sum({}, Box::new(x.into_value())),",
+                name, tag, tag
+            ));
+        }
+        t.lines(&["        }", "    }", "}"]);
+
+        name
+    }
+}
+
+/* Helper functions */
+fn compile_error(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::CompileError(String::from(reason)))
+}
+
+fn mismatch(what: &str, ty: &Ty) -> RuntimeError {
+    compile_error(&format!("{} doesn't apply to {:?}", what, ty))
+}
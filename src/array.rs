@@ -3,10 +3,10 @@ use std::cell::Cell;
 use std::mem::size_of;
 use std::slice::from_raw_parts_mut;
 
-use crate::alloc::api::AllocObject;
+use crate::alloc::api::{AllocObject, RawPtr, TypeTag};
 use crate::error::{RuntimeError, ErrorKind};
 use crate::memory::{MutatorView, MutatorScope};
-use crate::safeptr::ScopedPtr;
+use crate::safeptr::{CellPtr, ScopedPtr};
 
 pub type ArraySize = u32;
 pub const DEFAULT_ARRAY_SIZE: ArraySize = 8;
@@ -23,7 +23,22 @@ pub struct Array<T: Sized + Clone> {
     borrow: Cell<BorrowFlag>,
 }
 
-impl<T: Sized + Clone> AllocObject for Array<T> {}
+impl<T: Sized + Clone + ArrayElement> AllocObject for Array<T> {
+    const TYPE_TAG: TypeTag = T::ARRAY_TAG;
+}
+
+// arrays of pointers are tagged apart from arrays of plain words, so
+// that the heap can be walked without knowing the types of its objects
+pub trait ArrayElement {
+    const ARRAY_TAG: TypeTag = TypeTag::Words;
+}
+
+impl<T: Sized> ArrayElement for CellPtr<T> {
+    const ARRAY_TAG: TypeTag = TypeTag::Array;
+}
+
+impl ArrayElement for u32 {}
+impl ArrayElement for usize {}
 
 impl<T: Sized + Clone> Array<T> {
     pub fn alloc<'guard>(
@@ -102,6 +117,27 @@ impl<T: Sized + Clone> Array<T> {
         }
     }
 
+    // releases the backing storage, leaving an empty array
+    pub fn dealloc_data(
+        &self,
+        mem: &MutatorView
+    ) -> Result<(), RuntimeError> {
+        let array = self.data.get();
+
+        if let Some(ptr) = array.as_ptr() {
+            let capacity_bytes = array.capacity() * size_of::<T>() as ArraySize;
+            mem.dealloc_array(RawPtr::new(ptr as *const u8), capacity_bytes)?;
+        }
+
+        self.data.set(RawArray::new());
+        self.length.set(0);
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// The slice aliases the array's storage, so it must not outlive a
+    /// push, pop or reallocation, and no other slice may be live.
     pub unsafe fn as_slice<'guard>(&self, _guard: &'guard dyn MutatorScope) -> &mut [T] {
         if let Some(ptr) = self.data.get().as_ptr() {
             from_raw_parts_mut(ptr as *mut T, self.length.get() as usize)
@@ -110,6 +146,9 @@ impl<T: Sized + Clone> Array<T> {
         }
     }
 
+    /// # Safety
+    ///
+    /// As for `as_slice`, and slots past the length may be uninitialized.
     pub unsafe fn as_capacity_slice<'guard>(
         &self,
         _guard: &'guard dyn MutatorScope
@@ -128,6 +167,12 @@ pub struct RawArray<T: Sized> {
     ptr: Option<NonNull<T>>,
 }
 
+impl<T: Sized> Default for RawArray<T> {
+    fn default() -> RawArray<T> {
+        RawArray::new()
+    }
+}
+
 impl<T: Sized> RawArray<T> {
     pub fn new() -> RawArray<T> {
         RawArray {
@@ -140,6 +185,11 @@ impl<T: Sized> RawArray<T> {
         mem: &'scope MutatorView,
         capacity: u32
     ) -> Result<RawArray<T>, RuntimeError> {
+        // there's no such thing as an empty heap object
+        if capacity == 0 {
+            return Ok(RawArray::new());
+        }
+
         let capacity_bytes = capacity
             .checked_mul(size_of::<T>() as ArraySize)
             .ok_or(RuntimeError::new(ErrorKind::BadAllocationRequest))?;
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use crate::bytecode::*;
+use crate::constants::*;
+use crate::data::Nat;
+use crate::error::{err_parser, RuntimeError};
+
+/*
+ * Assembler
+ *
+ * Functions are written as lists of Asm, put together by concatenating
+ * what the builders below return: single instructions, the sum and
+ * product combinators, and loops on the additive trace. Calls name
+ * their target, which is resolved once every function in the block has
+ * been laid out.
+ *
+ * The builders only deal in binary sums, so every sum division is 1; a
+ * sum of more variants is nested, as in assrs and assls.
+ */
+
+// an instruction whose call target isn't known until the whole block
+// has been laid out
+#[derive(Clone, Copy, Debug)]
+pub enum Asm {
+    Op(DecodedInstr),
+    Call(&'static str),
+    Uncall(&'static str),
+}
+
+// functions laid out one after another, with the calls between them
+// resolved
+pub struct Block {
+    pub instrs: Vec<DecodedInstr>,
+    // START and END index of each function, in the order they were given
+    pub bounds: Vec<(usize, usize)>,
+}
+
+// lays out each function between a START and an END, with calls to any
+// function in the block
+pub fn assemble(functions: Vec<(&'static str, Vec<Asm>)>) -> Result<Block, RuntimeError> {
+    let mut bounds = Vec::with_capacity(functions.len());
+    let mut targets = HashMap::new();
+    let mut start = 0;
+
+    for (name, body) in &functions {
+        let end = start + body.len() + 1;
+
+        if targets.insert(*name, (start, end)).is_some() {
+            return Err(err_parser(&format!("function {} is defined twice", name)));
+        }
+        bounds.push((start, end));
+        start = end + 1;
+    }
+
+    // now that every function has an address, calls can be resolved
+    let target = |name: &str| targets.get(name).copied()
+        .ok_or_else(|| err_parser(&format!("call to undefined function {}", name)));
+
+    let mut instrs = Vec::with_capacity(start);
+    for (_, body) in functions {
+        instrs.push(instr(OP_START));
+        for asm in body {
+            instrs.push(match asm {
+                Asm::Op(instr) => instr,
+                Asm::Call(name) => call_instr(OP_CALL, target(name)?),
+                Asm::Uncall(name) => call_instr(OP_UNCALL, target(name)?),
+            });
+        }
+        instrs.push(instr(OP_END));
+    }
+
+    Ok(Block { instrs, bounds })
+}
+
+pub(crate) fn instr(opcode: u8) -> DecodedInstr {
+    DecodedInstr::new(encode_i(opcode, 0).unwrap())
+}
+
+pub(crate) fn call_instr(opcode: u8, (start, end): (usize, usize)) -> DecodedInstr {
+    DecodedInstr::with_pair(encode_i(opcode, 0).unwrap(), start as Nat, end as Nat)
+}
+
+/* Instructions */
+pub fn op(opcode: u8) -> Vec<Asm> {
+    vec![Asm::Op(instr(opcode))]
+}
+
+// an S-type instruction, such as SWAPS or DIST, over lc + rc variants
+pub fn op_s(opcode: u8, lc: u16, rc: u16) -> Vec<Asm> {
+    vec![Asm::Op(DecodedInstr::new(encode_s(opcode, lc, rc).unwrap()))]
+}
+
+pub fn call(name: &'static str) -> Vec<Asm> {
+    vec![Asm::Call(name)]
+}
+
+pub fn uncall(name: &'static str) -> Vec<Asm> {
+    vec![Asm::Uncall(name)]
+}
+
+// 1 + nat -> nat
+pub fn fold() -> Vec<Asm> {
+    vec![Asm::Op(DecodedInstr::new(encode_i(OP_FOLD, FOLD_NAT).unwrap()))]
+}
+
+// nat -> 1 + nat
+pub fn unfold() -> Vec<Asm> {
+    vec![Asm::Op(DecodedInstr::new(encode_i(OP_UFOLD, FOLD_NAT).unwrap()))]
+}
+
+// a into b + a; run backwards, it fails on anything on the left
+pub fn inr() -> Vec<Asm> {
+    op(OP_ZEROI)
+}
+
+// a into a + b; run backwards, it fails on anything on the right
+pub fn inl() -> Vec<Asm> {
+    [op(OP_ZEROI), op_s(OP_SWAPS, 1, 1)].concat()
+}
+
+/* Combinators */
+// sum combinator over a binary sum
+pub fn sum(left: Vec<Asm>, right: Vec<Asm>) -> Vec<Asm> {
+    let (lc, rc) = (left.len() as Nat, right.len() as Nat);
+    let sums = DecodedInstr::with_pair(encode_i(OP_SUMS, 1).unwrap(), lc, rc);
+    let sume = DecodedInstr::with_pair(encode_i(OP_SUME, 1).unwrap(), lc, rc);
+
+    [vec![Asm::Op(sums)], left, right, vec![Asm::Op(sume)]].concat()
+}
+
+pub fn prod(first: Vec<Asm>, second: Vec<Asm>) -> Vec<Asm> {
+    let prods = DecodedInstr::with_nat(
+        encode_i(OP_PRODS, 0).unwrap(),
+        first.len() as Nat + 1,
+    );
+    let prode = DecodedInstr::with_nat(
+        encode_i(OP_PRODE, 0).unwrap(),
+        second.len() as Nat + 1,
+    );
+
+    [vec![Asm::Op(prods)], first, second, vec![Asm::Op(prode)]].concat()
+}
+
+// (a + b) + c <-> a + (b + c), by flattening into a + b + c and
+// splitting it again the other way
+pub fn assrs() -> Vec<Asm> {
+    [
+        sum(
+            [op(OP_UNITI), op(OP_SWAPP)].concat(),
+            [op(OP_UNITI), op(OP_SWAPP)].concat(),
+        ),
+        op_s(OP_FACT, 2, 1),
+        op_s(OP_DIST, 1, 2),
+        sum(
+            [op(OP_SWAPP), op(OP_UNITE)].concat(),
+            [op(OP_SWAPP), op(OP_UNITE)].concat(),
+        ),
+    ].concat()
+}
+
+// a + (b + c) <-> (a + b) + c
+pub fn assls() -> Vec<Asm> {
+    [
+        sum(
+            [op(OP_UNITI), op(OP_SWAPP)].concat(),
+            [op(OP_UNITI), op(OP_SWAPP)].concat(),
+        ),
+        op_s(OP_FACT, 1, 2),
+        op_s(OP_DIST, 2, 1),
+        sum(
+            [op(OP_SWAPP), op(OP_UNITE)].concat(),
+            [op(OP_SWAPP), op(OP_UNITE)].concat(),
+        ),
+    ].concat()
+}
+
+/* Loops */
+// additive trace: given a body a + b <-> a + c, b <-> c by feeding the
+// body's left outputs back into it until it produces a c. The VM keeps
+// sums nested rather than flat, so assrs and assls have to move values
+// around instead of being no-ops.
+//
+// The loop head is only ever reached backwards, where COLN reads as the
+// EXPN that turns execution forwards again. Whatever the body leaves on
+// the left must eventually come out on the right, or the loop never
+// ends; run backwards, the same goes for the body's inverse.
+pub fn trace(body: Vec<Asm>) -> Vec<Asm> {
+    [
+        inr(),                          // 0 + b
+        sum(op(OP_COLN), vec![]),       // (-a + a) + b
+        assrs(),                        // -a + (a + b)
+        sum(vec![], body),              // -a + (a + c)
+        assls(),                        // (-a + a) + c
+        sum(op(OP_COLN), vec![]),       // 0 + c
+        op(OP_ZEROE),                   // c
+    ].concat()
+}
+
+// (n, x) <-> (n, x'), running the body (i, x) <-> (i, x') once for each
+// i below n. The loop state is (n - i, (i, x)); i = 0 only on entry,
+// which is what lets the body of the trace run backwards.
+pub fn times(body: Vec<Asm>) -> Vec<Asm> {
+    trace([
+        // exit with (i, x), step with (n - i - 1, (i, x)), or enter
+        // with (n, x)
+        sum(
+            [
+                prod(unfold(), vec![]),
+                op_s(OP_DIST, 1, 1),
+                op(OP_UNITI),
+                op(OP_SWAPP),
+            ].concat(),
+            [op(OP_UNITI), op(OP_SWAPP)].concat(),
+        ),
+        op_s(OP_FACT, 2, 1),
+        // (step + enter) + exit
+        op_s(OP_DIST, 1, 2),
+        op_s(OP_SWAPS, 1, 1),
+        sum(
+            [
+                op(OP_SWAPP),
+                op(OP_UNITE),
+                op_s(OP_SWAPS, 1, 1),
+                sum(op(OP_UNITI), [
+                    prod(vec![], body),
+                    op(OP_ASSLP),
+                    prod(op(OP_SWAPP), vec![]),
+                    op(OP_ASSRP),
+                ].concat()),
+                // (0 or i + 1, (n - i, x))
+                op_s(OP_FACT, 1, 1),
+                prod(fold(), vec![]),
+                op(OP_ASSLP),
+                prod(op(OP_SWAPP), vec![]),
+                op(OP_ASSRP),
+            ].concat(),
+            [op(OP_SWAPP), op(OP_UNITE), op(OP_UNITE)].concat(),
+        ),
+    ].concat())
+}
+
+// (n, x) <-> (n, x'), running the body x <-> x' n times
+pub fn repeat(body: Vec<Asm>) -> Vec<Asm> {
+    times(prod(vec![], body))
+}
+
+// a <-> (n, b), given a step a <-> a + b: runs the step until it gives
+// a b, where n is how many times it gave an a first. Backwards, n says
+// how many times to run the step's inverse, so the step needn't be able
+// to tell where it started from.
+pub fn count(step: Vec<Asm>) -> Vec<Asm> {
+    trace([
+        // (n, a) + a: an a from round the loop has been stepped n + 1
+        // times, one which has just entered none
+        sum(vec![], op(OP_UNITI)),
+        op_s(OP_SWAPS, 1, 1),
+        op_s(OP_FACT, 1, 1),
+        prod(fold(), vec![]),
+        // (n, a) + (n, b), going round again on the left
+        prod(vec![], step),
+        op(OP_SWAPP),
+        op_s(OP_DIST, 1, 1),
+        sum(op(OP_SWAPP), op(OP_SWAPP)),
+    ].concat())
+}
@@ -1,7 +1,7 @@
 use std::cell::Cell;
 
 use crate::alloc::api::AllocObject;
-use crate::array::{ArraySize, IndexedContainer};
+use crate::array::{Array, ArrayElement, ArraySize, Container, IndexedContainer, StackContainer};
 use crate::constants::*;
 use crate::data::{Nat, Product, Sum, Inductive};
 use crate::error::{RuntimeError, ErrorKind};
@@ -14,10 +14,61 @@ use crate::safeptr::{ScopedPtr, CellPtr};
 pub type Opcode = Nat;
 pub type Instruction<O> = Product<Opcode, Sum<O>>;
 pub type Function = Inductive<Instruction<()>>;
+// a function's opcodes as seen from one direction of execution
+pub type Stream = Array<Opcode>;
+// a function's instruction arguments, the same in both directions
+pub type Args = Array<LoadedArg>;
 
+// the argument of a control op, copied out of the function so that
+// dispatch doesn't walk the heap instruction for it
+#[derive(Copy, Clone)]
+pub struct LoadedArg {
+    pub fst: Nat,
+    pub snd: Nat,
+}
+
+impl ArrayElement for LoadedArg {}
+
+// a function with a stream per direction, loaded once and shared by
+// every thread which runs it
 #[derive(Clone)]
-pub struct Continuation {
+pub struct Program {
     function: CellPtr<Function>,
+    forward: CellPtr<Stream>,
+    backward: CellPtr<Stream>,
+    args: CellPtr<Args>,
+}
+impl AllocObject for Program {}
+
+impl Program {
+    pub fn load<'guard>(
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<ScopedPtr<'guard, Program>, RuntimeError> {
+        let instrs = decode_function(mem, function)?;
+
+        mem.alloc(Program {
+            function: CellPtr::new_with(function),
+            forward: CellPtr::new_with(alloc_stream(mem, &instrs, false)?),
+            backward: CellPtr::new_with(alloc_stream(mem, &instrs, true)?),
+            args: CellPtr::new_with(alloc_args(mem, &instrs)?),
+        })
+    }
+
+    pub fn function<'guard>(&self, guard: &'guard dyn MutatorScope)
+        -> ScopedPtr<'guard, Function>
+    {
+        self.function.get(guard)
+    }
+}
+
+#[derive(Clone)]
+pub struct Continuation {
+    program: CellPtr<Program>,
+    // the program's arrays, saving an indirection on every fetch
+    forward: CellPtr<Stream>,
+    backward: CellPtr<Stream>,
+    args: CellPtr<Args>,
     ip: Cell<ArraySize>,
     direction: Cell<bool>,
 }
@@ -26,28 +77,87 @@ impl AllocObject for Continuation {}
 impl Continuation {
     pub fn alloc<'guard>(
         mem: &'guard MutatorView,
-        func: ScopedPtr<'guard, Function>
+        program: ScopedPtr<'guard, Program>
     ) -> Result<ScopedPtr<'guard, Continuation>, RuntimeError> {
         mem.alloc(Continuation {
-            function: CellPtr::new_with(func),
+            program: CellPtr::new_with(program),
+            forward: program.forward.clone(),
+            backward: program.backward.clone(),
+            args: program.args.clone(),
             ip: Cell::new(0),
             direction: Cell::new(false),
         })
     }
 
-    // TODO: Optimize (way too many indirections)
-    pub fn fetch_instr<'guard>(
-        &self,
-        guard: &'guard dyn MutatorScope,
-    ) -> Result<ScopedPtr<'guard, Instruction<()>>, RuntimeError> {
-        let ptr = self.function.get(guard)
-            .get(guard, self.ip.get())?;
-        Ok(ptr.get(guard))
+    fn stream<'guard, const BACKWARD: bool>(&self, guard: &'guard dyn MutatorScope)
+        -> ScopedPtr<'guard, Stream>
+    {
+        if !BACKWARD {
+            self.forward.get(guard)
+        } else {
+            self.backward.get(guard)
+        }
+    }
+
+    // opcode at the instruction pointer, as seen when running in the
+    // given direction
+    pub fn fetch<const BACKWARD: bool>(&self, guard: &dyn MutatorScope)
+        -> Result<Opcode, RuntimeError>
+    {
+        self.stream::<BACKWARD>(guard).get(guard, self.ip.get())
+    }
+
+    // opcode at the instruction pointer, already resolved for the
+    // current direction
+    pub fn fetch_op(&self, guard: &dyn MutatorScope) -> Result<Opcode, RuntimeError> {
+        if !self.direction() {
+            self.fetch::<false>(guard)
+        } else {
+            self.fetch::<true>(guard)
+        }
+    }
+
+    // argument of the instruction at the instruction pointer
+    pub fn fetch_arg(&self, guard: &dyn MutatorScope) -> Result<LoadedArg, RuntimeError> {
+        self.args.get(guard).get(guard, self.ip.get())
+    }
+
+    // opcode of the instruction which runs after the current one, if
+    // execution doesn't jump anywhere; None at either end of the function
+    pub fn peek_op<const BACKWARD: bool>(&self, guard: &dyn MutatorScope) -> Option<Opcode> {
+        let next = if !BACKWARD {
+            self.ip().checked_add(1)?
+        } else {
+            self.ip().checked_sub(1)?
+        };
+
+        self.stream::<BACKWARD>(guard).get(guard, next).ok()
+    }
+
+    pub fn program<'guard>(&self, guard: &'guard dyn MutatorScope)
+        -> ScopedPtr<'guard, Program>
+    {
+        self.program.get(guard)
+    }
+
+    pub fn function<'guard>(&self, guard: &'guard dyn MutatorScope)
+        -> ScopedPtr<'guard, Function>
+    {
+        self.program.get(guard).function(guard)
     }
 
     pub fn set_ip(&self, i: ArraySize) { self.ip.set(i); }
     pub fn jump(&self, jmp: ArraySize) {
         if !self.direction() {
+            self.jump_in::<false>(jmp);
+        } else {
+            self.jump_in::<true>(jmp);
+        }
+    }
+
+    // jump for code which already knows which way it's running
+    pub fn jump_in<const BACKWARD: bool>(&self, jmp: ArraySize) {
+        if !BACKWARD {
             self.set_ip(self.ip() + jmp);
         } else {
             self.set_ip(self.ip() - jmp);
@@ -64,7 +174,274 @@ impl Continuation {
 
     pub fn ip(&self) -> ArraySize { self.ip.get() }
     pub fn direction(&self) -> bool { self.direction.get() }
-    pub fn reverse(&self) { self.direction.set(!self.direction()) }
+    pub fn reverse(&self) {
+        self.direction.set(!self.direction());
+    }
+}
+
+fn alloc_stream<'guard>(
+    mem: &'guard MutatorView,
+    instrs: &[DecodedInstr],
+    backward: bool,
+) -> Result<ScopedPtr<'guard, Stream>, RuntimeError> {
+    let stream = Stream::alloc_with_capacity(mem, instrs.len() as ArraySize)?;
+
+    for instr in instrs {
+        stream.push(mem, resolve_op(instr.op, backward))?;
+    }
+
+    Ok(stream)
+}
+
+fn alloc_args<'guard>(
+    mem: &'guard MutatorView,
+    instrs: &[DecodedInstr],
+) -> Result<ScopedPtr<'guard, Args>, RuntimeError> {
+    let args = Args::alloc_with_capacity(mem, instrs.len() as ArraySize)?;
+
+    for instr in instrs {
+        let arg = match instr.arg {
+            InstrArg::Nat(nat) => LoadedArg { fst: nat, snd: 0 },
+            InstrArg::Pair(fst, snd) => LoadedArg { fst, snd },
+        };
+        args.push(mem, arg)?;
+    }
+
+    Ok(args)
+}
+
+/*
+ * Instruction Construction
+ */
+// ops which take no argument still carry one, so that every
+// instruction can be decoded the same way
+pub fn alloc_instr<'guard>(
+    mem: &'guard MutatorView,
+    op: Opcode,
+) -> Result<ScopedPtr<'guard, Instruction<()>>, RuntimeError> {
+    alloc_instr_nat(mem, op, 0)
+}
+
+// general op or prod combinator
+pub fn alloc_instr_nat<'guard>(
+    mem: &'guard MutatorView,
+    op: Opcode,
+    nat: Nat,
+) -> Result<ScopedPtr<'guard, Instruction<()>>, RuntimeError> {
+    let arg = mem.alloc(Sum::new(0, CellPtr::new_with(mem.alloc(nat)?)))?;
+
+    mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(op)?),
+        CellPtr::new_with(unsafe { arg.cast::<Sum<()>>(mem) }),
+    ))
+}
+
+// CALL/UNCALL or sum combinator
+pub fn alloc_instr_pair<'guard>(
+    mem: &'guard MutatorView,
+    op: Opcode,
+    fst: Nat,
+    snd: Nat,
+) -> Result<ScopedPtr<'guard, Instruction<()>>, RuntimeError> {
+    let pair = mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(fst)?),
+        CellPtr::new_with(mem.alloc(snd)?),
+    ))?;
+    let arg = mem.alloc(Sum::new(1, CellPtr::new_with(pair)))?;
+
+    mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(op)?),
+        CellPtr::new_with(unsafe { arg.cast::<Sum<()>>(mem) }),
+    ))
+}
+
+/*
+ * Decoded Functions
+ *
+ * Functions flattened into plain instructions, for passes which need
+ * to look at the whole function at once rather than one instruction
+ * at a time.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InstrArg {
+    Nat(Nat),
+    Pair(Nat, Nat),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodedInstr {
+    pub op: Opcode,
+    pub arg: InstrArg,
+}
+
+impl DecodedInstr {
+    pub fn new(op: Opcode) -> DecodedInstr {
+        DecodedInstr { op, arg: InstrArg::Nat(0) }
+    }
+
+    pub fn with_nat(op: Opcode, nat: Nat) -> DecodedInstr {
+        DecodedInstr { op, arg: InstrArg::Nat(nat) }
+    }
+
+    pub fn with_pair(op: Opcode, fst: Nat, snd: Nat) -> DecodedInstr {
+        DecodedInstr { op, arg: InstrArg::Pair(fst, snd) }
+    }
+
+    // opcode when executing forwards
+    pub fn opcode(&self) -> u8 { get_opcode(self.op, false) }
+}
+
+pub fn decode_function<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<Vec<DecodedInstr>, RuntimeError> {
+    let mut instrs = Vec::with_capacity(function.length() as usize);
+
+    for index in 0..function.length() {
+        let instr = function.get(guard, index)?.get(guard);
+        let arg = instr.snd(guard);
+
+        let arg = match arg.tag() {
+            0 => InstrArg::Nat(*unsafe { arg.data(guard).cast::<Nat>(guard) }),
+            1 => {
+                let pair = unsafe { arg.data(guard).cast::<Product<Nat, Nat>>(guard) };
+                InstrArg::Pair(*pair.fst(guard), *pair.snd(guard))
+            },
+            _ => return Err(RuntimeError::new(ErrorKind::TypeError)),
+        };
+
+        instrs.push(DecodedInstr { op: *instr.fst(guard), arg });
+    }
+
+    Ok(instrs)
+}
+
+pub fn alloc_function<'guard>(
+    mem: &'guard MutatorView,
+    instrs: &[DecodedInstr],
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let function = Function::alloc_with_capacity(mem, instrs.len() as ArraySize)?;
+
+    for instr in instrs {
+        let alloced = match instr.arg {
+            InstrArg::Nat(nat) => alloc_instr_nat(mem, instr.op, nat)?,
+            InstrArg::Pair(fst, snd) => alloc_instr_pair(mem, instr.op, fst, snd)?,
+        };
+        function.push(mem, CellPtr::new_with(alloced))?;
+    }
+
+    Ok(function)
+}
+
+// frees a function along with every instruction in it
+pub fn free_function<'guard>(
+    mem: &'guard MutatorView,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<(), RuntimeError> {
+    for index in 0..function.length() {
+        let instr = function.get(mem, index)?.get(mem);
+        let arg = instr.snd(mem);
+
+        match arg.tag() {
+            0 => mem.dealloc(unsafe { arg.data(mem).cast::<Nat>(mem) })?,
+            _ => {
+                let pair = unsafe { arg.data(mem).cast::<Product<Nat, Nat>>(mem) };
+                mem.dealloc(pair.fst(mem))?;
+                mem.dealloc(pair.snd(mem))?;
+                mem.dealloc(pair)?;
+            },
+        }
+
+        mem.dealloc(arg)?;
+        mem.dealloc(instr.fst(mem))?;
+        mem.dealloc(instr)?;
+    }
+
+    function.dealloc_data(mem)?;
+    mem.dealloc(function)
+}
+
+// checks that every combinator and call refers to instructions within
+// the function, and that both ends of each combinator agree
+pub fn verify_function(instrs: &[DecodedInstr]) -> Result<(), RuntimeError> {
+    let len = instrs.len();
+    let opcode_at = |index: usize| -> Result<u8, RuntimeError> {
+        instrs.get(index)
+            .map(|instr| instr.opcode())
+            .ok_or(RuntimeError::new(ErrorKind::BoundsError))
+    };
+
+    for (index, instr) in instrs.iter().enumerate() {
+        match (instr.opcode(), instr.arg) {
+            (OP_SUMS, InstrArg::Pair(lc, rc)) => {
+                let end = index + lc as usize + rc as usize + 1;
+
+                if opcode_at(end)? != OP_SUME || instrs[end].arg != instr.arg {
+                    return Err(RuntimeError::new(ErrorKind::TypeError));
+                }
+            },
+            (OP_SUME, InstrArg::Pair(lc, rc)) => {
+                let start = index.checked_sub(lc as usize + rc as usize + 1)
+                    .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
+
+                if opcode_at(start)? != OP_SUMS {
+                    return Err(RuntimeError::new(ErrorKind::TypeError));
+                }
+            },
+            (OP_PRODS, InstrArg::Nat(jmp)) => {
+                if jmp == 0 || index + jmp as usize >= len {
+                    return Err(RuntimeError::new(ErrorKind::BoundsError));
+                }
+            },
+            (OP_PRODE, InstrArg::Nat(jmp)) => {
+                if jmp == 0 || index < jmp as usize {
+                    return Err(RuntimeError::new(ErrorKind::BoundsError));
+                }
+            },
+            (OP_CALL | OP_UNCALL, InstrArg::Pair(start, end)) => {
+                if start >= end
+                    || opcode_at(start as usize)? != OP_START
+                    || opcode_at(end as usize)? != OP_END
+                {
+                    return Err(RuntimeError::new(ErrorKind::TypeError));
+                }
+            },
+            (OP_SUMS | OP_SUME | OP_PRODS | OP_PRODE | OP_CALL | OP_UNCALL, _) => {
+                return Err(RuntimeError::new(ErrorKind::TypeError));
+            },
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+// finds the PRODE closing the product combinator whose second half
+// starts at `split`, checking that its jump covers the second half
+pub fn find_prode(
+    instrs: &[DecodedInstr],
+    split: usize,
+    hi: usize,
+) -> Result<usize, RuntimeError> {
+    let second = instrs.get(split..hi)
+        .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
+    let mut depth = 0;
+
+    for (offset, instr) in second.iter().enumerate() {
+        match instr.opcode() {
+            OP_PRODS => depth += 1,
+            OP_PRODE if depth == 0 => {
+                if instr.arg != InstrArg::Nat(offset as Nat + 1) {
+                    return Err(RuntimeError::new(ErrorKind::TypeError));
+                }
+                return Ok(split + offset);
+            },
+            OP_PRODE => depth -= 1,
+            _ => {},
+        }
+    }
+
+    Err(RuntimeError::new(ErrorKind::BoundsError))
 }
 
 // Decoding Functions
@@ -76,6 +453,22 @@ pub fn get_opcode(instr: Opcode, dir: bool) -> u8 {
     }
 }
 
+// the instruction with its opcode replaced by the one executed when
+// running in the given direction, keeping any immediates
+pub fn resolve_op(instr: Opcode, dir: bool) -> Opcode {
+    (instr & !OP_MASK) | get_opcode(instr, dir) as Opcode
+}
+
+// data ops only transform the current value, leaving the instruction
+// pointer and context stack for the VM to advance
+pub fn is_data_op(opcode: u8) -> bool {
+    !matches!(
+        opcode,
+        OP_EXPN | OP_COLN | OP_CALL | OP_UNCALL | OP_END | OP_READ | OP_WRITE
+            | OP_SUMS | OP_SUME | OP_PRODS | OP_PRODE
+    )
+}
+
 pub fn decode_i(instr: Opcode) -> u32 {
     (instr & I_MASK) >> 5
 }
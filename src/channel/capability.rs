@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+
+use super::CHANNEL_OPEN;
+
+/*
+ * Capabilities
+ *
+ * A sandboxed thread may only touch the channels it has been granted,
+ * either by id or by kind. A channel's kind is the standard id of
+ * whatever it is, so a storage channel opened at runtime is still of
+ * kind 3 even though its id isn't.
+ *
+ * Reading, and handing back what was read, needs Read. Writing, and
+ * taking back what was written, needs ReadWrite. Opening and closing
+ * channels both need channel 0, at either access, since closing only
+ * ever gives up what opening got.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    ReadWrite,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    ids: HashMap<Nat, Access>,
+    kinds: HashMap<Nat, Access>,
+}
+
+impl Capabilities {
+    // grants nothing
+    pub fn new() -> Capabilities { Capabilities::default() }
+
+    pub fn grant_id(&mut self, id: Nat, access: Access) {
+        self.ids.insert(id, access);
+    }
+
+    pub fn grant_kind(&mut self, kind: Nat, access: Access) {
+        self.kinds.insert(kind, access);
+    }
+
+    // the most either grant allows on the channel
+    pub fn access(&self, id: Nat, kind: Option<Nat>) -> Option<Access> {
+        let by_kind = kind.and_then(|kind| self.kinds.get(&kind));
+        self.ids.get(&id).copied().max(by_kind.copied())
+    }
+
+    // `read` and `undo` are as the channel would be asked; a WRITE or
+    // an undone one changes what's on the other side
+    pub fn check(&self, id: Nat, kind: Option<Nat>, read: bool, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let needed = if read != undo || id == CHANNEL_OPEN {
+            Access::Read
+        } else {
+            Access::ReadWrite
+        };
+
+        match self.access(id, kind) {
+            Some(access) if access >= needed => Ok(()),
+            _ => Err(RuntimeError::new(ErrorKind::ChannelDenied(id))),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::value::Value;
+use crate::bytecode::{alloc_function, verify_function, DecodedInstr, InstrArg};
+use crate::data::{Nat, Product};
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::{HeapMode, HeapOptions, Memory, MutatorView};
+use crate::safeptr::CellPtr;
+use crate::types::IType;
+use crate::vm::{EvalStatus, Thread};
+
+use super::{Capabilities, Channel, CHANNEL_CODE};
+
+/*
+ * Dynamic Code
+ *
+ * Channel 2 runs functions handed to it by the program. ?a is the
+ * function and its input, and READ 2 gives the output. The function's
+ * jumps and calls are checked up front, but nothing checks its type
+ * before it runs. It runs on a child thread in a heap of its own with
+ * type tags and checks on, which turn the ill-typed instructions found
+ * so far into a TypeError, but that's no proof against every function
+ * of the wrong type, so only hand the channel functions you'd run
+ * anyway. The output is checked against the output type before it
+ * comes back. The child gets no channels, and a budget of instructions,
+ * so a function which never finishes fails with OutOfFuel instead of
+ * hanging the program.
+ *
+ * A function gives the same output every time it's run on the same
+ * input, so nothing needs remembering: WRITE 2 takes an output back by
+ * checking it against another run, and undoing a WRITE runs it again.
+ */
+pub struct Code {
+    input: IType,
+    output: IType,
+    enabled: bool,
+    fuel: u64,
+}
+
+// instructions a function gets before it's given up on
+pub const DEFAULT_CODE_FUEL: u64 = 1 << 24;
+
+impl Code {
+    // runs functions of type input <-> output
+    pub fn new(input: IType, output: IType) -> Code {
+        Code { input, output, enabled: true, fuel: DEFAULT_CODE_FUEL }
+    }
+
+    // a disabled channel refuses to run anything
+    pub fn set_enabled(&mut self, enabled: bool) { self.enabled = enabled; }
+    pub fn enabled(&self) -> bool { self.enabled }
+
+    pub fn set_fuel(&mut self, fuel: u64) { self.fuel = fuel; }
+    pub fn fuel(&self) -> u64 { self.fuel }
+
+    fn run(&self, arg: Option<Value>) -> Result<Value, RuntimeError> {
+        if !self.enabled {
+            return Err(RuntimeError::new(ErrorKind::ChannelDenied(CHANNEL_CODE)));
+        }
+
+        let (function, input) = match arg {
+            Some(Value::Product(function, input)) => (function, input),
+            _ => return Err(RuntimeError::new(ErrorKind::TypeError)),
+        };
+        let instrs = value_to_function(&function)?;
+        verify_function(&instrs)?;
+
+        let memory = Memory::with_options(HeapOptions {
+            mode: HeapMode::Checked,
+            type_tags: true,
+        });
+        let mem = MutatorView::new(&memory);
+
+        let arg = mem.alloc(Product::new(
+            CellPtr::new_with(alloc_function(&mem, &instrs)?),
+            CellPtr::new_with(input.alloc(&mem)?),
+        ))?;
+        let child = Thread::alloc_with_arg(&mem, arg)?;
+        child.restrict(Capabilities::new());
+        if child.run_for(&mem, self.fuel)? == EvalStatus::Pending {
+            return Err(RuntimeError::new(ErrorKind::OutOfFuel));
+        }
+
+        Value::from_heap(&mem, &self.output, child.data().get(&mem))
+    }
+}
+
+impl Channel for Code {
+    fn value_type(&self) -> IType { self.output.clone() }
+    fn arg_type(&self) -> Option<IType> {
+        Some(IType::prod(function_type(), self.input.clone()))
+    }
+    fn kind(&self) -> Option<Nat> { Some(CHANNEL_CODE) }
+
+    fn read(&mut self, arg: Option<Value>, _undo: bool) -> Result<Value, RuntimeError> {
+        self.run(arg)
+    }
+
+    fn write(&mut self, value: Value, arg: Option<Value>, _undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        if self.run(arg)? != value {
+            return Err(RuntimeError::new(ErrorKind::IOError(
+                String::from("output doesn't match the function's")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/* Functions as values */
+// each instruction is its opcode and either a nat or a pair of them
+pub fn function_type() -> IType {
+    IType::ind(IType::prod(
+        IType::Nat,
+        IType::sum(IType::Nat, IType::prod(IType::Nat, IType::Nat)),
+    ))
+}
+
+pub fn function_to_value(instrs: &[DecodedInstr]) -> Value {
+    Value::Inductive(instrs.iter()
+        .map(|instr| {
+            let arg = match instr.arg {
+                InstrArg::Nat(nat) => Value::Sum(0, Box::new(Value::Nat(nat))),
+                InstrArg::Pair(fst, snd) => Value::Sum(1, Box::new(Value::Product(
+                    Box::new(Value::Nat(fst)),
+                    Box::new(Value::Nat(snd)),
+                ))),
+            };
+            Value::Product(Box::new(Value::Nat(instr.op)), Box::new(arg))
+        })
+        .collect())
+}
+
+pub fn value_to_function(value: &Value) -> Result<Vec<DecodedInstr>, RuntimeError> {
+    match value {
+        Value::Inductive(items) => items.iter()
+            .map(|item| value_to_instr(item).ok_or(RuntimeError::new(ErrorKind::TypeError)))
+            .collect(),
+        _ => Err(RuntimeError::new(ErrorKind::TypeError)),
+    }
+}
+
+fn value_to_instr(value: &Value) -> Option<DecodedInstr> {
+    let (op, arg) = match value {
+        Value::Product(op, arg) => (nat(op)?, &**arg),
+        _ => return None,
+    };
+
+    match arg {
+        Value::Sum(0, arg) => Some(DecodedInstr::with_nat(op, nat(arg)?)),
+        Value::Sum(1, arg) => match &**arg {
+            Value::Product(fst, snd) => Some(DecodedInstr::with_pair(op, nat(fst)?, nat(snd)?)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn nat(value: &Value) -> Option<Nat> {
+    match value {
+        Value::Nat(nat) => Some(*nat),
+        _ => None,
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::{self, BufRead, BufReader, Stdin, Stdout, Write};
+
+use crate::value::Value;
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+
+use super::{bytes_to_list, list_to_bytes, Channel, CHANNEL_CONSOLE};
+
+/*
+ * Console I/O
+ *
+ * Text moves as lists of nats, one byte each, with the first byte at
+ * the head of the list. READ takes a line including its newline, so
+ * the end of input reads as the empty list. WRITE prints the bytes
+ * as they are.
+ */
+pub struct Console<R, W> {
+    input: R,
+    output: W,
+    // lines handed back by undoing a READ, read again before new input
+    unread: Vec<Vec<u8>>,
+    // everything written, newest last, for undoing a WRITE
+    written: Vec<Vec<u8>>,
+}
+
+impl Console<BufReader<Stdin>, Stdout> {
+    pub fn stdio() -> Self {
+        Console::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Console {
+            input,
+            output,
+            unread: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    pub fn written(&self) -> &[Vec<u8>] { &self.written }
+}
+
+impl<R: BufRead, W: Write> Channel for Console<R, W> {
+    fn value_type(&self) -> IType { IType::ind(IType::Nat) }
+    fn kind(&self) -> Option<Nat> { Some(CHANNEL_CONSOLE) }
+
+    fn read(&mut self, _arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        let bytes = if undo {
+            // the output itself can't be taken back, only its record
+            self.written.pop()
+                .ok_or(RuntimeError::new(ErrorKind::IOError(
+                    String::from("nothing written to take back")
+                )))?
+        } else if let Some(line) = self.unread.pop() {
+            line
+        } else {
+            let mut line = Vec::new();
+            self.input.read_until(b'\n', &mut line)?;
+            line
+        };
+
+        Ok(bytes_to_list(&bytes))
+    }
+
+    fn write(&mut self, value: Value, _arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let bytes = list_to_bytes(&value)?;
+
+        if undo {
+            self.unread.push(bytes);
+        } else {
+            self.output.write_all(&bytes)?;
+            self.output.flush()?;
+            self.written.push(bytes);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use crate::value::Value;
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+
+use super::{Channel, CHANNEL_DELETE};
+
+/*
+ * Deletion
+ *
+ * WRITE 1 is the one way to destroy information on purpose: the value
+ * is freed, and the bits it held are added to the channel's count of
+ * erasures. Bits are counted by type rather than by heap size, so a
+ * nat is 32 bits, a sum's tag is just enough bits to pick one of its
+ * variants, and a list also erases its length.
+ *
+ * Under Journal, every erased value is kept, so undoing the WRITE gives
+ * it back and takes its bits off the count again. Otherwise there is
+ * nothing to undo with, and under Forbid nothing can be erased at all.
+ * There is never anything to READ.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ErasurePolicy {
+    Allow,
+    Forbid,
+    Journal,
+}
+
+pub struct Delete {
+    ty: IType,
+    policy: ErasurePolicy,
+    erased: u64,
+    journal: Vec<Value>,
+}
+
+impl Delete {
+    // erases values of the given type
+    pub fn new(ty: IType, policy: ErasurePolicy) -> Delete {
+        Delete { ty, policy, erased: 0, journal: Vec::new() }
+    }
+
+    pub fn policy(&self) -> ErasurePolicy { self.policy }
+    pub fn journal(&self) -> &[Value] { &self.journal }
+}
+
+impl Channel for Delete {
+    fn value_type(&self) -> IType { self.ty.clone() }
+    fn kind(&self) -> Option<Nat> { Some(CHANNEL_DELETE) }
+    fn erased_bits(&self) -> u64 { self.erased }
+
+    fn read(&mut self, _arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        if !undo {
+            return Err(delete_error("nothing to read from deletion"));
+        }
+
+        let value = self.journal.pop()
+            .ok_or(delete_error("erased value wasn't journaled"))?;
+        self.erased -= bits(&self.ty, &value)?;
+        Ok(value)
+    }
+
+    fn write(&mut self, value: Value, _arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        if undo {
+            return Err(delete_error("nothing was read from deletion"));
+        }
+
+        match self.policy {
+            ErasurePolicy::Forbid => {
+                return Err(RuntimeError::new(ErrorKind::ErasureForbidden));
+            },
+            ErasurePolicy::Journal => {
+                self.erased += bits(&self.ty, &value)?;
+                self.journal.push(value);
+            },
+            ErasurePolicy::Allow => self.erased += bits(&self.ty, &value)?,
+        }
+
+        Ok(())
+    }
+}
+
+// the information held by a value of the given type
+pub fn bits(ty: &IType, value: &Value) -> Result<u64, RuntimeError> {
+    let nat_bits = Nat::BITS as u64;
+
+    Ok(match (ty, value) {
+        (IType::Unit, Value::Unit) => 0,
+        (IType::Nat, Value::Nat(_)) => nat_bits,
+        (IType::BigNat, Value::BigNat(limbs)) => nat_bits * (limbs.len() as u64 + 1),
+        // one bit picks the side
+        (IType::Sum { .. }, Value::Sum(tag, inner)) => {
+            let variant = ty.variant(*tag).ok_or(RuntimeError::new(ErrorKind::TypeError))?;
+            1 + bits(variant, inner)?
+        },
+        (IType::Prod { fst, snd }, Value::Product(a, b)) => bits(fst, a)? + bits(snd, b)?,
+        (IType::Neg(inner), Value::Negative(x)) => bits(inner, x)?,
+        (IType::Ind(inner), Value::Inductive(items)) => {
+            nat_bits + items.iter()
+                .map(|item| bits(inner, item))
+                .sum::<Result<u64, _>>()?
+        },
+        _ => return Err(RuntimeError::new(ErrorKind::TypeError)),
+    })
+}
+
+/* Helper functions */
+fn delete_error(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(String::from(reason)))
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use crate::value::Value;
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+
+use super::{Channel, CHANNEL_OPEN};
+
+/*
+ * Channel Management
+ *
+ * Channel 0 opens and closes other channels. READ 0 takes a
+ * descriptor as ?a, has the host's Opener turn it into a channel, and
+ * reads back the new channel's id. WRITE 0 takes an id and closes the
+ * channel, which is kept aside rather than dropped so that undoing
+ * the close can bring it back as it was. Undoing an open drops the
+ * channel for good.
+ *
+ * Opened channels get ids from FIRST_DYNAMIC up, leaving the ones
+ * below for channels the host attaches itself.
+ */
+pub trait Opener {
+    fn descriptor_type(&self) -> IType;
+    fn open(&mut self, descriptor: Value) -> Result<Box<dyn Channel>, RuntimeError>;
+}
+
+pub const FIRST_DYNAMIC: Nat = 16;
+
+#[derive(Default)]
+pub struct Channels {
+    open: HashMap<Nat, Box<dyn Channel>>,
+    // closed by WRITE 0, newest last
+    closed: Vec<(Nat, Box<dyn Channel>)>,
+    opener: Option<Box<dyn Opener>>,
+}
+
+impl Channels {
+    pub fn new() -> Channels { Channels::default() }
+
+    pub fn attach(&mut self, id: Nat, channel: Box<dyn Channel>) -> Option<Box<dyn Channel>> {
+        self.open.insert(id, channel)
+    }
+
+    pub fn detach(&mut self, id: Nat) -> Option<Box<dyn Channel>> {
+        self.open.remove(&id)
+    }
+
+    pub fn set_opener(&mut self, opener: Box<dyn Opener>) {
+        self.opener = Some(opener);
+    }
+
+    pub fn is_open(&self, id: Nat) -> bool { self.open.contains_key(&id) }
+
+    pub fn kind(&self, id: Nat) -> Option<Nat> {
+        self.open.get(&id).and_then(|channel| channel.kind())
+    }
+
+    pub fn get(&mut self, id: Nat) -> Result<&mut Box<dyn Channel>, RuntimeError> {
+        self.open.get_mut(&id)
+            .ok_or(RuntimeError::new(ErrorKind::UnknownChannel(id)))
+    }
+
+    // including closed channels, which may yet be reopened
+    pub fn erased_bits(&self) -> u64 {
+        self.open.values()
+            .chain(self.closed.iter().map(|(_, channel)| channel))
+            .map(|channel| channel.erased_bits())
+            .sum()
+    }
+
+    // the type of ?a for READ/WRITE on the given channel, if it has one
+    pub fn arg_type(&mut self, id: Nat) -> Result<Option<IType>, RuntimeError> {
+        if id == CHANNEL_OPEN {
+            return Ok(Some(self.opener()?.descriptor_type()));
+        }
+        Ok(self.get(id)?.arg_type())
+    }
+
+    pub fn value_type(&mut self, id: Nat) -> Result<IType, RuntimeError> {
+        if id == CHANNEL_OPEN {
+            return Ok(IType::Nat);
+        }
+        Ok(self.get(id)?.value_type())
+    }
+
+    pub fn read(&mut self, id: Nat, arg: Option<Value>, undo: bool)
+        -> Result<Value, RuntimeError>
+    {
+        if id != CHANNEL_OPEN {
+            return self.get(id)?.read(arg, undo);
+        }
+
+        let (id, channel) = if undo {
+            self.closed.pop().ok_or(manager_error("no closed channel to reopen"))?
+        } else {
+            let descriptor = arg.ok_or(RuntimeError::new(ErrorKind::TypeError))?;
+            (self.next_id(), self.opener()?.open(descriptor)?)
+        };
+
+        self.open.insert(id, channel);
+        Ok(Value::Nat(id))
+    }
+
+    pub fn write(&mut self, id: Nat, value: Value, arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        if id != CHANNEL_OPEN {
+            return self.get(id)?.write(value, arg, undo);
+        }
+
+        let closing = match value {
+            Value::Nat(id) => id,
+            _ => return Err(RuntimeError::new(ErrorKind::TypeError)),
+        };
+        let channel = self.open.remove(&closing)
+            .ok_or(RuntimeError::new(ErrorKind::UnknownChannel(closing)))?;
+
+        if !undo {
+            self.closed.push((closing, channel));
+        }
+        Ok(())
+    }
+
+    fn opener(&mut self) -> Result<&mut Box<dyn Opener>, RuntimeError> {
+        self.opener.as_mut()
+            .ok_or(RuntimeError::new(ErrorKind::UnknownChannel(CHANNEL_OPEN)))
+    }
+
+    // the lowest id not taken by an open channel, or one which could
+    // still be reopened
+    fn next_id(&self) -> Nat {
+        (FIRST_DYNAMIC..)
+            .find(|id| {
+                !self.open.contains_key(id)
+                    && !self.closed.iter().any(|(closed, _)| closed == id)
+            })
+            .unwrap()
+    }
+}
+
+/* Helper functions */
+fn manager_error(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(String::from(reason)))
+}
@@ -0,0 +1,287 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::value::Value;
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+
+use super::replay::write_value;
+use super::Channel;
+
+/*
+ * Mock Channels
+ *
+ * Stand-ins for the outside world, so that programs which interact can
+ * be tested without a terminal or a filesystem. Each is a handle onto
+ * shared state: keep a clone when attaching one to a thread, and look
+ * through it afterwards.
+ *
+ * A Loopback joins two threads, each holding one end. A Scripted
+ * channel reads out values queued in advance and fails on any WRITE
+ * it wasn't told to expect. A Capture records everything which passes
+ * through it, as a transcript to compare against a golden copy.
+ */
+
+/* Loopback */
+#[derive(Clone)]
+pub struct Loopback {
+    ty: IType,
+    // queues[i] holds what's been written to end i, oldest first
+    queues: Rc<RefCell<[VecDeque<Value>; 2]>>,
+    end: usize,
+}
+
+impl Loopback {
+    // two ends, each reading what the other writes
+    pub fn pair(ty: IType) -> (Loopback, Loopback) {
+        let queues = Rc::new(RefCell::new([VecDeque::new(), VecDeque::new()]));
+        let end = |end| Loopback { ty: ty.clone(), queues: queues.clone(), end };
+        (end(0), end(1))
+    }
+
+    // values written to this end which it hasn't read yet
+    pub fn pending(&self) -> usize {
+        self.queues.borrow()[self.end].len()
+    }
+}
+
+impl Channel for Loopback {
+    fn value_type(&self) -> IType { self.ty.clone() }
+
+    fn read(&mut self, _arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        let mut queues = self.queues.borrow_mut();
+
+        if undo {
+            // only while the other end hasn't read it
+            queues[1 - self.end].pop_back()
+                .ok_or(mock_error("write already read by the other end"))
+        } else {
+            queues[self.end].pop_front()
+                .ok_or(mock_error("nothing written to this end"))
+        }
+    }
+
+    fn write(&mut self, value: Value, _arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let mut queues = self.queues.borrow_mut();
+
+        if undo {
+            queues[self.end].push_front(value);
+        } else {
+            queues[1 - self.end].push_back(value);
+        }
+        Ok(())
+    }
+}
+
+/* Scripted */
+#[derive(Default)]
+struct Script {
+    reads: VecDeque<Value>,
+    writes: VecDeque<Value>,
+    // consumed so far, newest last, for undoing
+    read: Vec<Value>,
+    written: Vec<Value>,
+}
+
+#[derive(Clone)]
+pub struct Scripted {
+    ty: IType,
+    script: Rc<RefCell<Script>>,
+}
+
+impl Scripted {
+    pub fn new(ty: IType) -> Scripted {
+        Scripted { ty, script: Rc::new(RefCell::new(Script::default())) }
+    }
+
+    // the next READ gives this, after anything queued before
+    pub fn queue_read(&self, value: Value) {
+        self.script.borrow_mut().reads.push_back(value);
+    }
+
+    // the next WRITE must be exactly this
+    pub fn expect_write(&self, value: Value) {
+        self.script.borrow_mut().writes.push_back(value);
+    }
+
+    // whether every queued value has been read and every expected
+    // value written
+    pub fn finished(&self) -> bool {
+        let script = self.script.borrow();
+        script.reads.is_empty() && script.writes.is_empty()
+    }
+}
+
+impl Channel for Scripted {
+    fn value_type(&self) -> IType { self.ty.clone() }
+
+    fn read(&mut self, _arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        let script = &mut *self.script.borrow_mut();
+
+        let value = if undo {
+            let value = script.written.pop()
+                .ok_or(mock_error("nothing written to take back"))?;
+            script.writes.push_front(value.clone());
+            value
+        } else {
+            let value = script.reads.pop_front()
+                .ok_or(mock_error("script has nothing left to read"))?;
+            script.read.push(value.clone());
+            value
+        };
+
+        Ok(value)
+    }
+
+    fn write(&mut self, value: Value, _arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let script = &mut *self.script.borrow_mut();
+
+        // on a mismatch, the expected value goes back where it was
+        if undo {
+            let expected = script.read.pop()
+                .ok_or(mock_error("nothing read to hand back"))?;
+            if expected != value {
+                script.read.push(expected.clone());
+                return Err(mismatch(&expected, &value));
+            }
+            script.reads.push_front(value);
+        } else {
+            let expected = script.writes.pop_front()
+                .ok_or(mock_error(&format!("unexpected write of {:?}", value)))?;
+            if expected != value {
+                script.writes.push_front(expected.clone());
+                return Err(mismatch(&expected, &value));
+            }
+            script.written.push(value);
+        }
+
+        Ok(())
+    }
+}
+
+/* Capture */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Captured {
+    pub read: bool,
+    pub undo: bool,
+    pub value: Value,
+}
+
+struct Recording {
+    // None for a sink, which keeps what's written to it and reads
+    // nothing
+    inner: Option<Box<dyn Channel>>,
+    written: Vec<Value>,
+    entries: Vec<Captured>,
+}
+
+#[derive(Clone)]
+pub struct Capture {
+    ty: IType,
+    recording: Rc<RefCell<Recording>>,
+}
+
+impl Capture {
+    // records what passes through another channel
+    pub fn new(inner: Box<dyn Channel>) -> Capture {
+        Capture::with_inner(inner.value_type(), Some(inner))
+    }
+
+    // a channel of its own, only for writing to
+    pub fn sink(ty: IType) -> Capture {
+        Capture::with_inner(ty, None)
+    }
+
+    fn with_inner(ty: IType, inner: Option<Box<dyn Channel>>) -> Capture {
+        Capture {
+            ty,
+            recording: Rc::new(RefCell::new(Recording {
+                inner,
+                written: Vec::new(),
+                entries: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<Captured> {
+        self.recording.borrow().entries.clone()
+    }
+}
+
+// one line per interaction, with values as in a replay log
+impl fmt::Display for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.recording.borrow().entries {
+            let mut value = String::new();
+            write_value(&entry.value, &mut value);
+
+            writeln!(f, "{} {} {}",
+                if entry.read { "read" } else { "write" },
+                if entry.undo { "undo" } else { "fwd" },
+                value.trim_end(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Channel for Capture {
+    fn value_type(&self) -> IType { self.ty.clone() }
+
+    fn arg_type(&self) -> Option<IType> {
+        self.recording.borrow().inner.as_ref().and_then(|inner| inner.arg_type())
+    }
+
+    fn kind(&self) -> Option<Nat> {
+        self.recording.borrow().inner.as_ref().and_then(|inner| inner.kind())
+    }
+
+    fn erased_bits(&self) -> u64 {
+        self.recording.borrow().inner.as_ref().map_or(0, |inner| inner.erased_bits())
+    }
+
+    fn read(&mut self, arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        let recording = &mut *self.recording.borrow_mut();
+
+        let value = match (&mut recording.inner, undo) {
+            (Some(inner), _) => inner.read(arg, undo)?,
+            (None, true) => recording.written.pop()
+                .ok_or(mock_error("nothing written to take back"))?,
+            (None, false) => return Err(mock_error("nothing to read from a sink")),
+        };
+
+        recording.entries.push(Captured { read: true, undo, value: value.clone() });
+        Ok(value)
+    }
+
+    fn write(&mut self, value: Value, arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let recording = &mut *self.recording.borrow_mut();
+
+        match (&mut recording.inner, undo) {
+            (Some(inner), _) => inner.write(value.clone(), arg, undo)?,
+            (None, false) => recording.written.push(value.clone()),
+            (None, true) => return Err(mock_error("nothing was read from a sink")),
+        }
+
+        recording.entries.push(Captured { read: false, undo, value });
+        Ok(())
+    }
+}
+
+/* Helper functions */
+fn mismatch(expected: &Value, value: &Value) -> RuntimeError {
+    mock_error(&format!("expected write of {:?}, got {:?}", expected, value))
+}
+
+fn mock_error(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(String::from(reason)))
+}
@@ -0,0 +1,82 @@
+use crate::value::Value;
+use crate::constants::MAX_ITYPE_FIELD;
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+
+mod capability;
+mod code;
+mod delete;
+mod console;
+mod manager;
+mod mock;
+mod pipe;
+mod replay;
+mod storage;
+
+pub use capability::{Access, Capabilities};
+pub use code::{function_to_value, function_type, value_to_function, Code, DEFAULT_CODE_FUEL};
+pub use console::Console;
+pub use delete::{bits, Delete, ErasurePolicy};
+pub use manager::{Channels, Opener, FIRST_DYNAMIC};
+pub use mock::{Capture, Captured, Loopback, Scripted};
+pub use pipe::Pipe;
+pub use replay::{Event, Replay, ReplayLog};
+pub use storage::Storage;
+
+/*
+ * Interaction Channels
+ *
+ * READ c and WRITE c move a value between the running program and
+ * whatever the host has attached to channel c. Executing backwards,
+ * each turns into the other, so a channel is also told when it is
+ * undoing: a READ then takes back the last value written, and a WRITE
+ * hands a value back to be read again.
+ *
+ * A channel with an argument type also gets a copy of ?a, which stays
+ * where it is either way.
+ */
+pub trait Channel {
+    // the type ?b of the values passing through the channel
+    fn value_type(&self) -> IType;
+    fn arg_type(&self) -> Option<IType> { None }
+    // the standard id of what the channel is, for granting by kind
+    fn kind(&self) -> Option<Nat> { None }
+    // information destroyed through the channel so far, in bits
+    fn erased_bits(&self) -> u64 { 0 }
+
+    fn read(&mut self, arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError>;
+    fn write(&mut self, value: Value, arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>;
+}
+
+/* Standard channel ids */
+pub const CHANNEL_OPEN: Nat = 0;
+pub const CHANNEL_DELETE: Nat = 1;
+pub const CHANNEL_CODE: Nat = 2;
+pub const CHANNEL_STORAGE: Nat = 3;
+pub const CHANNEL_CONSOLE: Nat = 4;
+pub const CHANNEL_NETWORK: Nat = 5;
+
+// READ/WRITE with this id take the real one from data, as (id * ?a)
+pub const CHANNEL_DYNAMIC: Nat = MAX_ITYPE_FIELD;
+
+/* Helper functions */
+// lists unfold from the end of their array, so the head goes last
+pub fn bytes_to_list(bytes: &[u8]) -> Value {
+    Value::Inductive(bytes.iter().rev().map(|b| Value::Nat(*b as Nat)).collect())
+}
+
+pub fn list_to_bytes(value: &Value) -> Result<Vec<u8>, RuntimeError> {
+    match value {
+        Value::Inductive(items) => items.iter()
+            .rev()
+            .map(|item| match item {
+                Value::Nat(n) => u8::try_from(*n)
+                    .map_err(|_| RuntimeError::new(ErrorKind::IntOverflow)),
+                _ => Err(RuntimeError::new(ErrorKind::TypeError)),
+            })
+            .collect(),
+        _ => Err(RuntimeError::new(ErrorKind::TypeError)),
+    }
+}
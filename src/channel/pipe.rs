@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::value::Value;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+
+use super::Channel;
+
+/*
+ * Pipes
+ *
+ * A pipe joins two threads which meet to pass each value: a WRITE on
+ * one end blocks until a READ on the other has taken what it offered,
+ * and a READ blocks until there's something to take. Blocking is the
+ * WouldBlock error, raised before the instruction has changed
+ * anything, so whoever is running the thread just tries again later.
+ *
+ * Running backwards is the same meeting the other way around. Undoing
+ * the READ offers the value back, and undoing the WRITE takes it, so
+ * the reader gives up a value only as the writer takes it back. An
+ * offer is only ever taken in the direction it was made in.
+ */
+#[derive(Debug, PartialEq)]
+enum Slot {
+    Empty,
+    Offered { value: Value, undo: bool },
+    // waiting for the offering end to see that it's gone
+    Taken { undo: bool },
+}
+
+#[derive(Clone)]
+pub struct Pipe {
+    ty: IType,
+    // slots[i] holds what's offered to end i
+    slots: Rc<RefCell<[Slot; 2]>>,
+    end: usize,
+}
+
+impl Pipe {
+    pub fn pair(ty: IType) -> (Pipe, Pipe) {
+        let slots = Rc::new(RefCell::new([Slot::Empty, Slot::Empty]));
+        let end = |end| Pipe { ty: ty.clone(), slots: slots.clone(), end };
+        (end(0), end(1))
+    }
+}
+
+impl Channel for Pipe {
+    fn value_type(&self) -> IType { self.ty.clone() }
+
+    // READ forwards, or undoing a WRITE: take what the other end offers
+    fn read(&mut self, _arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        let slot = &mut self.slots.borrow_mut()[self.end];
+
+        match std::mem::replace(slot, Slot::Taken { undo }) {
+            Slot::Offered { value, undo: offered } if offered == undo => Ok(value),
+            other => {
+                *slot = other;
+                Err(RuntimeError::new(ErrorKind::WouldBlock))
+            },
+        }
+    }
+
+    // WRITE forwards, or undoing a READ: offer the value, then wait
+    // until it's been taken
+    fn write(&mut self, value: Value, _arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let slot = &mut self.slots.borrow_mut()[1 - self.end];
+
+        match slot {
+            Slot::Empty => *slot = Slot::Offered { value, undo },
+            Slot::Taken { undo: taken } if *taken == undo => {
+                *slot = Slot::Empty;
+                return Ok(());
+            },
+            _ => {},
+        }
+
+        Err(RuntimeError::new(ErrorKind::WouldBlock))
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use crate::value::Value;
+use crate::data::Nat;
+use crate::error::{err_parser, RuntimeError, ErrorKind};
+
+/*
+ * Record and Replay
+ *
+ * READ and WRITE are the only way non-determinism gets into a thread,
+ * so a log of every value which crossed a channel, and when, is
+ * enough to run the thread again exactly. While replaying, a thread
+ * never touches its channels: reads come from the log, and writes
+ * are checked against it.
+ *
+ * The log is a tape. Interactions move forward through it, except
+ * that the inverse of the one just replayed moves back over it, which
+ * lets a replayed thread run backwards across I/O even if the recorded
+ * one never did.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    // instructions executed before this one, for finding it again; a
+    // replay doesn't have to line up with it, since it may go back and
+    // forth over the log
+    pub step: u64,
+    pub channel: Nat,
+    // READ rather than WRITE, after resolving for direction
+    pub read: bool,
+    pub undo: bool,
+    pub value: Value,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayLog {
+    events: Vec<Event>,
+}
+
+impl ReplayLog {
+    pub fn new() -> ReplayLog { ReplayLog::default() }
+
+    pub fn events(&self) -> &[Event] { &self.events }
+    pub fn push(&mut self, event: Event) { self.events.push(event) }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RuntimeError> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<ReplayLog, RuntimeError> {
+        ReplayLog::parse(&fs::read_to_string(path)?)
+    }
+
+    // one event per line: step, channel, op, direction, then the value
+    pub fn parse(text: &str) -> Result<ReplayLog, RuntimeError> {
+        let mut events = Vec::new();
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut tokens = line.split_whitespace();
+            let step = number(&mut tokens)?;
+            let channel = number(&mut tokens)?.try_into()
+                .map_err(|_| err_parser("channel id out of range"))?;
+            let read = match tokens.next() {
+                Some("read") => true,
+                Some("write") => false,
+                _ => return Err(err_parser("expected read or write")),
+            };
+            let undo = match tokens.next() {
+                Some("fwd") => false,
+                Some("undo") => true,
+                _ => return Err(err_parser("expected fwd or undo")),
+            };
+            let value = parse_value(&mut tokens)?;
+
+            if tokens.next().is_some() {
+                return Err(err_parser("trailing tokens after value"));
+            }
+            events.push(Event { step, channel, read, undo, value });
+        }
+
+        Ok(ReplayLog { events })
+    }
+}
+
+impl fmt::Display for ReplayLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in &self.events {
+            let mut value = String::new();
+            write_value(&event.value, &mut value);
+
+            writeln!(f, "{} {} {} {} {}",
+                event.step,
+                event.channel,
+                if event.read { "read" } else { "write" },
+                if event.undo { "undo" } else { "fwd" },
+                value.trim_end(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// a log being played back, and how far through it the thread has got
+pub struct Replay {
+    log: ReplayLog,
+    cursor: usize,
+}
+
+impl Replay {
+    pub fn new(log: ReplayLog) -> Replay {
+        Replay { log, cursor: 0 }
+    }
+
+    pub fn log(&self) -> &ReplayLog { &self.log }
+    pub fn finished(&self) -> bool { self.cursor == self.log.events.len() }
+
+    // the value the recorded interaction read or wrote; `step` is only
+    // for reporting where the replay went wrong
+    pub fn next(&mut self, step: u64, channel: Nat, read: bool, undo: bool)
+        -> Result<&Value, RuntimeError>
+    {
+        let events = &self.log.events;
+
+        let forward = events.get(self.cursor).filter(|event| {
+            event.channel == channel && event.read == read && event.undo == undo
+        });
+        if forward.is_some() {
+            self.cursor += 1;
+            return Ok(&events[self.cursor - 1].value);
+        }
+
+        // the inverse of the last interaction replayed, so back up over it
+        let back = self.cursor.checked_sub(1)
+            .and_then(|last| events.get(last))
+            .filter(|event| {
+                event.channel == channel
+                    && event.read != read
+                    && event.undo != undo
+            });
+        if back.is_some() {
+            self.cursor -= 1;
+            return Ok(&events[self.cursor].value);
+        }
+
+        Err(RuntimeError::new(ErrorKind::ReplayDiverged(step)))
+    }
+}
+
+/* Value encoding */
+// prefix notation, one token per node: u for unit, n for a nat, b for
+// a big nat followed by its limbs, s for a sum tag followed by its
+// payload, p for a product, - for a negative and i for an inductive
+// followed by its items
+pub(super) fn write_value(value: &Value, out: &mut String) {
+    match value {
+        Value::Unit => out.push_str("u "),
+        Value::Nat(n) => out.push_str(&format!("n{} ", n)),
+        Value::BigNat(limbs) => {
+            out.push_str(&format!("b{} ", limbs.len()));
+            for limb in limbs {
+                out.push_str(&format!("{} ", limb));
+            }
+        },
+        Value::Sum(tag, x) => {
+            out.push_str(&format!("s{} ", tag));
+            write_value(x, out);
+        },
+        Value::Product(a, b) => {
+            out.push_str("p ");
+            write_value(a, out);
+            write_value(b, out);
+        },
+        Value::Negative(x) => {
+            out.push_str("- ");
+            write_value(x, out);
+        },
+        Value::Inductive(items) => {
+            out.push_str(&format!("i{} ", items.len()));
+            for item in items {
+                write_value(item, out);
+            }
+        },
+    }
+}
+
+fn parse_value(tokens: &mut SplitWhitespace) -> Result<Value, RuntimeError> {
+    let token = tokens.next().ok_or(err_parser("expected a value"))?;
+    let (head, rest) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+    let count = || rest.parse::<u32>()
+        .map_err(|_| err_parser("expected a number after value tag"));
+
+    Ok(match head {
+        "u" => Value::Unit,
+        "n" => Value::Nat(count()?),
+        "b" => {
+            let limbs = (0..count()?)
+                .map(|_| number(tokens)?.try_into()
+                    .map_err(|_| err_parser("big nat limb out of range")))
+                .collect::<Result<_, _>>()?;
+            Value::BigNat(limbs)
+        },
+        "s" => Value::Sum(count()?, Box::new(parse_value(tokens)?)),
+        "p" => {
+            let a = parse_value(tokens)?;
+            let b = parse_value(tokens)?;
+            Value::Product(Box::new(a), Box::new(b))
+        },
+        "-" => Value::Negative(Box::new(parse_value(tokens)?)),
+        "i" => {
+            let items = (0..count()?)
+                .map(|_| parse_value(tokens))
+                .collect::<Result<_, _>>()?;
+            Value::Inductive(items)
+        },
+        _ => return Err(err_parser("unknown value tag")),
+    })
+}
+
+fn number(tokens: &mut SplitWhitespace) -> Result<u64, RuntimeError> {
+    tokens.next()
+        .and_then(|token| token.parse().ok())
+        .ok_or(err_parser("expected a number"))
+}
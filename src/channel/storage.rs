@@ -0,0 +1,153 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::types::IType;
+use crate::value::Value;
+
+use super::{bytes_to_list, list_to_bytes, Channel, CHANNEL_STORAGE};
+
+/*
+ * Storage I/O
+ *
+ * Files in a single directory, named and filled with byte lists. ?a
+ * is the file name: READ gives the file's contents and WRITE replaces
+ * them.
+ *
+ * Before every WRITE, the file's old contents (or the fact that there
+ * were none) are journaled next to it in .journal, so undoing the
+ * WRITE puts the file back exactly as it was, even from a later
+ * process. Undoing a READ changes nothing on disk, but fails unless
+ * the file still holds what was read.
+ */
+pub struct Storage {
+    root: PathBuf,
+    // entries are numbered from 0, newest last
+    entries: usize,
+}
+
+const JOURNAL: &str = ".journal";
+
+impl Storage {
+    // picks up any journal left by an earlier Storage on the directory
+    pub fn open(root: impl AsRef<Path>) -> Result<Storage, RuntimeError> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(JOURNAL))?;
+
+        let mut storage = Storage { root, entries: 0 };
+        while storage.entry(storage.entries, "name").exists() {
+            storage.entries += 1;
+        }
+
+        Ok(storage)
+    }
+
+    pub fn journal_len(&self) -> usize { self.entries }
+
+    fn path(&self, name: &[u8]) -> Result<PathBuf, RuntimeError> {
+        let name = std::str::from_utf8(name).map_err(|_| bad_name())?;
+
+        // nothing hidden or nested, which keeps the journal out of reach
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(bad_name());
+        }
+
+        Ok(self.root.join(name))
+    }
+
+    // an entry is a .name file, written last, and a .prev file holding
+    // the old contents if there were any
+    fn entry(&self, index: usize, ext: &str) -> PathBuf {
+        self.root.join(JOURNAL).join(format!("{}.{}", index, ext))
+    }
+
+    fn journal(&mut self, name: &[u8], path: &Path) -> Result<(), RuntimeError> {
+        let prev = self.entry(self.entries, "prev");
+
+        match fs::read(path) {
+            Ok(old) => fs::write(&prev, old)?,
+            // could be left over from an entry which was never finished
+            Err(e) if e.kind() == io::ErrorKind::NotFound => remove_if_exists(&prev)?,
+            Err(e) => return Err(e.into()),
+        }
+
+        fs::write(self.entry(self.entries, "name"), name)?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn restore(&mut self, name: &[u8], path: &Path) -> Result<(), RuntimeError> {
+        let newest = self.entries.checked_sub(1)
+            .ok_or(storage_error("nothing written to take back"))?;
+
+        if fs::read(self.entry(newest, "name"))? != name {
+            return Err(storage_error("last write was to a different file"));
+        }
+
+        let prev = self.entry(newest, "prev");
+        if prev.exists() {
+            fs::rename(&prev, path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+
+        fs::remove_file(self.entry(newest, "name"))?;
+        self.entries = newest;
+        Ok(())
+    }
+}
+
+impl Channel for Storage {
+    fn value_type(&self) -> IType { IType::ind(IType::Nat) }
+    fn arg_type(&self) -> Option<IType> { Some(IType::ind(IType::Nat)) }
+    fn kind(&self) -> Option<Nat> { Some(CHANNEL_STORAGE) }
+
+    fn read(&mut self, arg: Option<Value>, undo: bool) -> Result<Value, RuntimeError> {
+        let name = list_to_bytes(&arg.ok_or(RuntimeError::new(ErrorKind::TypeError))?)?;
+        let path = self.path(&name)?;
+        let contents = fs::read(&path)?;
+
+        if undo {
+            self.restore(&name, &path)?;
+        }
+
+        Ok(bytes_to_list(&contents))
+    }
+
+    fn write(&mut self, value: Value, arg: Option<Value>, undo: bool)
+        -> Result<(), RuntimeError>
+    {
+        let name = list_to_bytes(&arg.ok_or(RuntimeError::new(ErrorKind::TypeError))?)?;
+        let path = self.path(&name)?;
+        let contents = list_to_bytes(&value)?;
+
+        if undo {
+            if fs::read(&path)? != contents {
+                return Err(storage_error("file changed since it was read"));
+            }
+        } else {
+            self.journal(&name, &path)?;
+            fs::write(&path, contents)?;
+        }
+
+        Ok(())
+    }
+}
+
+/* Helper functions */
+fn remove_if_exists(path: &Path) -> Result<(), RuntimeError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn storage_error(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(String::from(reason)))
+}
+
+fn bad_name() -> RuntimeError {
+    storage_error("invalid file name")
+}
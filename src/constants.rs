@@ -7,6 +7,8 @@ pub const C_DIV_MASK: u32 = 0x00003FE0;
 pub const C_LC_MASK: u32 = 0x007FC000;
 pub const C_RC_MASK: u32 = 0xFF800000;
 
+pub const OPCODE_COUNT: usize = (OP_MASK + 1) as usize;
+
 // I-Type
 pub const OP_ID: u8 = 0;
 pub const OP_ID_R: u8 = !OP_ID & (OP_MASK as u8);
@@ -43,6 +45,11 @@ pub const OP_SWAPS_R: u8 = !OP_SWAPS & (OP_MASK as u8);
 pub const OP_DIST: u8 = 7;
 pub const OP_FACT: u8 = !OP_DIST & (OP_MASK as u8);
 
+// FOLD/UFOLD Immediates
+pub const FOLD_NAT: u32 = 0;
+pub const FOLD_IND: u32 = 1;
+pub const FOLD_BIGNAT: u32 = 2;
+
 // Maximums/Minimums
 pub const MAX_ITYPE_FIELD: u32 = 134217727;
 pub const MAX_STYPE_FIELD: u16 = 8191;
@@ -1,9 +1,12 @@
-use crate::array::{Array, ArraySize};
-use crate::alloc::api::AllocObject;
-use crate::data::{Bool, Product, Sum};
-use crate::safeptr::{CellPtr, UntypedCellPtr};
+use std::cell::Cell;
+use std::mem::size_of;
 
-pub type ContextStack = Array<Context>;
+use crate::array::{Array, ArraySize, Container, IndexedContainer, StackContainer};
+use crate::alloc::api::{AllocObject, RawPtr, TypeTag, MAX_OBJECT_SIZE};
+use crate::data::{Bool, Product, Sum};
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::{MutatorScope, MutatorView};
+use crate::safeptr::{CellPtr, ScopedPtr, UntypedCellPtr};
 
 /* Context Type */
 #[derive(Clone)]
@@ -35,4 +38,283 @@ pub enum Context {
     },
 }
 
-impl AllocObject for Context {}
+/*
+ * Packed Context Stack
+ *
+ * Each context is a tagged word as described in iris.md: a 3-bit tag in
+ * the low bits, with the rest of the word split between an instruction
+ * index and a second field. Heap pointers don't fit in the packed
+ * fields, so a combinator context's root is pushed as a whole word
+ * beneath its tagged word, which always sits on top. The value a
+ * product context sets aside is always the other field of its root, so
+ * it's read back from there rather than stored.
+ */
+pub type ContextWord = usize;
+
+const TAG_BITS: u32 = 3;
+const TAG_MASK: ContextWord = (1 << TAG_BITS) - 1;
+const INDEX_BITS: u32 = (ContextWord::BITS - TAG_BITS).div_ceil(2);
+const JUMP_BITS: u32 = ContextWord::BITS - TAG_BITS - INDEX_BITS;
+const RET_BITS: u32 = ContextWord::BITS - TAG_BITS - 1;
+
+// the stack's words can't outgrow one heap block, and as an array grows
+// by half again each time it can stop growing anywhere above two thirds
+// of that; contexts take up to two words each, so a stack this deep
+// always fits whatever capacity it started with
+pub const MAX_DEPTH: ArraySize =
+    (MAX_OBJECT_SIZE / size_of::<ContextWord>() * 2 / 3 / 2) as ArraySize;
+
+// a StackOverflow names at most this many of the innermost calls
+pub const CHAIN_LIMIT: usize = 16;
+
+const TAG_NIL: ContextWord = 0;
+const TAG_FIRST: ContextWord = 1;
+const TAG_SECOND: ContextWord = 2;
+const TAG_LEFT: ContextWord = 3;
+const TAG_RIGHT: ContextWord = 4;
+const TAG_CALL: ContextWord = 5;
+
+pub struct ContextStack {
+    words: Array<ContextWord>,
+    depth: Cell<ArraySize>,
+    max_depth: Cell<ArraySize>,
+}
+
+impl AllocObject for ContextStack {
+    const TYPE_TAG: TypeTag = TypeTag::Context;
+}
+
+impl ContextStack {
+    pub fn alloc_with_capacity<'guard>(
+        mem: &'guard MutatorView,
+        capacity: ArraySize,
+    ) -> Result<ScopedPtr<'guard, ContextStack>, RuntimeError> {
+        mem.alloc(ContextStack {
+            words: Array::with_capacity(mem, capacity)?,
+            depth: Cell::new(0),
+            max_depth: Cell::new(MAX_DEPTH),
+        })
+    }
+
+    pub fn push(
+        &self,
+        mem: &MutatorView,
+        cxt: Context,
+    ) -> Result<(), RuntimeError> {
+        if self.depth.get() >= self.max_depth.get() {
+            return Err(RuntimeError::new(
+                ErrorKind::StackOverflow(self.call_chain(mem, CHAIN_LIMIT)?)
+            ));
+        }
+
+        // pack first, so nothing is pushed if a field overflows
+        let (tag, fst, snd) = match &cxt {
+            Context::Nil => (TAG_NIL, 0, 0),
+            Context::First { snd_op_index, .. } => {
+                (TAG_FIRST, pack(*snd_op_index, INDEX_BITS)?, 0)
+            },
+            Context::Second { fst_op_index, .. } => {
+                (TAG_SECOND, pack(*fst_op_index, INDEX_BITS)?, 0)
+            },
+            Context::Left { right_op_index, jump, .. } => (
+                TAG_LEFT,
+                pack(*right_op_index, INDEX_BITS)?,
+                pack(*jump, JUMP_BITS)?,
+            ),
+            Context::Right { left_op_index, jump, .. } => (
+                TAG_RIGHT,
+                pack(*left_op_index, INDEX_BITS)?,
+                pack(*jump, JUMP_BITS)?,
+            ),
+            Context::Call { not, ret } => {
+                (TAG_CALL, *not as ContextWord, pack(*ret, RET_BITS)?)
+            },
+        };
+
+        match cxt {
+            Context::First { snd_val: val, root_val, .. } => {
+                let root = root_val.get(mem);
+                if word(mem, val.get(mem)) != word(mem, root.snd(mem)) {
+                    return Err(RuntimeError::new(ErrorKind::BadContext));
+                }
+                self.words.push(mem, word(mem, root))?;
+            },
+            Context::Second { fst_val: val, root_val, .. } => {
+                let root = root_val.get(mem);
+                if word(mem, val.get(mem)) != word(mem, root.fst(mem)) {
+                    return Err(RuntimeError::new(ErrorKind::BadContext));
+                }
+                self.words.push(mem, word(mem, root))?;
+            },
+            Context::Left { root_val, .. } | Context::Right { root_val, .. } => {
+                self.words.push(mem, word(mem, root_val.get(mem)))?;
+            },
+            Context::Nil | Context::Call { .. } => {},
+        }
+
+        let header = match tag {
+            // the call bit takes the place of the index field
+            TAG_CALL => tag | fst << TAG_BITS | snd << (TAG_BITS + 1),
+            _ => tag | fst << TAG_BITS | snd << (TAG_BITS + INDEX_BITS),
+        };
+        self.words.push(mem, header)?;
+        self.depth.set(self.depth.get() + 1);
+        Ok(())
+    }
+
+    pub fn pop(
+        &self,
+        guard: &dyn MutatorScope,
+    ) -> Result<Context, RuntimeError> {
+        let cxt = self.top(guard)?;
+
+        for _ in 0..=pointer_count(&cxt) {
+            self.words.pop(guard)?;
+        }
+        self.depth.set(self.depth.get() - 1);
+
+        Ok(cxt)
+    }
+
+    pub fn top(
+        &self,
+        guard: &dyn MutatorScope,
+    ) -> Result<Context, RuntimeError> {
+        match self.words.length() {
+            0 => Err(RuntimeError::new(ErrorKind::BoundsError)),
+            length => self.context_at(guard, length - 1),
+        }
+    }
+
+    // return addresses of up to `limit` of the calls in progress,
+    // innermost first
+    pub fn call_chain(
+        &self,
+        guard: &dyn MutatorScope,
+        limit: usize,
+    ) -> Result<Vec<ArraySize>, RuntimeError> {
+        let mut chain = Vec::new();
+        let mut length = self.words.length();
+
+        while length > 0 && chain.len() < limit {
+            let cxt = self.context_at(guard, length - 1)?;
+            if let Context::Call { ret, .. } = cxt {
+                chain.push(ret);
+            }
+            length -= pointer_count(&cxt) + 1;
+        }
+
+        Ok(chain)
+    }
+
+    // every context on the stack, bottom first
+    pub fn contexts(
+        &self,
+        guard: &dyn MutatorScope,
+    ) -> Result<Vec<Context>, RuntimeError> {
+        let mut cxts = Vec::with_capacity(self.depth() as usize);
+        let mut length = self.words.length();
+
+        while length > 0 {
+            let cxt = self.context_at(guard, length - 1)?;
+            length -= pointer_count(&cxt) + 1;
+            cxts.push(cxt);
+        }
+
+        cxts.reverse();
+        Ok(cxts)
+    }
+
+    fn context_at(
+        &self,
+        guard: &dyn MutatorScope,
+        at: ArraySize,
+    ) -> Result<Context, RuntimeError> {
+        let header = self.words.get(guard, at)?;
+        let index = field(header, TAG_BITS, INDEX_BITS);
+        let jump = field(header, TAG_BITS + INDEX_BITS, JUMP_BITS);
+
+        // the root pointer, in the word under the header
+        let root = || -> Result<ContextWord, RuntimeError> {
+            let index = at.checked_sub(1)
+                .ok_or(RuntimeError::new(ErrorKind::BadContext))?;
+            self.words.get(guard, index)
+        };
+
+        Ok(match header & TAG_MASK {
+            TAG_NIL => Context::Nil,
+            TAG_FIRST => {
+                let root_val = cell::<Product<(), ()>>(root()?);
+                Context::First {
+                    snd_op_index: index,
+                    snd_val: CellPtr::new_with(root_val.get(guard).snd(guard)),
+                    root_val,
+                }
+            },
+            TAG_SECOND => {
+                let root_val = cell::<Product<(), ()>>(root()?);
+                Context::Second {
+                    fst_op_index: index,
+                    fst_val: CellPtr::new_with(root_val.get(guard).fst(guard)),
+                    root_val,
+                }
+            },
+            TAG_LEFT => Context::Left {
+                right_op_index: index,
+                jump,
+                root_val: cell(root()?),
+            },
+            TAG_RIGHT => Context::Right {
+                left_op_index: index,
+                jump,
+                root_val: cell(root()?),
+            },
+            TAG_CALL => Context::Call {
+                not: header >> TAG_BITS & 1 == 1,
+                ret: field(header, TAG_BITS + 1, RET_BITS),
+            },
+            _ => return Err(RuntimeError::new(ErrorKind::BadContext)),
+        })
+    }
+
+    // number of words used by the stack, not the number of contexts
+    pub fn size(&self) -> ArraySize {
+        self.words.length()
+    }
+
+    pub fn depth(&self) -> ArraySize { self.depth.get() }
+    pub fn max_depth(&self) -> ArraySize { self.max_depth.get() }
+    // clamped to MAX_DEPTH, past which the stack might not fit in memory
+    pub fn set_max_depth(&self, max: ArraySize) { self.max_depth.set(max.min(MAX_DEPTH)); }
+}
+
+/* Helper functions */
+fn pack(value: ArraySize, bits: u32) -> Result<ContextWord, RuntimeError> {
+    let value = value as ContextWord;
+
+    if bits < ContextWord::BITS && value >> bits != 0 {
+        Err(RuntimeError::new(ErrorKind::IntOverflow))
+    } else {
+        Ok(value)
+    }
+}
+
+fn field(header: ContextWord, shift: u32, bits: u32) -> ArraySize {
+    ((header >> shift) & ((1 << bits) - 1)) as ArraySize
+}
+
+fn word<T>(guard: &dyn MutatorScope, ptr: ScopedPtr<'_, T>) -> ContextWord {
+    ptr.as_rawptr(guard).as_word()
+}
+
+fn cell<T>(word: ContextWord) -> CellPtr<T> {
+    CellPtr::new(unsafe { RawPtr::<()>::from_usize(word).cast() })
+}
+
+fn pointer_count(cxt: &Context) -> ArraySize {
+    match cxt {
+        Context::First { .. } | Context::Second { .. }
+        | Context::Left { .. } | Context::Right { .. } => 1,
+        Context::Nil | Context::Call { .. } => 0,
+    }
+}
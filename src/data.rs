@@ -1,7 +1,7 @@
 use std::fmt;
 use std::cell::Cell;
 
-use crate::alloc::api::AllocObject;
+use crate::alloc::api::{AllocObject, TypeTag};
 use crate::array::Array;
 use crate::memory::MutatorScope;
 use crate::safeptr::{CellPtr, ScopedPtr, UntypedCellPtr};
@@ -27,7 +27,9 @@ impl AllocObject for () {}
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Unit(u32); // has to represent some kind of data or alloc freaks out
-impl AllocObject for Unit {}
+impl AllocObject for Unit {
+    const TYPE_TAG: TypeTag = TypeTag::Unit;
+}
 
 impl Unit {
     pub fn new() -> Unit { Unit(0) }
@@ -42,7 +44,9 @@ impl Print for Unit {
 }
 
 pub type Nat = u32;
-impl AllocObject for Nat {}
+impl AllocObject for Nat {
+    const TYPE_TAG: TypeTag = TypeTag::Nat;
+}
 
 impl Print for Nat {
     fn print<'guard>(
@@ -91,7 +95,10 @@ pub struct Fraction {
     size: Nat
 }
 
-impl AllocObject for Fraction {}
+impl AllocObject for Fraction {
+    const TYPE_TAG: TypeTag = TypeTag::Fraction;
+}
+
 impl Fraction {
     pub fn new(ptr: UntypedCellPtr, size: Nat) -> Self {
         Fraction { ptr, size }
@@ -114,7 +121,9 @@ impl Print for Fraction {
 
 #[derive(Clone, Debug)]
 pub struct Negative<O: AllocObject>(CellPtr<O>);
-impl<O: AllocObject> AllocObject for Negative<O> {}
+impl<O: AllocObject> AllocObject for Negative<O> {
+    const TYPE_TAG: TypeTag = TypeTag::Negative;
+}
 
 impl<O: AllocObject> Negative<O> {
     pub fn new(data: CellPtr<O>) -> Negative<O> { Negative(data) }
@@ -139,7 +148,9 @@ pub struct Sum<O: AllocObject> {
     tag: Cell<Nat>,
     data: CellPtr<O>,
 }
-impl<O: AllocObject> AllocObject for Sum<O> {}
+impl<O: AllocObject> AllocObject for Sum<O> {
+    const TYPE_TAG: TypeTag = TypeTag::Sum;
+}
 
 impl<O: AllocObject> Sum<O> {
     pub fn new(tag: Nat, data: CellPtr<O>) -> Sum<O> {
@@ -178,7 +189,9 @@ pub struct Product<F: AllocObject, S: AllocObject> {
     fst: CellPtr<F>,
     snd: CellPtr<S>,
 }
-impl<F: AllocObject, S: AllocObject> AllocObject for Product<F, S> {}
+impl<F: AllocObject, S: AllocObject> AllocObject for Product<F, S> {
+    const TYPE_TAG: TypeTag = TypeTag::Product;
+}
 
 impl<F: AllocObject, S: AllocObject> Product<F, S> {
     pub fn new(fst: CellPtr<F>, snd: CellPtr<S>) -> Product<F, S> {
@@ -219,5 +232,20 @@ impl<F: AllocObject + Print, S: AllocObject + Print> Print for Product<F, S> {
     }
 }
 
+// where the fields of sums and products sit, for native code which
+// reads and writes them without going through the accessors above
+#[cfg(feature = "jit")]
+pub(crate) const SUM_TAG_OFFSET: usize = std::mem::offset_of!(Sum<()>, tag);
+#[cfg(feature = "jit")]
+pub(crate) const SUM_DATA_OFFSET: usize = std::mem::offset_of!(Sum<()>, data);
+#[cfg(feature = "jit")]
+pub(crate) const PRODUCT_FST_OFFSET: usize = std::mem::offset_of!(Product<(), ()>, fst);
+#[cfg(feature = "jit")]
+pub(crate) const PRODUCT_SND_OFFSET: usize = std::mem::offset_of!(Product<(), ()>, snd);
+
 // TODO: Switch to packed implementation
 pub type Inductive<O> = Array<CellPtr<O>>;
+
+// arbitrary-precision nat, stored as little-endian base 2^32 limbs
+// with no trailing zero limbs (so zero is the empty array)
+pub type BigNat = Array<Nat>;
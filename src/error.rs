@@ -3,7 +3,10 @@ use std::fmt;
 use std::io;
 
 use crate::alloc::api::AllocError;
+use crate::alloc::checked::HeapFault;
 use crate::alloc::BlockError;
+use crate::array::ArraySize;
+use crate::data::Nat;
 
 // source code position
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -24,6 +27,7 @@ pub enum ErrorKind {
     LexerError(String),
     ParseError(String),
     EvalError(String),
+    CompileError(String),
     BadAllocationRequest,
     IntOverflow,
     OutOfMemory,
@@ -36,6 +40,20 @@ pub enum ErrorKind {
     LessThanElim,
     FracUnification,
     BadContext,
+    UnknownChannel(Nat),
+    ChannelDenied(Nat),
+    ErasureForbidden,
+    // READ/WRITE which can't go ahead yet, and can be tried again
+    WouldBlock,
+    Deadlock,
+    // a thread ran out of instructions before it finished
+    OutOfFuel,
+    // step at which a replayed thread stopped matching its log
+    ReplayDiverged(u64),
+    // return addresses of the calls in progress, innermost first
+    StackOverflow(Vec<ArraySize>),
+    UseAfterFree(HeapFault),
+    DoubleFree(HeapFault),
 }
 
 #[derive(Debug, PartialEq)]
@@ -99,6 +117,9 @@ impl fmt::Display for RuntimeError {
             ErrorKind::EvalError(ref reason) => write!(f,
                 "Eval Error: {}", reason
             ),
+            ErrorKind::CompileError(ref reason) => write!(f,
+                "Compile Error: {}", reason
+            ),
             ErrorKind::BadAllocationRequest => write!(f,
                 "Invalid memory size allocation requested"
             ),
@@ -111,6 +132,34 @@ impl fmt::Display for RuntimeError {
             ErrorKind::BadContext => write!(f,
                 "Attempted invalid context transition"
             ),
+            ErrorKind::UnknownChannel(id) => write!(f,
+                "No channel attached with id {}", id
+            ),
+            ErrorKind::ChannelDenied(id) => write!(f,
+                "Channel {} not permitted", id
+            ),
+            ErrorKind::ErasureForbidden => write!(f,
+                "Attempted to erase data where erasure is forbidden"
+            ),
+            ErrorKind::WouldBlock => write!(f,
+                "Channel not ready, interaction would block"
+            ),
+            ErrorKind::Deadlock => write!(f,
+                "Every thread is blocked on a channel"
+            ),
+            ErrorKind::OutOfFuel => write!(f,
+                "Ran out of fuel before finishing"
+            ),
+            ErrorKind::ReplayDiverged(step) => write!(f,
+                "Replay diverged from the log at step {}", step
+            ),
+            ErrorKind::StackOverflow(ref chain) => {
+                write!(f, "Context stack overflow (call chain:")?;
+                for ret in chain {
+                    write!(f, " {}", ret)?;
+                }
+                write!(f, ")")
+            },
             ErrorKind::MulOrDivBy0 => write!(f,
                 "Attempted multiplication or division by 0"
             ),
@@ -123,6 +172,12 @@ impl fmt::Display for RuntimeError {
             ErrorKind::MutableBorrowError => write!(f,
                 "Attempted to modify container that is already mutably borrowed"
             ),
+            ErrorKind::UseAfterFree(ref fault) => write!(f,
+                "Accessed freed object ({})", fault
+            ),
+            ErrorKind::DoubleFree(ref fault) => write!(f,
+                "Attempted to free object twice ({})", fault
+            ),
         }
     }
 }
@@ -155,6 +210,15 @@ impl From<AllocError> for RuntimeError {
             AllocError::BadRequest => RuntimeError::new(
                 ErrorKind::BadAllocationRequest
             ),
+            AllocError::UseAfterFree(fault) => RuntimeError::new(
+                ErrorKind::UseAfterFree(fault)
+            ),
+            AllocError::DoubleFree(fault) => RuntimeError::new(
+                ErrorKind::DoubleFree(fault)
+            ),
+            AllocError::TypeMismatch => RuntimeError::new(
+                ErrorKind::TypeError
+            ),
         }
     }
 }
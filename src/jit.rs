@@ -0,0 +1,862 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::mem::{offset_of, transmute, ManuallyDrop};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, FuncRef, InstBuilder, JumpTableData, MemFlags, Type, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use crate::alloc::api::{AllocObject, RawPtr, TypeTag, HEADER_SIZE};
+use crate::value::Value as HostValue;
+use crate::array::Container;
+use crate::bytecode::*;
+use crate::constants::*;
+use crate::data::*;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::{HeapMode, MutatorView};
+use crate::safeptr::{ScopedPtr, ScopedRef};
+use crate::types::IType;
+use crate::vm::{EvalStatus, Thread};
+
+/*
+ * Cranelift JIT
+ *
+ * Each compiled function gets one native routine per direction of
+ * execution. The swaps, unit, zero and associativity ops are lowered
+ * to loads and stores on the objects themselves, allocating and
+ * freeing through the heap, and jump straight to the next
+ * instruction; other data ops are called with their opcode already
+ * resolved. The context stack is only consulted at instructions where
+ * a combinator can change state. Everything else is handed to the
+ * interpreter one instruction at a time, after which the routine
+ * dispatches on the new instruction pointer.
+ *
+ * While native code runs, the data pointer, instruction pointer and
+ * step count live in the frame, and are only written back to the
+ * thread when control passes to the interpreter.
+ */
+const JIT_PENDING: i32 = 0;
+const JIT_OK: i32 = 1;
+const JIT_ERR: i32 = 2;
+const JIT_CHANGED: i32 = 3;
+
+// objects lowered code hands back to the heap
+const FREE_UNIT: i32 = 0;
+const FREE_SUM: i32 = 1;
+const FREE_PRODUCT: i32 = 2;
+
+// errors lowered code raises itself
+const FAIL_TYPE: i32 = 0;
+const FAIL_ZERO: i32 = 1;
+
+// state shared between native code and the helpers it calls into
+#[repr(C)]
+struct JitFrame<'guard> {
+    data: *const u8,
+    steps: u64,
+    ip: u32,
+    // lowered code only runs on unchecked heaps, and checks tags
+    // inline if objects carry them
+    lowered: u8,
+    tagged: u8,
+    thread: &'guard Thread,
+    mem: &'guard MutatorView<'guard>,
+    error: Option<RuntimeError>,
+}
+
+const FRAME_DATA: i32 = offset_of!(JitFrame<'static>, data) as i32;
+const FRAME_STEPS: i32 = offset_of!(JitFrame<'static>, steps) as i32;
+const FRAME_IP: i32 = offset_of!(JitFrame<'static>, ip) as i32;
+const FRAME_LOWERED: i32 = offset_of!(JitFrame<'static>, lowered) as i32;
+const FRAME_TAGGED: i32 = offset_of!(JitFrame<'static>, tagged) as i32;
+
+impl<'guard> JitFrame<'guard> {
+    fn new(thread: &'guard Thread, mem: &'guard MutatorView<'guard>) -> JitFrame<'guard> {
+        let mut frame = JitFrame {
+            data: std::ptr::null(),
+            steps: 0,
+            ip: 0,
+            lowered: (mem.heap_mode() == HeapMode::Unchecked) as u8,
+            tagged: mem.has_type_tags() as u8,
+            thread,
+            mem,
+            error: None,
+        };
+
+        frame.reload();
+        frame
+    }
+
+    // hands the state native code keeps in the frame back to the thread
+    fn flush(&mut self) {
+        let data = RawPtr::<()>::from_usize(self.data as usize);
+
+        self.thread.data().set(ScopedPtr::new(self.mem, data.scoped_ref(self.mem)));
+        self.thread.continuation().get(self.mem).set_ip(self.ip);
+        self.thread.add_steps(self.steps);
+        self.steps = 0;
+    }
+
+    fn reload(&mut self) {
+        self.data = self.thread.data().get(self.mem).as_rawptr(self.mem).as_word() as *const u8;
+        self.ip = self.thread.continuation().get(self.mem).ip();
+    }
+
+    // runs part of the interpreter on the thread's own state
+    fn interpret<F>(&mut self, f: F) -> i32
+        where F: FnOnce(&Thread, &'guard MutatorView<'guard>) -> Result<EvalStatus, RuntimeError>
+    {
+        self.flush();
+        let result = f(self.thread, self.mem);
+        self.reload();
+
+        self.status(result)
+    }
+
+    fn fail(&mut self, e: RuntimeError) -> i32 {
+        self.error = Some(e);
+        JIT_ERR
+    }
+
+    fn status(&mut self, result: Result<EvalStatus, RuntimeError>) -> i32 {
+        match result {
+            Ok(EvalStatus::Pending) => JIT_PENDING,
+            Ok(EvalStatus::Ok) => JIT_OK,
+            Ok(EvalStatus::Err) => JIT_ERR,
+            Err(e) => self.fail(e),
+        }
+    }
+}
+
+type NativeFn = extern "C" fn(*mut JitFrame<'_>) -> i32;
+
+struct CompiledFunction {
+    forward: NativeFn,
+    backward: NativeFn,
+    // the types of the function's input and output, if annotated
+    types: Option<(IType, IType)>,
+}
+
+struct Helpers {
+    ip: FuncId,
+    context: FuncId,
+    data_op: FuncId,
+    step: FuncId,
+    alloc_unit: FuncId,
+    alloc_sum: FuncId,
+    alloc_product: FuncId,
+    free: FuncId,
+    fail: FuncId,
+}
+
+// what the IR for a single routine needs close at hand
+struct Lowering {
+    frame: Value,
+    lowered: Value,
+    tagged: Value,
+    ptr: Type,
+    alloc_unit: FuncRef,
+    alloc_sum: FuncRef,
+    alloc_product: FuncRef,
+    free: FuncRef,
+    exit: Block,
+    type_error: Block,
+    expected_zero: Block,
+}
+
+pub struct Jit {
+    module: ManuallyDrop<JITModule>,
+    helpers: Helpers,
+    // keyed by the address of the compiled function, so entries are
+    // dropped in free before the address can be reused
+    compiled: HashMap<usize, CompiledFunction>,
+}
+
+impl Jit {
+    pub fn new() -> Result<Jit, RuntimeError> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").map_err(compile_error)?;
+        flags.set("is_pic", "false").map_err(compile_error)?;
+
+        let isa = cranelift_native::builder()
+            .map_err(compile_error)?
+            .finish(settings::Flags::new(flags))
+            .map_err(compile_error)?;
+
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("iris_jit_ip", jit_ip as *const u8);
+        builder.symbol("iris_jit_context", jit_context as *const u8);
+        builder.symbol("iris_jit_data_op", jit_data_op as *const u8);
+        builder.symbol("iris_jit_step", jit_step as *const u8);
+        builder.symbol("iris_jit_alloc_unit", jit_alloc_unit as *const u8);
+        builder.symbol("iris_jit_alloc_sum", jit_alloc_sum as *const u8);
+        builder.symbol("iris_jit_alloc_product", jit_alloc_product as *const u8);
+        builder.symbol("iris_jit_free", jit_free as *const u8);
+        builder.symbol("iris_jit_fail", jit_fail as *const u8);
+
+        let mut module = JITModule::new(builder);
+        let ptr = module.target_config().pointer_type();
+
+        let mut frame_sig = module.make_signature();
+        frame_sig.params.push(AbiParam::new(ptr));
+        frame_sig.returns.push(AbiParam::new(types::I32));
+
+        let mut ip_sig = frame_sig.clone();
+        ip_sig.params.push(AbiParam::new(types::I32));
+
+        let mut data_op_sig = ip_sig.clone();
+        data_op_sig.params.push(AbiParam::new(types::I32));
+
+        // allocators take the opcode they allocate for, and return a
+        // null pointer on failure
+        let mut alloc_unit_sig = module.make_signature();
+        alloc_unit_sig.params.push(AbiParam::new(ptr));
+        alloc_unit_sig.params.push(AbiParam::new(types::I32));
+        alloc_unit_sig.returns.push(AbiParam::new(ptr));
+
+        let mut alloc_sum_sig = alloc_unit_sig.clone();
+        alloc_sum_sig.params.insert(2, AbiParam::new(types::I32));
+        alloc_sum_sig.params.insert(3, AbiParam::new(ptr));
+
+        let mut alloc_product_sig = alloc_unit_sig.clone();
+        alloc_product_sig.params.insert(2, AbiParam::new(ptr));
+        alloc_product_sig.params.insert(3, AbiParam::new(ptr));
+
+        let mut free_sig = ip_sig.clone();
+        free_sig.params.push(AbiParam::new(ptr));
+
+        let mut declare = |name: &str, sig| {
+            module.declare_function(name, Linkage::Import, sig)
+                .map_err(compile_error)
+        };
+
+        let helpers = Helpers {
+            ip: declare("iris_jit_ip", &ip_sig)?,
+            context: declare("iris_jit_context", &frame_sig)?,
+            data_op: declare("iris_jit_data_op", &data_op_sig)?,
+            step: declare("iris_jit_step", &frame_sig)?,
+            alloc_unit: declare("iris_jit_alloc_unit", &alloc_unit_sig)?,
+            alloc_sum: declare("iris_jit_alloc_sum", &alloc_sum_sig)?,
+            alloc_product: declare("iris_jit_alloc_product", &alloc_product_sig)?,
+            free: declare("iris_jit_free", &free_sig)?,
+            fail: declare("iris_jit_fail", &ip_sig)?,
+        };
+
+        Ok(Jit {
+            module: ManuallyDrop::new(module),
+            helpers,
+            compiled: HashMap::new(),
+        })
+    }
+
+    // only functions compiled here will run natively; the rest are
+    // interpreted as usual
+    pub fn compile<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<(), RuntimeError> {
+        self.compile_with(mem, function, None)
+    }
+
+    // as compile, but a thread entering the function at either end has
+    // its data checked against the type annotation first
+    pub fn compile_typed<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+        input: IType,
+        output: IType,
+    ) -> Result<(), RuntimeError> {
+        self.compile_with(mem, function, Some((input, output)))
+    }
+
+    fn compile_with<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+        types: Option<(IType, IType)>,
+    ) -> Result<(), RuntimeError> {
+        let instrs = decode_function(mem, function)?;
+        verify_function(&instrs)?;
+
+        let boundaries = context_boundaries(&instrs);
+        let forward = self.build(&instrs, &boundaries, false)?;
+        let backward = self.build(&instrs, &boundaries, true)?;
+        self.module.finalize_definitions().map_err(compile_error)?;
+
+        let compiled = unsafe {
+            CompiledFunction {
+                forward: transmute::<*const u8, NativeFn>(
+                    self.module.get_finalized_function(forward)
+                ),
+                backward: transmute::<*const u8, NativeFn>(
+                    self.module.get_finalized_function(backward)
+                ),
+                types,
+            }
+        };
+
+        self.compiled.insert(function.as_rawptr(mem).as_word(), compiled);
+        Ok(())
+    }
+
+    pub fn is_compiled<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+    ) -> bool {
+        self.compiled.contains_key(&function.as_rawptr(mem).as_word())
+    }
+
+    // frees a function, forgetting any native code compiled for it
+    pub fn free<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<(), RuntimeError> {
+        self.compiled.remove(&function.as_rawptr(mem).as_word());
+        free_function(mem, function)
+    }
+
+    // equivalent to Thread::run, using native code where available
+    pub fn run(&self, mem: &MutatorView, thread: &Thread)
+        -> Result<(), RuntimeError>
+    {
+        loop {
+            let cont = thread.continuation().get(mem);
+            let function = cont.function(mem).as_rawptr(mem).as_word();
+            let direction = cont.direction();
+
+            if let Some(compiled) = self.compiled.get(&function) {
+                let native = if !direction {
+                    compiled.forward
+                } else {
+                    compiled.backward
+                };
+
+                if let Some((input, output)) = &compiled.types {
+                    let length = cont.function(mem).length();
+
+                    if !direction && cont.ip() == 0 {
+                        HostValue::check_heap(mem, input, thread.data().get(mem))?;
+                    } else if direction && cont.ip() + 1 == length {
+                        HostValue::check_heap(mem, output, thread.data().get(mem))?;
+                    }
+                }
+
+                let mut frame = JitFrame::new(thread, mem);
+                let status = native(&mut frame);
+                frame.flush();
+
+                match status {
+                    JIT_OK => return Ok(()),
+                    JIT_ERR => return Err(frame.error.unwrap_or_else(|| {
+                        RuntimeError::new(ErrorKind::EvalError(
+                            String::from("native code failed")
+                        ))
+                    })),
+                    _ => {},
+                }
+
+                // native code only hands back control when the direction
+                // changes or the instruction pointer leaves the function
+                if cont.direction() != direction {
+                    continue;
+                }
+            }
+
+            if thread.eval_next_instr(mem)? == EvalStatus::Ok {
+                return Ok(());
+            }
+        }
+    }
+
+    fn build(
+        &mut self,
+        instrs: &[DecodedInstr],
+        boundaries: &HashSet<usize>,
+        direction: bool,
+    ) -> Result<FuncId, RuntimeError> {
+        let ptr = self.module.target_config().pointer_type();
+        let mut ctx = self.module.make_context();
+        ctx.func.signature.params.push(AbiParam::new(ptr));
+        ctx.func.signature.returns.push(AbiParam::new(types::I32));
+
+        let id = self.module.declare_anonymous_function(&ctx.func.signature)
+            .map_err(compile_error)?;
+
+        let mut builder_ctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+
+        let mut declare = |id| self.module.declare_func_in_func(id, b.func);
+        let ip_fn = declare(self.helpers.ip);
+        let context_fn = declare(self.helpers.context);
+        let data_op_fn = declare(self.helpers.data_op);
+        let step_fn = declare(self.helpers.step);
+        let alloc_unit = declare(self.helpers.alloc_unit);
+        let alloc_sum = declare(self.helpers.alloc_sum);
+        let alloc_product = declare(self.helpers.alloc_product);
+        let free = declare(self.helpers.free);
+        let fail = declare(self.helpers.fail);
+
+        let entry = b.create_block();
+        let dispatch = b.create_block();
+        let exit = b.create_block();
+        let blocks: Vec<Block> = instrs.iter().map(|_| b.create_block()).collect();
+
+        b.append_block_params_for_function_params(entry);
+        b.append_block_param(exit, types::I32);
+
+        b.switch_to_block(entry);
+        let frame = b.block_params(entry)[0];
+        let flags = MemFlags::trusted();
+        let lowered = b.ins().uload8(types::I32, flags, frame, FRAME_LOWERED);
+        let tagged = b.ins().uload8(types::I32, flags, frame, FRAME_TAGGED);
+        b.ins().jump(dispatch, &[]);
+
+        let lowering = Lowering {
+            frame,
+            lowered,
+            tagged,
+            ptr,
+            alloc_unit,
+            alloc_sum,
+            alloc_product,
+            free,
+            exit,
+            type_error: b.create_block(),
+            expected_zero: b.create_block(),
+        };
+
+        // (re)enter at the current instruction pointer, bailing out if
+        // the direction has changed underneath us
+        b.switch_to_block(dispatch);
+        let dir = b.ins().iconst(types::I32, direction as i64);
+        let call = b.ins().call(ip_fn, &[frame, dir]);
+        let ip = b.inst_results(call)[0];
+        let pending = b.ins().iconst(types::I32, JIT_PENDING as i64);
+        let default = b.func.dfg.block_call(exit, &[pending]);
+        let table: Vec<_> = blocks.iter()
+            .map(|block| b.func.dfg.block_call(*block, &[]))
+            .collect();
+        let jt = b.create_jump_table(JumpTableData::new(default, &table));
+        b.ins().br_table(ip, jt);
+
+        for (index, instr) in instrs.iter().enumerate() {
+            b.switch_to_block(blocks[index]);
+
+            if boundaries.contains(&index) {
+                let call = b.ins().call(context_fn, &[frame]);
+                let status = b.inst_results(call)[0];
+                let check_err = b.create_block();
+                let body = b.create_block();
+
+                let changed = b.ins().icmp_imm(IntCC::Equal, status, JIT_CHANGED as i64);
+                b.ins().brif(changed, dispatch, &[], check_err, &[]);
+
+                b.switch_to_block(check_err);
+                let failed = b.ins().icmp_imm(IntCC::Equal, status, JIT_ERR as i64);
+                b.ins().brif(failed, exit, &[status], body, &[]);
+
+                b.switch_to_block(body);
+            }
+
+            let opcode = get_opcode(instr.op, direction);
+
+            if is_data_op(opcode) {
+                let next = match direction {
+                    false if index + 1 < blocks.len() => blocks[index + 1],
+                    true if index > 0 => blocks[index - 1],
+                    _ => dispatch,
+                };
+
+                if lowers(opcode) {
+                    let native = b.create_block();
+                    let interpreted = b.create_block();
+                    b.ins().brif(lowering.lowered, native, &[], interpreted, &[]);
+
+                    b.switch_to_block(native);
+                    lowering.data_op(&mut b, opcode, instr.op);
+
+                    // the ip moves on just as Continuation::jump would
+                    let next_ip = match direction {
+                        false => (index as u32).wrapping_add(1),
+                        true => (index as u32).wrapping_sub(1),
+                    };
+                    let next_ip = b.ins().iconst(types::I32, next_ip as i64);
+                    b.ins().store(flags, next_ip, frame, FRAME_IP);
+                    let steps = b.ins().load(types::I64, flags, frame, FRAME_STEPS);
+                    let steps = b.ins().iadd_imm(steps, 1);
+                    b.ins().store(flags, steps, frame, FRAME_STEPS);
+                    b.ins().jump(next, &[]);
+
+                    b.switch_to_block(interpreted);
+                }
+
+                let opcode = b.ins().iconst(types::I32, opcode as i64);
+                let op = b.ins().iconst(types::I32, instr.op as i32 as i64);
+                let call = b.ins().call(data_op_fn, &[frame, opcode, op]);
+                let status = b.inst_results(call)[0];
+
+                let failed = b.ins().icmp_imm(IntCC::NotEqual, status, JIT_PENDING as i64);
+                b.ins().brif(failed, exit, &[status], next, &[]);
+            } else {
+                let call = b.ins().call(step_fn, &[frame]);
+                let status = b.inst_results(call)[0];
+
+                let continues = b.ins().icmp_imm(IntCC::Equal, status, JIT_PENDING as i64);
+                b.ins().brif(continues, dispatch, &[], exit, &[status]);
+            }
+        }
+
+        lowering.fail(&mut b, lowering.type_error, fail, FAIL_TYPE);
+        lowering.fail(&mut b, lowering.expected_zero, fail, FAIL_ZERO);
+
+        b.switch_to_block(exit);
+        let status = b.block_params(exit)[0];
+        b.ins().return_(&[status]);
+
+        b.seal_all_blocks();
+        b.finalize();
+
+        self.module.define_function(id, &mut ctx).map_err(compile_error)?;
+        self.module.clear_context(&mut ctx);
+        Ok(id)
+    }
+}
+
+impl Lowering {
+    fn load_ptr(&self, b: &mut FunctionBuilder, object: Value, offset: usize) -> Value {
+        b.ins().load(self.ptr, MemFlags::trusted(), object, offset as i32)
+    }
+
+    fn store_ptr(&self, b: &mut FunctionBuilder, value: Value, object: Value, offset: usize) {
+        b.ins().store(MemFlags::trusted(), value, object, offset as i32);
+    }
+
+    // fills in a block which leaves the routine through the fail helper
+    fn fail(&self, b: &mut FunctionBuilder, block: Block, fail: FuncRef, code: i32) {
+        b.switch_to_block(block);
+        let code = b.ins().iconst(types::I32, code as i64);
+        let call = b.ins().call(fail, &[self.frame, code]);
+        let status = b.inst_results(call)[0];
+        b.ins().jump(self.exit, &[status]);
+    }
+
+    // continues only if the object's header, when the heap keeps
+    // them, agrees with the tag
+    fn check_tag(&self, b: &mut FunctionBuilder, object: Value, tag: TypeTag) {
+        let check = b.create_block();
+        let ok = b.create_block();
+        b.ins().brif(self.tagged, check, &[], ok, &[]);
+
+        b.switch_to_block(check);
+        let header = b.ins().uload8(
+            types::I32,
+            MemFlags::trusted(),
+            object,
+            -(HEADER_SIZE as i32)
+        );
+        let same = b.ins().icmp_imm(IntCC::Equal, header, tag as i64);
+        let untagged = b.ins().icmp_imm(IntCC::Equal, header, TypeTag::Untagged as i64);
+        let matches = b.ins().bor(same, untagged);
+        b.ins().brif(matches, ok, &[], self.type_error, &[]);
+
+        b.switch_to_block(ok);
+    }
+
+    // continues only if the helper returned a non-null object
+    fn check_alloc(&self, b: &mut FunctionBuilder, object: Value) {
+        let ok = b.create_block();
+        let err = b.ins().iconst(types::I32, JIT_ERR as i64);
+        b.ins().brif(object, ok, &[], self.exit, &[err]);
+        b.switch_to_block(ok);
+    }
+
+    fn free(&self, b: &mut FunctionBuilder, kind: i32, object: Value) {
+        let ok = b.create_block();
+        let kind = b.ins().iconst(types::I32, kind as i64);
+        let call = b.ins().call(self.free, &[self.frame, kind, object]);
+        let status = b.inst_results(call)[0];
+        let failed = b.ins().icmp_imm(IntCC::NotEqual, status, JIT_PENDING as i64);
+        b.ins().brif(failed, self.exit, &[status], ok, &[]);
+        b.switch_to_block(ok);
+    }
+
+    // the same transformations as Thread::exec_data_op, in the same order
+    fn data_op(&self, b: &mut FunctionBuilder, opcode: u8, op: Opcode) {
+        let flags = MemFlags::trusted();
+        let data = self.load_ptr(b, self.frame, FRAME_DATA as usize);
+        let opcode_val = b.ins().iconst(types::I32, opcode as i64);
+
+        let new_data = match opcode {
+            OP_ZEROI => {
+                let tag = b.ins().iconst(types::I32, 1);
+                let call = b.ins().call(self.alloc_sum, &[self.frame, opcode_val, tag, data]);
+                let sum = b.inst_results(call)[0];
+                self.check_alloc(b, sum);
+                Some(sum)
+            },
+            OP_ZEROE => {
+                self.check_tag(b, data, TypeTag::Sum);
+                let tag = b.ins().load(types::I32, flags, data, SUM_TAG_OFFSET as i32);
+                let ok = b.create_block();
+                b.ins().brif(tag, ok, &[], self.expected_zero, &[]);
+
+                b.switch_to_block(ok);
+                let inner = self.load_ptr(b, data, SUM_DATA_OFFSET);
+                self.free(b, FREE_SUM, data);
+                Some(inner)
+            },
+            OP_UNITI => {
+                let call = b.ins().call(self.alloc_unit, &[self.frame, opcode_val]);
+                let unit = b.inst_results(call)[0];
+                self.check_alloc(b, unit);
+
+                let call = b.ins().call(self.alloc_product, &[self.frame, opcode_val, unit, data]);
+                let prod = b.inst_results(call)[0];
+                self.check_alloc(b, prod);
+                Some(prod)
+            },
+            OP_UNITE => {
+                self.check_tag(b, data, TypeTag::Product);
+                let unit = self.load_ptr(b, data, PRODUCT_FST_OFFSET);
+                self.check_tag(b, unit, TypeTag::Unit);
+                let inner = self.load_ptr(b, data, PRODUCT_SND_OFFSET);
+
+                self.free(b, FREE_UNIT, unit);
+                self.free(b, FREE_PRODUCT, data);
+                Some(inner)
+            },
+            OP_SWAPP | OP_SWAPP_R => {
+                self.check_tag(b, data, TypeTag::Product);
+                let fst = self.load_ptr(b, data, PRODUCT_FST_OFFSET);
+                let snd = self.load_ptr(b, data, PRODUCT_SND_OFFSET);
+
+                self.store_ptr(b, snd, data, PRODUCT_FST_OFFSET);
+                self.store_ptr(b, fst, data, PRODUCT_SND_OFFSET);
+                None
+            },
+            OP_ASSRP | OP_ASSLP => {
+                // (a * b) * c <-> a * (b * c), reusing the inner product
+                let (outer_field, other_field) = if opcode == OP_ASSRP {
+                    (PRODUCT_FST_OFFSET, PRODUCT_SND_OFFSET)
+                } else {
+                    (PRODUCT_SND_OFFSET, PRODUCT_FST_OFFSET)
+                };
+
+                self.check_tag(b, data, TypeTag::Product);
+                let inner = self.load_ptr(b, data, outer_field);
+                let near = self.load_ptr(b, inner, outer_field);
+                let far = self.load_ptr(b, inner, other_field);
+                let other = self.load_ptr(b, data, other_field);
+
+                self.store_ptr(b, far, inner, outer_field);
+                self.store_ptr(b, other, inner, other_field);
+                self.store_ptr(b, near, data, outer_field);
+                self.store_ptr(b, inner, data, other_field);
+                None
+            },
+            OP_SWAPS | OP_SWAPS_R => {
+                let (lc, rc) = decode_s(op);
+
+                self.check_tag(b, data, TypeTag::Sum);
+                let tag = b.ins().load(types::I32, flags, data, SUM_TAG_OFFSET as i32);
+                let left = b.ins().icmp_imm(IntCC::UnsignedLessThan, tag, lc as i64);
+                let to_right = b.ins().iadd_imm(tag, rc as i64);
+                let to_left = b.ins().iadd_imm(tag, -(lc as i64));
+                let tag = b.ins().select(left, to_right, to_left);
+                b.ins().store(flags, tag, data, SUM_TAG_OFFSET as i32);
+                None
+            },
+            // ID, START and the sum associators leave the data alone
+            _ => None,
+        };
+
+        if let Some(new_data) = new_data {
+            self.store_ptr(b, new_data, self.frame, FRAME_DATA as usize);
+        }
+    }
+}
+
+// data ops with a native lowering; the rest call into the interpreter
+fn lowers(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        OP_ID | OP_ID_R | OP_START | OP_ASSRS | OP_ASSLS
+            | OP_ZEROI | OP_ZEROE | OP_UNITI | OP_UNITE
+            | OP_SWAPP | OP_SWAPP_R | OP_ASSRP | OP_ASSLP
+            | OP_SWAPS | OP_SWAPS_R
+    )
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        let module = unsafe { ManuallyDrop::take(&mut self.module) };
+        unsafe { module.free_memory() };
+    }
+}
+
+/* Native helpers */
+extern "C" fn jit_ip(frame: *mut JitFrame<'_>, direction: i32) -> i32 {
+    let frame = unsafe { &mut *frame };
+    frame.flush();
+    let cont = frame.thread.continuation().get(frame.mem);
+
+    if cont.direction() as i32 == direction {
+        cont.ip() as i32
+    } else {
+        -1
+    }
+}
+
+extern "C" fn jit_context(frame: *mut JitFrame<'_>) -> i32 {
+    let frame = unsafe { &mut *frame };
+    let mut changed = false;
+
+    frame.flush();
+    let backward = frame.thread.continuation().get(frame.mem).direction();
+    loop {
+        let result = if !backward {
+            frame.thread.eval_context::<false>(frame.mem)
+        } else {
+            frame.thread.eval_context::<true>(frame.mem)
+        };
+        match result {
+            Ok(true) => changed = true,
+            Ok(false) => break,
+            Err(e) => return frame.fail(e),
+        }
+    }
+    frame.reload();
+
+    if changed { JIT_CHANGED } else { JIT_PENDING }
+}
+
+extern "C" fn jit_data_op(frame: *mut JitFrame<'_>, opcode: i32, op: i32) -> i32 {
+    let frame = unsafe { &mut *frame };
+
+    frame.interpret(|thread, mem| {
+        let result = if !thread.continuation().get(mem).direction() {
+            thread.exec_data_op::<false>(mem, opcode as u8, op as Opcode)
+        } else {
+            thread.exec_data_op::<true>(mem, opcode as u8, op as Opcode)
+        };
+        result.map(|_| EvalStatus::Pending)
+    })
+}
+
+extern "C" fn jit_step(frame: *mut JitFrame<'_>) -> i32 {
+    let frame = unsafe { &mut *frame };
+
+    frame.interpret(|thread, mem| thread.eval_next_instr(mem))
+}
+
+// allocations are counted against the opcode making them, just as
+// they are when the interpreter runs it
+fn jit_alloc<T: AllocObject>(frame: &mut JitFrame<'_>, opcode: i32, object: T) -> *const u8 {
+    let before = frame.mem.alloc_count();
+
+    match frame.mem.alloc(object) {
+        Ok(ptr) => {
+            frame.thread.record_allocs(opcode as u8, frame.mem.alloc_count() - before);
+            ptr.as_rawptr(frame.mem).as_word() as *const u8
+        },
+        Err(e) => {
+            frame.fail(e);
+            std::ptr::null()
+        },
+    }
+}
+
+// turns a pointer from native code back into one the heap understands
+fn scoped<'guard, T>(frame: &JitFrame<'guard>, object: *const u8) -> ScopedPtr<'guard, T> {
+    let raw = RawPtr::new(object as *const T);
+    ScopedPtr::new(frame.mem, raw.scoped_ref(frame.mem))
+}
+
+extern "C" fn jit_alloc_unit(frame: *mut JitFrame<'_>, opcode: i32) -> *const u8 {
+    let frame = unsafe { &mut *frame };
+    jit_alloc(frame, opcode, Unit::new())
+}
+
+extern "C" fn jit_alloc_sum(
+    frame: *mut JitFrame<'_>,
+    opcode: i32,
+    tag: i32,
+    data: *const u8,
+) -> *const u8 {
+    let frame = unsafe { &mut *frame };
+    let data = scoped::<()>(frame, data);
+
+    jit_alloc(frame, opcode, Sum::new(tag as Nat, data.into()))
+}
+
+extern "C" fn jit_alloc_product(
+    frame: *mut JitFrame<'_>,
+    opcode: i32,
+    fst: *const u8,
+    snd: *const u8,
+) -> *const u8 {
+    let frame = unsafe { &mut *frame };
+    let fst = scoped::<()>(frame, fst);
+    let snd = scoped::<()>(frame, snd);
+
+    jit_alloc(frame, opcode, Product::new(fst.into(), snd.into()))
+}
+
+extern "C" fn jit_free(frame: *mut JitFrame<'_>, kind: i32, object: *const u8) -> i32 {
+    let frame = unsafe { &mut *frame };
+    let result = match kind {
+        FREE_UNIT => frame.mem.dealloc(scoped::<Unit>(frame, object)),
+        FREE_SUM => frame.mem.dealloc(scoped::<Sum<()>>(frame, object)),
+        _ => frame.mem.dealloc(scoped::<Product<(), ()>>(frame, object)),
+    };
+
+    match result {
+        Ok(()) => JIT_PENDING,
+        Err(e) => frame.fail(e),
+    }
+}
+
+extern "C" fn jit_fail(frame: *mut JitFrame<'_>, code: i32) -> i32 {
+    let frame = unsafe { &mut *frame };
+    let kind = match code {
+        FAIL_ZERO => ErrorKind::ExpectedZero,
+        _ => ErrorKind::TypeError,
+    };
+
+    frame.fail(RuntimeError::new(kind))
+}
+
+/* Helper functions */
+// instructions at which a combinator context may take effect, i.e. the
+// last instruction of each first half and the first of each second half
+fn context_boundaries(instrs: &[DecodedInstr]) -> HashSet<usize> {
+    let mut boundaries = HashSet::new();
+
+    for (index, instr) in instrs.iter().enumerate() {
+        let split = match (instr.opcode(), instr.arg) {
+            (OP_SUMS, InstrArg::Pair(lc, _)) => index + lc as usize + 1,
+            (OP_SUME, InstrArg::Pair(_, rc)) => index - rc as usize,
+            (OP_PRODS, InstrArg::Nat(jmp)) => index + jmp as usize,
+            (OP_PRODE, InstrArg::Nat(jmp)) => index - jmp as usize + 1,
+            _ => continue,
+        };
+
+        boundaries.insert(split - 1);
+        boundaries.insert(split);
+    }
+
+    boundaries
+}
+
+fn compile_error<E: Display>(e: E) -> RuntimeError {
+    RuntimeError::new(ErrorKind::CompileError(e.to_string()))
+}
@@ -1,15 +1,25 @@
 #![feature(exclusive_range_pattern)]
 
 mod alloc;
-mod array;
-mod context;
+pub mod aot;
+pub mod array;
+pub mod asm;
+pub mod channel;
+pub mod context;
 mod printer;
-mod types;
 pub mod safeptr;
+pub mod sched;
 pub mod data;
 pub mod memory;
 pub mod bytecode;
 pub mod constants;
 pub mod error;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod op;
+pub mod opt;
+pub mod stdlib;
+pub mod suspend;
+pub mod types;
+pub mod value;
 pub mod vm;
@@ -1,14 +1,17 @@
 use std::slice::from_raw_parts;
 
 use crate::alloc::api::{AllocRaw, AllocObject, RawPtr};
+pub use crate::alloc::api::TypeTag;
 use crate::alloc::immix::StickyImmixHeap;
 use crate::array::ArraySize;
 use crate::data::Fraction;
 use crate::error::{RuntimeError, ErrorKind};
-use crate::safeptr::{ScopedPtr, ScopedRef};
+use crate::safeptr::{CellPtr, ScopedPtr, ScopedRef};
 
 /* Immix Heap */
 pub type Heap = StickyImmixHeap;
+pub use crate::alloc::immix::{BlockStats, HeapOptions, HeapStats};
+pub use crate::alloc::checked::{HeapFault, HeapMode, HeapSite};
 
 pub struct MutatorView<'memory> {
     heap: &'memory Heap,
@@ -54,6 +57,46 @@ impl<'memory> MutatorView<'memory> {
         Ok(())
     }
 
+    pub fn stats(&self) -> HeapStats { self.heap.stats() }
+    pub fn alloc_count(&self) -> usize { self.heap.alloc_count() }
+    pub fn heap_mode(&self) -> HeapMode { self.heap.mode() }
+    pub fn has_type_tags(&self) -> bool { self.heap.has_type_tags() }
+
+    pub fn set_site(&self, site: Option<HeapSite>) {
+        self.heap.set_site(site);
+    }
+
+    // always succeeds unless the heap is in checked mode
+    pub fn check_access<T>(&self, object: ScopedPtr<'_, T>)
+        -> Result<(), RuntimeError>
+    {
+        self.heap.check_live(object.as_rawptr(self))?;
+        Ok(())
+    }
+
+    // reads a pointer out of a cell, checking it like check_access
+    pub fn load<T: Sized>(&self, cell: &CellPtr<T>)
+        -> Result<ScopedPtr<'_, T>, RuntimeError>
+    {
+        let object = cell.get(self);
+        self.check_access(object)?;
+        Ok(object)
+    }
+
+    // None unless the heap was built with type tags
+    pub fn type_tag<T>(&self, object: ScopedPtr<'_, T>) -> Option<TypeTag> {
+        self.heap.type_tag(object.as_rawptr(self))
+    }
+
+    // always succeeds unless the heap was built with type tags
+    pub fn check_tag<T, U>(&self, object: ScopedPtr<'_, T>)
+        -> Result<(), RuntimeError>
+        where U: AllocObject
+    {
+        self.heap.check_tag::<T, U>(object.as_rawptr(self))?;
+        Ok(())
+    }
+
     pub fn alloc_array(
         &self,
         capacity: ArraySize
@@ -124,9 +167,19 @@ pub struct Memory {
 
 impl Memory {
     pub fn new() -> Memory {
-        Memory { heap: StickyImmixHeap::new() }
+        Memory::with_options(HeapOptions::default())
     }
 
+    pub fn with_mode(mode: HeapMode) -> Memory {
+        Memory { heap: StickyImmixHeap::with_mode(mode) }
+    }
+
+    pub fn with_options(options: HeapOptions) -> Memory {
+        Memory { heap: StickyImmixHeap::with_options(options) }
+    }
+
+    pub fn stats(&self) -> HeapStats { self.heap.stats() }
+
     pub fn mutate<M: Mutator>(&self, m: &M, input: M::Input) -> Result<M::Output, RuntimeError> {
         let mut guard = MutatorView::new(self);
         m.run(&mut guard, input)
@@ -1,10 +1,35 @@
 use crate::alloc::api::{AllocObject, RawPtr};
 use crate::array::*;
 use crate::data::*;
-use crate::error::RuntimeError;
+use crate::error::{RuntimeError, ErrorKind};
 use crate::memory::{MutatorScope, MutatorView};
 use crate::safeptr::{CellPtr, ScopedPtr};
 
+// in checked mode, every pointer read out of a field must still be live
+fn live<'guard, T>(ptr: ScopedPtr<'guard, T>, mem: &'guard MutatorView)
+    -> Result<ScopedPtr<'guard, T>, RuntimeError>
+{
+    mem.check_access(ptr)?;
+    Ok(ptr)
+}
+
+// validates the object's type tag, if the heap keeps them, before casting
+pub(crate) fn checked_cast<'guard, T, U>(mem: &'guard MutatorView, ptr: ScopedPtr<'guard, T>)
+    -> Result<ScopedPtr<'guard, U>, RuntimeError>
+    where U: AllocObject
+{
+    mem.check_tag::<T, U>(ptr)?;
+    Ok(unsafe { ptr.cast::<U>(mem) })
+}
+
+// like live, but the field must also hold a U
+fn live_as<'guard, T, U>(ptr: ScopedPtr<'guard, T>, mem: &'guard MutatorView)
+    -> Result<ScopedPtr<'guard, U>, RuntimeError>
+    where U: AllocObject
+{
+    checked_cast(mem, live(ptr, mem)?)
+}
+
 /*
  * Functions
  */
@@ -16,11 +41,11 @@ pub fn zeroi<'guard, T>(val: ScopedPtr<'guard, T>) -> Sum<T>
 
 pub fn zeroe<'guard, T>(
     val: ScopedPtr<'guard, Sum<T>>,
-    guard: &'guard dyn MutatorScope
-) -> ScopedPtr<'guard, T>
+    mem: &'guard MutatorView
+) -> Result<ScopedPtr<'guard, T>, RuntimeError>
     where T: AllocObject
 {
-    val.data(guard)
+    live(val.data(mem), mem)
 }
 
 pub fn swaps<'guard, T: AllocObject>(
@@ -52,57 +77,58 @@ pub fn uniti<'guard, T>(
 
 pub fn unite<'guard, T>(
     val: ScopedPtr<'guard, Product<Unit, T>>,
-    mem: &'guard dyn MutatorScope
-) -> ScopedPtr<'guard, T>
+    mem: &'guard MutatorView
+) -> Result<ScopedPtr<'guard, T>, RuntimeError>
     where T: AllocObject
 {
-    val.snd(mem)
-
+    live_as::<_, Unit>(val.fst(mem), mem)?;
+    live(val.snd(mem), mem)
 }
 
 pub fn swapp<'guard>(
     val: &ScopedPtr<'guard, Product<(), ()>>,
-    guard: &'guard dyn MutatorScope
-) {
-    let fst = val.fst(guard);
-    let snd = val.snd(guard);
+    mem: &'guard MutatorView
+) -> Result<(), RuntimeError> {
+    let fst = live(val.fst(mem), mem)?;
+    let snd = live(val.snd(mem), mem)?;
 
     val.set_fst(snd);
     val.set_snd(fst);
+    Ok(())
 }
 
 pub fn assrp<'guard>(
     val: &ScopedPtr<'guard, Product<(), ()>>,
-    guard: &'guard dyn MutatorScope
-) {
-    let inner_ptr = val.fst(guard);
-    let inner = unsafe { inner_ptr.cast::<Product<(), ()>>(guard) };
+    mem: &'guard MutatorView
+) -> Result<(), RuntimeError> {
+    let inner = live_as::<_, Product<(), ()>>(val.fst(mem), mem)?;
 
-    let a = inner.fst(guard);
-    let b = inner.snd(guard);
-    let c = val.snd(guard);
+    let a = live(inner.fst(mem), mem)?;
+    let b = live(inner.snd(mem), mem)?;
+    let c = live(val.snd(mem), mem)?;
 
     inner.set_fst(b);
     inner.set_snd(c);
     val.set_fst(a);
-    val.set_snd(inner.as_untyped(guard));
+    val.set_snd(inner.as_untyped(mem));
+    Ok(())
 }
 
 pub fn asslp<'guard>(
     val: &ScopedPtr<'guard, Product<(), ()>>,
-    guard: &'guard dyn MutatorScope
-) {
-    let inner_ptr = val.snd(guard);
-    let inner = unsafe { inner_ptr.cast::<Product<(), ()>>(guard) };
+    mem: &'guard MutatorView
+) -> Result<(), RuntimeError> {
+    let inner = live_as::<_, Product<(), ()>>(val.snd(mem), mem)?;
 
-    let a = val.fst(guard);
-    let b = inner.fst(guard);
-    let c = inner.snd(guard);
+    let a = live(val.fst(mem), mem)?;
+    let b = live(inner.fst(mem), mem)?;
+    let c = live(inner.snd(mem), mem)?;
 
     inner.set_fst(a);
     inner.set_snd(b);
-    val.set_fst(inner.as_untyped(guard));
+    val.set_fst(inner.as_untyped(mem));
     val.set_snd(c);
+    Ok(())
 }
 
 pub fn dist<'guard>(
@@ -112,7 +138,7 @@ pub fn dist<'guard>(
     mem: &'guard MutatorView
 ) -> Result<ScopedPtr<'guard, Sum<Product<(), ()>>>, RuntimeError>
 {
-    let sum = val.fst(mem);
+    let sum = live_as::<_, Sum<()>>(val.fst(mem), mem)?;
     let tag = sum.tag();
 
     if tag <= (lc - 1) as u32 {
@@ -124,7 +150,7 @@ pub fn dist<'guard>(
                 val.cast::<Product<(), ()>>(mem)
             };
 
-            new_val.set_fst(sum.data(mem));
+            new_val.set_fst(live(sum.data(mem), mem)?);
             new_sum.set_data(new_val);
             new_sum.set_tag(0);
 
@@ -146,7 +172,7 @@ pub fn dist<'guard>(
                 val.cast::<Product<(), ()>>(mem)
             };
 
-            new_val.set_fst(sum.data(mem));
+            new_val.set_fst(live(sum.data(mem), mem)?);
             new_sum.set_data(new_val);
             new_sum.set_tag(1);
 
@@ -170,12 +196,12 @@ pub fn fact<'guard>(
     mem: &'guard MutatorView
 ) -> Result<ScopedPtr<'guard, Product</*Sum*/(), ()>>, RuntimeError>
 {
-    let prod = val.data(mem);
+    let prod = live_as::<_, Product<(), ()>>(val.data(mem), mem)?;
     let tag = val.tag();
 
     if tag == 0 {
         if lc == 1 {
-            let fst = prod.fst(mem);
+            let fst = live(prod.fst(mem), mem)?;
             let cast_val = unsafe { val.cast::<Sum<()>>(mem) };
             cast_val.set_data(fst);
             prod.set_fst(cast_val.as_untyped(mem));
@@ -189,15 +215,14 @@ pub fn fact<'guard>(
     } else {
         if rc == 1 {
             let cast_val = unsafe { val.cast::<Sum<()>>(mem) };
-            let fst = prod.fst(mem);
+            let fst = live(prod.fst(mem), mem)?;
             cast_val.set_data(fst);
             cast_val.set_tag(lc as u32);
             prod.set_fst(cast_val.as_untyped(mem));
 
             Ok(prod)
         } else {
-            let fst = prod.fst(mem);
-            let cast_fst = unsafe { fst.cast::<Sum<()>>(mem) };
+            let cast_fst = live_as::<_, Sum<()>>(prod.fst(mem), mem)?;
             cast_fst.set_tag(cast_fst.tag() + lc as u32);
             mem.dealloc(val)?;
 
@@ -213,29 +238,30 @@ pub fn expn<'guard>(
 ) -> Result<ScopedPtr<'guard, Sum<()>>, RuntimeError>
 {
     if val.tag() == 0 {
-        let cast_val = unsafe { val.cast::<Sum<Negative<()>>>(mem) };
-        let inner = cast_val.data(mem)
-            .data(mem);
+        let neg = live_as::<_, Negative<()>>(val.data(mem), mem)?;
+        let inner = live(neg.data(mem), mem)?;
 
         if div == 0 {
             val.set_data(inner);
             val.set_tag(1);
+            mem.dealloc(neg)?;
             Ok(val)
         } else {
-            let cast_inner = unsafe { inner.cast::<Sum<()>>(mem) };
+            let cast_inner = checked_cast::<_, Sum<()>>(mem, inner)?;
             let inner_tag = cast_inner.tag();
             cast_inner.set_tag(inner_tag + div);
 
-            mem.dealloc(cast_val.data(mem))?;
-            mem.dealloc(cast_val)?;
+            mem.dealloc(neg)?;
+            mem.dealloc(val)?;
             Ok(cast_inner)
         }
     } else {
-        let inner = val.data(mem);
+        let inner = live(val.data(mem), mem)?;
 
         if div == 0 {
             let neg = mem.alloc(Negative::new(CellPtr::new_with(inner)))?;
             val.set_data(unsafe { neg.cast::<()>(mem) });
+            val.set_tag(0);
 
             Ok(val)
         } else {
@@ -264,8 +290,8 @@ pub fn colf<'guard>(
     mem: &'guard MutatorView
 ) -> Result<(), RuntimeError>
 {
-    let frac = prod.fst(mem);
-    let val = prod.snd(mem);
+    let frac = live(prod.fst(mem), mem)?;
+    let val = live(prod.snd(mem), mem)?;
 
     mem.dealloc_frac(frac, val, frac.size())?;
     mem.dealloc(prod)
@@ -279,7 +305,7 @@ pub fn fold<'guard>(
     if val.tag() == 0 {
         let cast_val = unsafe { val.cast::<Sum<Unit>>(mem) };
 
-        mem.dealloc(cast_val.data(mem))?;
+        mem.dealloc(live_as::<_, Unit>(cast_val.data(mem), mem)?)?;
         mem.dealloc(cast_val)?;
         Array::alloc(mem)
     } else {
@@ -287,8 +313,8 @@ pub fn fold<'guard>(
             val.cast::<Sum<Product<(), Inductive<()>>>>(mem)
         };
 
-        let data = cast_val.data(mem);
-        let inductive = data.snd(mem);
+        let data = live_as::<_, Product<(), Inductive<()>>>(cast_val.data(mem), mem)?;
+        let inductive = live_as::<_, Inductive<()>>(data.snd(mem), mem)?;
 
         inductive.push(mem, CellPtr::new_with(data.fst(mem)))?;
         mem.dealloc(data)?;
@@ -299,18 +325,62 @@ pub fn fold<'guard>(
 }
 
 pub fn fold_nat<'guard>(
-    val: ScopedPtr<'guard, Sum<Nat>>,
+    val: ScopedPtr<'guard, Sum<()>>,
     mem: &'guard MutatorView
 ) -> Result<ScopedPtr<'guard, Nat>, RuntimeError>
 {
-    let nat = val.data(mem);
-    let mut binding = nat.as_rawptr(mem);
-    let nat_mut = binding.as_mut();
+    if val.tag() == 0 {
+        let cast_val = unsafe { val.cast::<Sum<Unit>>(mem) };
+
+        mem.dealloc(live_as::<_, Unit>(cast_val.data(mem), mem)?)?;
+        mem.dealloc(cast_val)?;
+        mem.alloc(0 as Nat)
+    } else {
+        let cast_val = unsafe { val.cast::<Sum<Nat>>(mem) };
+        let nat = live_as::<_, Nat>(cast_val.data(mem), mem)?;
+        let mut binding = nat.as_rawptr(mem);
+        let nat_mut = binding.as_mut();
+
+        *nat_mut = nat_mut.checked_add(1)
+            .ok_or(RuntimeError::new(ErrorKind::IntOverflow))?;
+        mem.dealloc(cast_val)?;
+
+        Ok(nat)
+    }
+}
+
+pub fn fold_bignat<'guard>(
+    val: ScopedPtr<'guard, Sum<()>>,
+    mem: &'guard MutatorView
+) -> Result<ScopedPtr<'guard, BigNat>, RuntimeError>
+{
+    if val.tag() == 0 {
+        let cast_val = unsafe { val.cast::<Sum<Unit>>(mem) };
 
-    *nat_mut = *nat_mut + 1;
-    mem.dealloc(val)?;
+        mem.dealloc(live_as::<_, Unit>(cast_val.data(mem), mem)?)?;
+        mem.dealloc(cast_val)?;
+        BigNat::alloc(mem)
+    } else {
+        let cast_val = unsafe { val.cast::<Sum<BigNat>>(mem) };
+        let nat = live_as::<_, BigNat>(cast_val.data(mem), mem)?;
+
+        // add one, carrying into a new limb if every limb overflows
+        for index in 0..nat.length() {
+            let limb = nat.get(mem, index)?;
+
+            if limb == Nat::MAX {
+                nat.set(mem, index, 0)?;
+            } else {
+                nat.set(mem, index, limb + 1)?;
+                mem.dealloc(cast_val)?;
+                return Ok(nat);
+            }
+        }
 
-    Ok(nat)
+        nat.push(mem, 1)?;
+        mem.dealloc(cast_val)?;
+        Ok(nat)
+    }
 }
 
 pub fn unfold<'guard>(
@@ -347,15 +417,57 @@ pub fn unfold<'guard>(
 pub fn unfold_nat<'guard>(
     val: ScopedPtr<'guard, Nat>,
     mem: &'guard MutatorView
-) -> Result<ScopedPtr<'guard, Sum<Nat>>, RuntimeError>
+) -> Result<ScopedPtr<'guard, Sum<()>>, RuntimeError>
+{
+    if *val == 0 {
+        mem.dealloc(val)?;
+        let ptr = mem.alloc(
+            Sum::new(0, CellPtr::new_with(mem.alloc(Unit::new())?))
+        )?;
+
+        Ok(unsafe { ptr.cast::<Sum<()>>(mem) })
+    } else {
+        let mut binding = val.as_rawptr(mem);
+        let val_mut = binding.as_mut();
+        *val_mut -= 1;
+
+        let ptr = mem.alloc(Sum::new(1, CellPtr::new_with(val)))?;
+        Ok(unsafe { ptr.cast::<Sum<()>>(mem) })
+    }
+}
+
+pub fn unfold_bignat<'guard>(
+    val: ScopedPtr<'guard, BigNat>,
+    mem: &'guard MutatorView
+) -> Result<ScopedPtr<'guard, Sum<()>>, RuntimeError>
 {
-    let mut binding = val.as_rawptr(mem);
-    let val_mut = binding.as_mut();
-    *val_mut = *val_mut - 1;
+    if val.length() == 0 {
+        val.dealloc_data(mem)?;
+        mem.dealloc(val)?;
+        let ptr = mem.alloc(
+            Sum::new(0, CellPtr::new_with(mem.alloc(Unit::new())?))
+        )?;
 
-    if *val_mut == 0 {
-        mem.alloc(Sum::new(0, CellPtr::new_with(val)))
+        Ok(unsafe { ptr.cast::<Sum<()>>(mem) })
     } else {
-        mem.alloc(Sum::new(1, CellPtr::new_with(val)))
+        // subtract one, borrowing from higher limbs
+        for index in 0..val.length() {
+            let limb = val.get(mem, index)?;
+
+            if limb == 0 {
+                val.set(mem, index, Nat::MAX)?;
+            } else {
+                val.set(mem, index, limb - 1)?;
+                break;
+            }
+        }
+
+        // keep the representation normalized
+        if val.top(mem)? == 0 {
+            val.pop(mem)?;
+        }
+
+        let ptr = mem.alloc(Sum::new(1, CellPtr::new_with(val)))?;
+        Ok(unsafe { ptr.cast::<Sum<()>>(mem) })
     }
 }
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use crate::bytecode::*;
+use crate::constants::*;
+use crate::data::*;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::MutatorView;
+use crate::safeptr::ScopedPtr;
+
+/*
+ * Bytecode Optimizer
+ *
+ * Functions are parsed into a tree of combinators so that rewrites never
+ * have to think about jump offsets; the tree is flattened back out
+ * afterwards, which recomputes every combinator jump and call target.
+ */
+pub fn optimize_function<'guard>(
+    mem: &'guard MutatorView,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let instrs = decode_function(mem, function)?;
+    alloc_function(mem, &optimize(&instrs)?)
+}
+
+pub fn optimize(instrs: &[DecodedInstr]) -> Result<Vec<DecodedInstr>, RuntimeError> {
+    verify_function(instrs)?;
+
+    let items = parse(instrs, 0, instrs.len())?;
+    Ok(flatten(&peephole(items)))
+}
+
+pub fn inline_function<'guard>(
+    mem: &'guard MutatorView,
+    function: ScopedPtr<'guard, Function>,
+    budget: usize,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let instrs = decode_function(mem, function)?;
+    alloc_function(mem, &inline(&instrs, budget)?)
+}
+
+// splices the body of every callee of at most `budget` instructions,
+// counting those of the callees it inlines in turn, in place of the
+// calls to it; callees stay in the function, since calls which could
+// not be inlined may still refer to them
+pub fn inline(instrs: &[DecodedInstr], budget: usize) -> Result<Vec<DecodedInstr>, RuntimeError> {
+    verify_function(instrs)?;
+
+    let mut inliner = Inliner {
+        instrs,
+        budget,
+        bodies: HashMap::new(),
+        active: Vec::new(),
+    };
+    let items = parse(instrs, 0, instrs.len())?;
+    Ok(flatten(&inliner.inline(items)?))
+}
+
+/* Structured Functions */
+#[derive(Clone, Debug)]
+pub(crate) enum Item {
+    // index in the original function, kept so calls can be re-targeted
+    Instr(usize, DecodedInstr),
+    Sum {
+        sums: DecodedInstr,
+        sume: DecodedInstr,
+        left: Vec<Item>,
+        right: Vec<Item>,
+    },
+    Prod {
+        prods: DecodedInstr,
+        prode: DecodedInstr,
+        first: Vec<Item>,
+        second: Vec<Item>,
+    },
+}
+
+pub(crate) fn parse(
+    instrs: &[DecodedInstr],
+    lo: usize,
+    hi: usize,
+) -> Result<Vec<Item>, RuntimeError> {
+    let mut items = Vec::new();
+    let mut index = lo;
+
+    while index < hi {
+        let instr = instrs[index];
+
+        match (instr.opcode(), instr.arg) {
+            (OP_SUMS, InstrArg::Pair(lc, rc)) => {
+                let split = index + lc as usize + 1;
+                let end = split + rc as usize;
+                if end >= hi {
+                    return Err(RuntimeError::new(ErrorKind::BoundsError));
+                }
+
+                items.push(Item::Sum {
+                    sums: instr,
+                    sume: instrs[end],
+                    left: parse(instrs, index + 1, split)?,
+                    right: parse(instrs, split, end)?,
+                });
+                index = end + 1;
+            },
+            (OP_PRODS, InstrArg::Nat(jmp)) => {
+                let split = index + jmp as usize;
+                let end = find_prode(instrs, split, hi)?;
+
+                items.push(Item::Prod {
+                    prods: instr,
+                    prode: instrs[end],
+                    first: parse(instrs, index + 1, split)?,
+                    second: parse(instrs, split, end)?,
+                });
+                index = end + 1;
+            },
+            _ => {
+                items.push(Item::Instr(index, instr));
+                index += 1;
+            },
+        }
+    }
+
+    Ok(items)
+}
+
+pub(crate) fn flatten(items: &[Item]) -> Vec<DecodedInstr> {
+    let mut out = Vec::new();
+    let mut moved = HashMap::new();
+    emit(items, &mut out, &mut moved);
+
+    // START and END are never rewritten, so every call target survives
+    for instr in out.iter_mut() {
+        if let (OP_CALL | OP_UNCALL, InstrArg::Pair(start, end)) = (instr.opcode(), instr.arg) {
+            instr.arg = InstrArg::Pair(
+                moved[&(start as usize)] as Nat,
+                moved[&(end as usize)] as Nat,
+            );
+        }
+    }
+
+    out
+}
+
+fn emit(items: &[Item], out: &mut Vec<DecodedInstr>, moved: &mut HashMap<usize, usize>) {
+    for item in items {
+        match item {
+            Item::Instr(index, instr) => {
+                moved.insert(*index, out.len());
+                out.push(*instr);
+            },
+            Item::Sum { sums, sume, left, right } => {
+                let start = out.len();
+                out.push(*sums);
+                emit(left, out, moved);
+                let lc = out.len() - start - 1;
+                emit(right, out, moved);
+                let rc = out.len() - start - lc - 1;
+
+                let arg = InstrArg::Pair(lc as Nat, rc as Nat);
+                out[start].arg = arg;
+                out.push(DecodedInstr { op: sume.op, arg });
+            },
+            Item::Prod { prods, prode, first, second } => {
+                let start = out.len();
+                out.push(*prods);
+                emit(first, out, moved);
+                let split = out.len();
+                emit(second, out, moved);
+
+                out[start].arg = InstrArg::Nat((split - start) as Nat);
+                out.push(DecodedInstr {
+                    op: prode.op,
+                    arg: InstrArg::Nat((out.len() - split + 1) as Nat),
+                });
+            },
+        }
+    }
+}
+
+/* Inlining */
+struct Inliner<'a> {
+    instrs: &'a [DecodedInstr],
+    budget: usize,
+    // callee bodies with their own calls inlined, or None if the callee
+    // can't be inlined
+    bodies: HashMap<usize, Option<Vec<Item>>>,
+    // functions currently being inlined into, so recursive calls are
+    // left as calls
+    active: Vec<usize>,
+}
+
+impl<'a> Inliner<'a> {
+    fn inline(&mut self, items: Vec<Item>) -> Result<Vec<Item>, RuntimeError> {
+        let mut out = Vec::with_capacity(items.len());
+
+        for item in items {
+            match item {
+                Item::Instr(index, instr) => match (instr.opcode(), instr.arg) {
+                    (OP_START, _) => {
+                        self.active.push(index);
+                        out.push(item);
+                    },
+                    (OP_END, _) => {
+                        self.active.pop();
+                        out.push(item);
+                    },
+                    (opcode @ (OP_CALL | OP_UNCALL), InstrArg::Pair(start, end)) => {
+                        let start = start as usize;
+
+                        let body = if self.active.contains(&start) {
+                            None
+                        } else {
+                            self.body(start, end as usize)?
+                        };
+
+                        match body {
+                            Some(body) if opcode == OP_CALL => out.extend(body),
+                            Some(body) => out.extend(invert(body)),
+                            None => out.push(item),
+                        }
+                    },
+                    _ => out.push(item),
+                },
+                Item::Sum { sums, sume, left, right } => out.push(Item::Sum {
+                    sums,
+                    sume,
+                    left: self.inline(left)?,
+                    right: self.inline(right)?,
+                }),
+                Item::Prod { prods, prode, first, second } => out.push(Item::Prod {
+                    prods,
+                    prode,
+                    first: self.inline(first)?,
+                    second: self.inline(second)?,
+                }),
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn body(&mut self, start: usize, end: usize) -> Result<Option<Vec<Item>>, RuntimeError> {
+        if let Some(body) = self.bodies.get(&start) {
+            return Ok(body.clone());
+        }
+
+        // the budget applies to the body with its own callees inlined,
+        // so a chain of small functions can't add up to a large one
+        let body = parse(self.instrs, start + 1, end)?;
+        let body = if size(&body) <= self.budget && can_inline(&body) {
+            self.active.push(start);
+            let body = self.inline(body)?;
+            self.active.pop();
+
+            Some(body).filter(|body| size(body) <= self.budget)
+        } else {
+            None
+        };
+
+        self.bodies.insert(start, body.clone());
+        Ok(body)
+    }
+}
+
+fn size(items: &[Item]) -> usize {
+    items.iter()
+        .map(|item| match item {
+            Item::Instr(..) => 1,
+            Item::Sum { left, right, .. } => size(left) + size(right) + 2,
+            Item::Prod { first, second, .. } => size(first) + size(second) + 2,
+        })
+        .sum()
+}
+
+// EXPN and COLN turn execution around, which only comes back to the
+// right place if the callee still has its own START and END
+fn can_inline(items: &[Item]) -> bool {
+    items.iter().all(|item| match item {
+        Item::Instr(_, instr) => !matches!(
+            instr.opcode(),
+            OP_EXPN | OP_COLN | OP_START | OP_END
+        ),
+        Item::Sum { left, right, .. } => can_inline(left) && can_inline(right),
+        Item::Prod { first, second, .. } => can_inline(first) && can_inline(second),
+    })
+}
+
+// gives items which run forwards the way the originals run backwards
+fn invert(items: Vec<Item>) -> Vec<Item> {
+    items.into_iter()
+        .rev()
+        .map(|item| match item {
+            Item::Instr(index, instr) => Item::Instr(index, invert_instr(instr)),
+            Item::Sum { sums, sume, left, right } => Item::Sum {
+                sums: invert_instr(sume),
+                sume: invert_instr(sums),
+                left: invert(left),
+                right: invert(right),
+            },
+            Item::Prod { prods, prode, first, second } => Item::Prod {
+                prods: invert_instr(prode),
+                prode: invert_instr(prods),
+                first: invert(first),
+                second: invert(second),
+            },
+        })
+        .collect()
+}
+
+fn invert_instr(instr: DecodedInstr) -> DecodedInstr {
+    DecodedInstr {
+        op: resolve_op(instr.op, true),
+        arg: instr.arg,
+    }
+}
+
+/* Peephole Rewrites */
+fn peephole(items: Vec<Item>) -> Vec<Item> {
+    let mut out: Vec<Item> = Vec::with_capacity(items.len());
+
+    for item in items {
+        match item {
+            Item::Instr(_, instr) if is_noop(&instr) => {},
+            Item::Instr(index, instr) => match out.last() {
+                Some(Item::Instr(_, prev)) if cancels(prev, &instr) => {
+                    out.pop();
+                },
+                _ => out.push(Item::Instr(index, instr)),
+            },
+            Item::Sum { sums, sume, left, right } => {
+                let left = peephole(left);
+                let right = peephole(right);
+
+                // a sum combinator which does nothing in either branch
+                // just unwraps and rewraps the same value
+                if !left.is_empty() || !right.is_empty() {
+                    out.push(Item::Sum { sums, sume, left, right });
+                }
+            },
+            Item::Prod { prods, prode, first, second } => {
+                let first = peephole(first);
+                let second = peephole(second);
+
+                if !first.is_empty() || !second.is_empty() {
+                    out.push(Item::Prod { prods, prode, first, second });
+                }
+            },
+        }
+    }
+
+    out
+}
+
+fn is_noop(instr: &DecodedInstr) -> bool {
+    matches!(instr.opcode(), OP_ID | OP_ID_R | OP_ASSRS | OP_ASSLS)
+}
+
+// whether running `snd` straight after `fst` always gives back the
+// value `fst` started with
+fn cancels(fst: &DecodedInstr, snd: &DecodedInstr) -> bool {
+    let (a, b) = (fst.opcode(), snd.opcode());
+
+    match (a, b) {
+        // swaps are their own inverse, whichever way they are encoded
+        (OP_SWAPP | OP_SWAPP_R, OP_SWAPP | OP_SWAPP_R) => true,
+        (OP_SWAPS | OP_SWAPS_R, OP_SWAPS | OP_SWAPS_R) => {
+            let (lc, rc) = decode_s(fst.op);
+            decode_s(snd.op) == (rc, lc)
+        },
+        // folding a nat can overflow, and that error must not be lost
+        (OP_FOLD, OP_UFOLD) if decode_i(fst.op) == FOLD_NAT => false,
+        // nor can the error ZEROE gives when there is no zero to remove
+        (OP_ZEROE, OP_ZEROI) => false,
+        (OP_ZEROI | OP_ZEROE | OP_UNITI | OP_UNITE | OP_ASSRP | OP_ASSLP
+            | OP_DIST | OP_FACT | OP_FOLD | OP_UFOLD, _) => {
+            b == (!a & OP_MASK as u8) && fst.op >> 5 == snd.op >> 5
+        },
+        _ => false,
+    }
+}
@@ -71,6 +71,7 @@ impl<'guard, T: Sized + PartialEq> PartialEq for ScopedPtr<'guard, T> {
 
 /* Cell Pointers */
 #[derive(Clone, Debug)]
+#[repr(transparent)]
 pub struct CellPtr<T: Sized> {
     inner: Cell<RawPtr<T>>,
 }
@@ -0,0 +1,138 @@
+use crate::channel::Pipe;
+use crate::data::Nat;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::{MutatorScope, MutatorView};
+use crate::safeptr::{CellPtr, ScopedPtr};
+use crate::types::IType;
+use crate::vm::{EvalStatus, Thread};
+
+/*
+ * Scheduling
+ *
+ * A Scheduler takes turns running threads which all live in the same
+ * Memory, for at most `fuel` instructions each turn. A thread blocked
+ * on a channel gives up the rest of its turn and tries the same
+ * instruction again on its next one.
+ *
+ * Threads talk through Pipes connected by the scheduler, which block
+ * until both ends meet. Reversing every thread and running them again
+ * undoes each meeting in turn, last first. The pipes live as long as
+ * the threads' channels do, so close the scheduler once it's done with.
+ *
+ * One round can be spent only offering values, with nothing taken
+ * until the next, so it takes two rounds in a row without any thread
+ * getting anywhere to call it a deadlock.
+ */
+pub type ThreadId = usize;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TaskState {
+    Ready,
+    Blocked,
+    Finished,
+}
+
+struct Task {
+    thread: CellPtr<Thread>,
+    state: TaskState,
+}
+
+pub struct Scheduler {
+    tasks: Vec<Task>,
+    fuel: u64,
+}
+
+impl Scheduler {
+    pub fn new(fuel: u64) -> Scheduler {
+        Scheduler { tasks: Vec::new(), fuel }
+    }
+
+    pub fn spawn(&mut self, thread: ScopedPtr<'_, Thread>) -> ThreadId {
+        self.tasks.push(Task {
+            thread: CellPtr::new_with(thread),
+            state: TaskState::Ready,
+        });
+        self.tasks.len() - 1
+    }
+
+    pub fn thread<'guard>(&self, guard: &'guard dyn MutatorScope, id: ThreadId)
+        -> ScopedPtr<'guard, Thread>
+    {
+        self.tasks[id].thread.get(guard)
+    }
+
+    pub fn state(&self, id: ThreadId) -> TaskState { self.tasks[id].state }
+
+    // a pipe of values of type `ty`, between channel `a.1` of thread
+    // `a.0` and channel `b.1` of thread `b.0`
+    pub fn connect(
+        &self,
+        guard: &dyn MutatorScope,
+        a: (ThreadId, Nat),
+        b: (ThreadId, Nat),
+        ty: IType,
+    ) {
+        let (a_end, b_end) = Pipe::pair(ty);
+        self.thread(guard, a.0).attach_channel(a.1, Box::new(a_end));
+        self.thread(guard, b.0).attach_channel(b.1, Box::new(b_end));
+    }
+
+    // closes every thread, dropping the pipes between them along with
+    // anything else attached, and forgets them
+    pub fn close(&mut self, guard: &dyn MutatorScope) {
+        for task in self.tasks.drain(..) {
+            task.thread.get(guard).close();
+        }
+    }
+
+    // turns every thread around, so that running again undoes the run
+    pub fn reverse(&mut self, guard: &dyn MutatorScope) {
+        for task in &mut self.tasks {
+            task.thread.get(guard).reverse(guard);
+            task.state = TaskState::Ready;
+        }
+    }
+
+    // gives every unfinished thread one turn, returning whether any of
+    // them got anywhere
+    pub fn round(&mut self, mem: &MutatorView) -> Result<bool, RuntimeError> {
+        let mut progress = false;
+
+        for task in &mut self.tasks {
+            if task.state == TaskState::Finished {
+                continue;
+            }
+
+            let thread = task.thread.get(mem);
+            let before = thread.steps();
+            task.state = match thread.run_for(mem, self.fuel) {
+                Ok(EvalStatus::Ok) => TaskState::Finished,
+                Ok(_) => TaskState::Ready,
+                Err(e) if *e.error_kind() == ErrorKind::WouldBlock => TaskState::Blocked,
+                Err(e) => return Err(e),
+            };
+
+            progress |= thread.steps() != before || task.state == TaskState::Finished;
+        }
+
+        Ok(progress)
+    }
+
+    // runs every thread until they've all finished
+    pub fn run(&mut self, mem: &MutatorView) -> Result<(), RuntimeError> {
+        let mut idle = 0;
+
+        while self.tasks.iter().any(|task| task.state != TaskState::Finished) {
+            if self.round(mem)? {
+                idle = 0;
+            } else {
+                idle += 1;
+                if idle == 2 {
+                    return Err(RuntimeError::new(ErrorKind::Deadlock));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
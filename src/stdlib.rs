@@ -0,0 +1,230 @@
+use crate::asm::*;
+use crate::bytecode::*;
+use crate::constants::*;
+use crate::data::Nat;
+use crate::types::IType;
+
+/*
+ * Standard Library
+ *
+ * Reversible arithmetic, assembled into a single block of functions
+ * which can be linked onto the end of any program. Every function is
+ * an isomorphism between its input and output types; running one
+ * backwards on a value outside its image (e.g. dec on 0) fails with
+ * ExpectedZero rather than producing garbage.
+ *
+ * bool is 1 + 1, with false on the left. int is nat + nat, where
+ * left n is n and right n is -(n + 1).
+ *
+ * Anything that needs to go round more than once loops with the
+ * additive trace, using the loop forms in asm.rs, so no function grows
+ * the context stack with the size of its arguments.
+ */
+pub struct Signature {
+    pub input: IType,
+    pub output: IType,
+}
+
+pub struct Iso {
+    pub name: &'static str,
+    pub signature: Signature,
+    // indices of the function's START and END within the library
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct Library {
+    instrs: Vec<DecodedInstr>,
+    isos: Vec<Iso>,
+}
+
+impl Library {
+    pub fn new() -> Library {
+        let (signatures, functions): (Vec<_>, Vec<_>) = definitions().into_iter()
+            .map(|(name, signature, body)| ((name, signature), (name, body)))
+            .unzip();
+
+        // every call is to another definition, so this can't fail
+        let block = assemble(functions).unwrap();
+        let isos = signatures.into_iter()
+            .zip(block.bounds)
+            .map(|((name, signature), (start, end))| Iso { name, signature, start, end })
+            .collect();
+
+        Library { instrs: block.instrs, isos }
+    }
+
+    pub fn instrs(&self) -> &[DecodedInstr] { &self.instrs }
+    pub fn isos(&self) -> &[Iso] { &self.isos }
+
+    pub fn get(&self, name: &str) -> Option<&Iso> {
+        self.isos.iter().find(|iso| iso.name == name)
+    }
+
+    // the library's instructions with every call target moved along by
+    // `base`, for appending to a program of that length
+    pub fn link(&self, base: usize) -> Vec<DecodedInstr> {
+        self.instrs.iter()
+            .map(|instr| match (instr.opcode(), instr.arg) {
+                (OP_CALL | OP_UNCALL, InstrArg::Pair(start, end)) => DecodedInstr {
+                    op: instr.op,
+                    arg: InstrArg::Pair(start + base as Nat, end + base as Nat),
+                },
+                _ => *instr,
+            })
+            .collect()
+    }
+
+    // a CALL (or UNCALL, if `invert`) of the named function, once the
+    // library has been linked at `base`
+    pub fn call(&self, name: &str, base: usize, invert: bool) -> Option<DecodedInstr> {
+        let iso = self.get(name)?;
+        let op = if invert { OP_UNCALL } else { OP_CALL };
+
+        Some(call_instr(op, (iso.start + base, iso.end + base)))
+    }
+
+    // a complete program which runs the named function on its input
+    pub fn program(&self, name: &str) -> Option<Vec<DecodedInstr>> {
+        let mut instrs = vec![instr(OP_START), self.call(name, 3, false)?, instr(OP_END)];
+        instrs.extend(self.link(3));
+
+        Some(instrs)
+    }
+}
+
+impl Default for Library {
+    fn default() -> Library { Library::new() }
+}
+
+/* Definitions */
+fn definitions() -> Vec<(&'static str, Signature, Vec<Asm>)> {
+    let nat2 = || IType::prod(IType::Nat, IType::Nat);
+    let bool = || IType::sum(IType::Unit, IType::Unit);
+    let int = || IType::sum(IType::Nat, IType::Nat);
+
+    vec![
+        // n <-> n + 1
+        ("inc", sig(IType::Nat, IType::Nat), [inr(), fold()].concat()),
+        ("dec", sig(IType::Nat, IType::Nat), [unfold(), op(OP_ZEROE)].concat()),
+        // (a, b) <-> (a, a + b)
+        ("add", sig(nat2(), nat2()), repeat(call("inc"))),
+        // (a, b) <-> (a, b - a)
+        ("sub", sig(nat2(), nat2()), uncall("add")),
+        // (a, (b, acc)) <-> (a, (b, acc + a * b)), by adding a to acc
+        // b times
+        ("mul", sig(
+            IType::prod(IType::Nat, nat2()),
+            IType::prod(IType::Nat, nat2()),
+        ), [
+            op(OP_ASSLP),
+            prod(op(OP_SWAPP), vec![]),
+            op(OP_ASSRP),
+            repeat(call("add")),
+            op(OP_ASSLP),
+            prod(op(OP_SWAPP), vec![]),
+            op(OP_ASSRP),
+        ].concat()),
+        // (n, acc) <-> (n, acc + n(n - 1) / 2), by adding each index
+        // below n to acc
+        ("triangle", sig(nat2(), nat2()), times(call("add"))),
+        // (a, b) <-> (min(a, b), b - a), by taking one from each until
+        // either runs out
+        ("diff", sig(nat2(), IType::prod(IType::Nat, int())), [
+            count([
+                prod(unfold(), unfold()),
+                op_s(OP_DIST, 1, 1),
+                sum(vec![], [
+                    op(OP_SWAPP),
+                    op_s(OP_DIST, 1, 1),
+                    sum(op(OP_SWAPP), op(OP_SWAPP)),
+                ].concat()),
+                // (0, b) + (a + 1, 0) + (a + 1, b + 1)
+                assls(),
+                op_s(OP_SWAPS, 1, 1),
+            ].concat()),
+            prod(vec![], sum(
+                [prod(vec![], fold()), op(OP_UNITE)].concat(),
+                [op(OP_SWAPP), op(OP_UNITE)].concat(),
+            )),
+        ].concat()),
+        // (a, b) <-> (a < b, (a, b)), which holds when b - a is
+        // positive
+        ("lt", sig(nat2(), IType::prod(bool(), nat2())), [
+            call("diff"),
+            prod(vec![], [
+                sum(
+                    [unfold(), copy_tag(), prod(fold(), vec![])].concat(),
+                    [op(OP_UNITI), prod(inl(), vec![]), op(OP_SWAPP)].concat(),
+                ),
+                op_s(OP_FACT, 1, 1),
+                op(OP_SWAPP),
+            ].concat()),
+            op(OP_ASSLP),
+            prod(op(OP_SWAPP), vec![]),
+            op(OP_ASSRP),
+            prod(vec![], uncall("diff")),
+        ].concat()),
+        // n <-> -n; flattening int into 1 + nat + nat turns negation
+        // into swapping the last two variants
+        ("neg", sig(int(), int()), [
+            sum(
+                [unfold(), op(OP_UNITI), op(OP_SWAPP)].concat(),
+                [op(OP_UNITI), op(OP_SWAPP)].concat(),
+            ),
+            op_s(OP_FACT, 2, 1),
+            op_s(OP_DIST, 1, 2),
+            sum(vec![], prod(op_s(OP_SWAPS, 1, 1), vec![])),
+            op_s(OP_FACT, 1, 2),
+            op_s(OP_DIST, 2, 1),
+            sum(
+                [op(OP_SWAPP), op(OP_UNITE), fold()].concat(),
+                [op(OP_SWAPP), op(OP_UNITE)].concat(),
+            ),
+        ].concat()),
+        // (x, y) <-> (x + y)(x + y + 1) / 2 + y, by counting back along
+        // the diagonals to (0, 0)
+        ("pair", sig(nat2(), IType::Nat), [
+            count([
+                prod(vec![], unfold()),
+                op(OP_SWAPP),
+                op_s(OP_DIST, 1, 1),
+                sum(
+                    [op(OP_UNITE), unfold(), op(OP_UNITI), op(OP_SWAPP)].concat(),
+                    [op(OP_SWAPP), op(OP_UNITI), op(OP_SWAPP)].concat(),
+                ),
+                // 1 + nat + (nat * nat): (0, 0), (x + 1, 0) or (x, y + 1)
+                op_s(OP_FACT, 2, 1),
+                op_s(OP_DIST, 1, 2),
+                sum(op(OP_UNITE), [
+                    op(OP_SWAPP),
+                    op(OP_UNITE),
+                    // the pair before (x + 1, 0) is (0, x), and before
+                    // (x, y + 1) is (x + 1, y)
+                    sum(op(OP_UNITI), vec![]),
+                    op_s(OP_FACT, 1, 1),
+                    prod(fold(), vec![]),
+                ].concat()),
+                op_s(OP_SWAPS, 1, 1),
+            ].concat()),
+            op(OP_SWAPP),
+            op(OP_UNITE),
+        ].concat()),
+        ("unpair", sig(IType::Nat, nat2()), uncall("pair")),
+    ]
+}
+
+// a + b <-> (a + b) * bool, where the bool is true for the right
+fn copy_tag() -> Vec<Asm> {
+    [
+        sum(
+            [op(OP_UNITI), prod(inl(), vec![]), op(OP_SWAPP)].concat(),
+            [op(OP_UNITI), prod(inr(), vec![]), op(OP_SWAPP)].concat(),
+        ),
+        op_s(OP_FACT, 1, 1),
+    ].concat()
+}
+
+fn sig(input: IType, output: IType) -> Signature {
+    Signature { input, output }
+}
@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::{FromStr, Lines, SplitWhitespace};
+
+use crate::alloc::api::TypeTag;
+use crate::array::{ArraySize, Container, ContainerFromSlice, IndexedContainer,
+                   SliceableContainer, StackContainer};
+use crate::bytecode::{decode_function, verify_function, Continuation, Function, Program};
+use crate::context::{Context, ContextStack};
+use crate::data::{BigNat, Fraction, Inductive, Nat, Negative, Product, Sum, Unit};
+use crate::error::{err_eval, err_parser, RuntimeError};
+use crate::memory::MutatorView;
+use crate::safeptr::{CellPtr, ScopedPtr, UntypedScopedPtr};
+
+/*
+ * Suspending Threads
+ *
+ * An image of a thread holds everything it needs to carry on running:
+ * the function and where it is in it, the context stack, and every
+ * object reachable from either or from the current value. Objects are
+ * numbered in the order they're written, each after the objects it
+ * points to, so they can be allocated again in one pass. Objects which
+ * were shared are still shared once resumed.
+ *
+ * Data in the VM is untyped, so the heap is walked by the type tags in
+ * object headers, and only a heap built with type tags can be
+ * suspended. The default HeapOptions only have tags in debug builds, so
+ * a host which may suspend its threads should build its heap with
+ * HeapOptions::suspendable(). Any heap can be resumed into.
+ *
+ * While a combinator runs, one field of its root object has been moved
+ * out into the current value, and may have been freed since. A root is
+ * only a shell until the combinator ends, so it isn't walked: products
+ * are resumed with both fields on the half held in the context, and
+ * sums keep just their tag.
+ *
+ * Channels, capabilities, and recording or replaying aren't part of the
+ * image; they belong to the host, which sets them up again on the
+ * resumed thread.
+ */
+#[derive(Clone, Debug, PartialEq)]
+enum Object {
+    Unit,
+    Nat(Nat),
+    // big nats and streams
+    Words(Vec<Nat>),
+    Sum(Nat, usize),
+    Product(usize, usize),
+    Negative(usize),
+    Fraction(usize, Nat),
+    List(Vec<usize>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Frame {
+    Nil,
+    First { snd_op_index: ArraySize, snd_val: usize },
+    Second { fst_op_index: ArraySize, fst_val: usize },
+    Left { right_op_index: ArraySize, jump: ArraySize, tag: Nat },
+    Right { left_op_index: ArraySize, jump: ArraySize, tag: Nat },
+    Call { not: bool, ret: ArraySize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    objects: Vec<Object>,
+    function: usize,
+    ip: ArraySize,
+    direction: bool,
+    steps: u64,
+    max_depth: ArraySize,
+    frames: Vec<Frame>,
+    data: usize,
+}
+
+// what a thread is made of, as allocated when resuming
+pub struct Parts<'guard> {
+    pub continuation: ScopedPtr<'guard, Continuation>,
+    pub cxt_stack: ScopedPtr<'guard, ContextStack>,
+    pub data: UntypedScopedPtr<'guard>,
+}
+
+impl Image {
+    pub fn capture(
+        mem: &MutatorView,
+        cont: ScopedPtr<'_, Continuation>,
+        cxt_stack: ScopedPtr<'_, ContextStack>,
+        data: UntypedScopedPtr<'_>,
+        steps: u64,
+    ) -> Result<Image, RuntimeError> {
+        if !mem.has_type_tags() {
+            return Err(untagged());
+        }
+        let mut walk = Walk { mem, seen: HashMap::new(), objects: Vec::new() };
+
+        let function = walk.visit(cont.function(mem).as_untyped(mem))?;
+        let data = walk.visit(data)?;
+
+        let mut frames = Vec::new();
+        for cxt in cxt_stack.contexts(mem)? {
+            frames.push(match cxt {
+                Context::Nil => Frame::Nil,
+                Context::First { snd_op_index, snd_val, .. } => Frame::First {
+                    snd_op_index,
+                    snd_val: walk.visit(snd_val.get(mem))?,
+                },
+                Context::Second { fst_op_index, fst_val, .. } => Frame::Second {
+                    fst_op_index,
+                    fst_val: walk.visit(fst_val.get(mem))?,
+                },
+                Context::Left { right_op_index, jump, root_val } => Frame::Left {
+                    right_op_index,
+                    jump,
+                    tag: walk.shell(root_val.get(mem))?,
+                },
+                Context::Right { left_op_index, jump, root_val } => Frame::Right {
+                    left_op_index,
+                    jump,
+                    tag: walk.shell(root_val.get(mem))?,
+                },
+                Context::Call { not, ret } => Frame::Call { not, ret },
+            });
+        }
+
+        Ok(Image {
+            objects: walk.objects,
+            function,
+            ip: cont.ip(),
+            direction: cont.direction(),
+            steps,
+            max_depth: cxt_stack.max_depth(),
+            frames,
+            data,
+        })
+    }
+
+    // allocates the thread's parts in `mem`, ready to carry on running
+    pub fn restore<'guard>(&self, mem: &'guard MutatorView)
+        -> Result<Parts<'guard>, RuntimeError>
+    {
+        self.check_function()?;
+
+        let mut objects: Vec<UntypedScopedPtr<'guard>> = Vec::with_capacity(self.objects.len());
+        for object in &self.objects {
+            let ptr = match object {
+                Object::Unit => mem.alloc(Unit::new())?.as_untyped(mem),
+                Object::Nat(n) => mem.alloc(*n)?.as_untyped(mem),
+                Object::Words(words) => BigNat::from_slice(mem, words)?.as_untyped(mem),
+                Object::Sum(tag, x) => {
+                    let x = earlier(&objects, *x)?;
+                    mem.alloc(Sum::new(*tag, CellPtr::new_with(x)))?.as_untyped(mem)
+                },
+                Object::Product(a, b) => {
+                    let (a, b) = (earlier(&objects, *a)?, earlier(&objects, *b)?);
+                    mem.alloc(Product::new(CellPtr::new_with(a), CellPtr::new_with(b)))?
+                        .as_untyped(mem)
+                },
+                Object::Negative(x) => {
+                    let x = earlier(&objects, *x)?;
+                    mem.alloc(Negative::new(CellPtr::new_with(x)))?.as_untyped(mem)
+                },
+                Object::Fraction(x, size) => {
+                    let x = earlier(&objects, *x)?;
+                    mem.alloc(Fraction::new(CellPtr::new_with(x), *size))?.as_untyped(mem)
+                },
+                Object::List(items) => {
+                    let list = Inductive::<()>::alloc_with_capacity(mem, items.len() as ArraySize)?;
+                    for item in items {
+                        list.push(mem, CellPtr::new_with(earlier(&objects, *item)?))?;
+                    }
+                    list.as_untyped(mem)
+                },
+            };
+            objects.push(ptr);
+        }
+
+        let function = unsafe { earlier(&objects, self.function)?.cast::<Function>(mem) };
+        verify_function(&decode_function(mem, function)?)?;
+        if self.ip >= function.length() {
+            return Err(err_parser("instruction pointer is outside the function"));
+        }
+
+        let continuation = Continuation::alloc(mem, Program::load(mem, function)?)?;
+        continuation.set_ip(self.ip);
+        if self.direction {
+            continuation.reverse();
+        }
+
+        let data = earlier(&objects, self.data)?;
+        let cxt_stack = ContextStack::alloc_with_capacity(mem, 256)?;
+        cxt_stack.set_max_depth(self.max_depth);
+
+        for frame in &self.frames {
+            // both fields of a product shell are on the half still to
+            // come, and a sum shell's payload is whatever's current, as
+            // each is overwritten when the combinator ends
+            let product = |val: UntypedScopedPtr<'guard>| mem.alloc(Product::new(
+                CellPtr::new_with(val),
+                CellPtr::new_with(val),
+            ));
+            let sum = |tag: Nat| mem.alloc(Sum::new(tag, CellPtr::new_with(data)));
+
+            cxt_stack.push(mem, match *frame {
+                Frame::Nil => Context::Nil,
+                Frame::First { snd_op_index, snd_val } => {
+                    let val = earlier(&objects, snd_val)?;
+                    Context::First {
+                        snd_op_index,
+                        snd_val: CellPtr::new_with(val),
+                        root_val: CellPtr::new_with(product(val)?),
+                    }
+                },
+                Frame::Second { fst_op_index, fst_val } => {
+                    let val = earlier(&objects, fst_val)?;
+                    Context::Second {
+                        fst_op_index,
+                        fst_val: CellPtr::new_with(val),
+                        root_val: CellPtr::new_with(product(val)?),
+                    }
+                },
+                Frame::Left { right_op_index, jump, tag } => Context::Left {
+                    right_op_index,
+                    jump,
+                    root_val: CellPtr::new_with(sum(tag)?),
+                },
+                Frame::Right { left_op_index, jump, tag } => Context::Right {
+                    left_op_index,
+                    jump,
+                    root_val: CellPtr::new_with(sum(tag)?),
+                },
+                Frame::Call { not, ret } => Context::Call { not, ret },
+            })?;
+        }
+
+        Ok(Parts { continuation, cxt_stack, data })
+    }
+
+    pub fn steps(&self) -> u64 { self.steps }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RuntimeError> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Image, RuntimeError> {
+        Image::parse(&fs::read_to_string(path)?)
+    }
+
+    // a header of named fields, then one object and one context per
+    // line, as written by Display
+    pub fn parse(text: &str) -> Result<Image, RuntimeError> {
+        let mut lines = text.lines();
+
+        let function = header(&mut lines, "function")?;
+        let ip = header(&mut lines, "ip")?;
+        let direction = match header::<String>(&mut lines, "direction")?.as_str() {
+            "forward" => false,
+            "backward" => true,
+            _ => return Err(err_parser("expected forward or backward")),
+        };
+        let steps = header(&mut lines, "steps")?;
+        let max_depth = header(&mut lines, "max-depth")?;
+        let data = header(&mut lines, "data")?;
+
+        let objects = (0..header::<usize>(&mut lines, "objects")?)
+            .map(|_| parse_line(&mut lines, parse_object))
+            .collect::<Result<_, _>>()?;
+        let frames = (0..header::<usize>(&mut lines, "contexts")?)
+            .map(|_| parse_line(&mut lines, parse_frame))
+            .collect::<Result<_, _>>()?;
+
+        if lines.any(|line| !line.trim().is_empty()) {
+            return Err(err_parser("trailing lines after contexts"));
+        }
+
+        Ok(Image { objects, function, ip, direction, steps, max_depth, frames, data })
+    }
+
+    // the function must be a list of instructions, for decoding it once
+    // it's been allocated
+    fn check_function(&self) -> Result<(), RuntimeError> {
+        let object = |index: usize| self.objects.get(index)
+            .ok_or(err_parser("no such object"));
+        let is_nat = |index: usize| Ok::<_, RuntimeError>(matches!(object(index)?, Object::Nat(_)));
+
+        let Object::List(instrs) = object(self.function)? else {
+            return Err(err_parser("function isn't a list"));
+        };
+
+        for instr in instrs {
+            let well_formed = match object(*instr)? {
+                Object::Product(op, arg) => is_nat(*op)? && match object(*arg)? {
+                    Object::Sum(0, nat) => is_nat(*nat)?,
+                    Object::Sum(1, pair) => match object(*pair)? {
+                        Object::Product(fst, snd) => is_nat(*fst)? && is_nat(*snd)?,
+                        _ => false,
+                    },
+                    _ => false,
+                },
+                _ => false,
+            };
+
+            if !well_formed {
+                return Err(err_parser("function holds something other than instructions"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "function {}", self.function)?;
+        writeln!(f, "ip {}", self.ip)?;
+        writeln!(f, "direction {}", if self.direction { "backward" } else { "forward" })?;
+        writeln!(f, "steps {}", self.steps)?;
+        writeln!(f, "max-depth {}", self.max_depth)?;
+        writeln!(f, "data {}", self.data)?;
+
+        // prefix notation as in a replay log, with children as indices
+        writeln!(f, "objects {}", self.objects.len())?;
+        for object in &self.objects {
+            match object {
+                Object::Unit => writeln!(f, "u")?,
+                Object::Nat(n) => writeln!(f, "n{}", n)?,
+                Object::Words(words) => writeln!(f, "w{}{}", words.len(), list(words))?,
+                Object::Sum(tag, x) => writeln!(f, "s{} {}", tag, x)?,
+                Object::Product(a, b) => writeln!(f, "p {} {}", a, b)?,
+                Object::Negative(x) => writeln!(f, "- {}", x)?,
+                Object::Fraction(x, size) => writeln!(f, "f{} {}", size, x)?,
+                Object::List(items) => writeln!(f, "l{}{}", items.len(), list(items))?,
+            }
+        }
+
+        writeln!(f, "contexts {}", self.frames.len())?;
+        for frame in &self.frames {
+            match frame {
+                Frame::Nil => writeln!(f, "nil")?,
+                Frame::First { snd_op_index, snd_val } => {
+                    writeln!(f, "first {} {}", snd_op_index, snd_val)?
+                },
+                Frame::Second { fst_op_index, fst_val } => {
+                    writeln!(f, "second {} {}", fst_op_index, fst_val)?
+                },
+                Frame::Left { right_op_index, jump, tag } => {
+                    writeln!(f, "left {} {} {}", right_op_index, jump, tag)?
+                },
+                Frame::Right { left_op_index, jump, tag } => {
+                    writeln!(f, "right {} {} {}", left_op_index, jump, tag)?
+                },
+                Frame::Call { not, ret } => {
+                    writeln!(f, "{} {}", if *not { "uncall" } else { "call" }, ret)?
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/* Heap walk */
+struct Walk<'a, 'memory> {
+    mem: &'a MutatorView<'memory>,
+    // index of each object written so far, by address
+    seen: HashMap<usize, usize>,
+    objects: Vec<Object>,
+}
+
+impl Walk<'_, '_> {
+    fn visit(&mut self, ptr: UntypedScopedPtr<'_>) -> Result<usize, RuntimeError> {
+        let mem = self.mem;
+        let addr = ptr.as_rawptr(mem).as_word();
+        if let Some(index) = self.seen.get(&addr) {
+            return Ok(*index);
+        }
+
+        let object = match self.tag(ptr)? {
+            TypeTag::Unit => Object::Unit,
+            TypeTag::Nat => Object::Nat(*unsafe { ptr.cast::<Nat>(mem) }),
+            TypeTag::Words => {
+                let words = unsafe { ptr.cast::<BigNat>(mem) };
+                Object::Words(words.access_slice(mem, |words| words.to_vec()))
+            },
+            TypeTag::Sum => {
+                let sum = unsafe { ptr.cast::<Sum<()>>(mem) };
+                Object::Sum(sum.tag(), self.visit(sum.data(mem))?)
+            },
+            TypeTag::Product => {
+                let prod = unsafe { ptr.cast::<Product<(), ()>>(mem) };
+                Object::Product(self.visit(prod.fst(mem))?, self.visit(prod.snd(mem))?)
+            },
+            TypeTag::Negative => {
+                let neg = unsafe { ptr.cast::<Negative<()>>(mem) };
+                Object::Negative(self.visit(neg.data(mem))?)
+            },
+            TypeTag::Fraction => {
+                let frac = unsafe { ptr.cast::<Fraction>(mem) };
+                Object::Fraction(self.visit(frac.ptr().get(mem))?, frac.size())
+            },
+            TypeTag::Array => {
+                let list = unsafe { ptr.cast::<Inductive<()>>(mem) };
+                let items = (0..list.length())
+                    .map(|index| self.visit(list.get(mem, index)?.get(mem)))
+                    .collect::<Result<_, _>>()?;
+                Object::List(items)
+            },
+            TypeTag::Untagged | TypeTag::Context => {
+                return Err(err_eval("value holds an object which can't be suspended"));
+            },
+        };
+
+        self.objects.push(object);
+        self.seen.insert(addr, self.objects.len() - 1);
+        Ok(self.objects.len() - 1)
+    }
+
+    // the tag of a combinator's root, without going into its fields
+    fn shell(&self, sum: ScopedPtr<'_, Sum<()>>) -> Result<Nat, RuntimeError> {
+        match self.tag(sum.as_untyped(self.mem))? {
+            TypeTag::Sum => Ok(sum.tag()),
+            _ => Err(err_eval("sum combinator's root isn't a sum")),
+        }
+    }
+
+    fn tag(&self, ptr: UntypedScopedPtr<'_>) -> Result<TypeTag, RuntimeError> {
+        self.mem.check_access(ptr)?;
+        self.mem.type_tag(ptr).ok_or_else(untagged)
+    }
+}
+
+/* Helper functions */
+fn untagged() -> RuntimeError {
+    err_eval("only a heap with type tags can be suspended")
+}
+
+fn earlier<'guard>(objects: &[UntypedScopedPtr<'guard>], index: usize)
+    -> Result<UntypedScopedPtr<'guard>, RuntimeError>
+{
+    objects.get(index).copied()
+        .ok_or(err_parser("object refers to one which isn't before it"))
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|item| format!(" {}", item)).collect()
+}
+
+fn header<T: FromStr>(lines: &mut Lines, key: &str) -> Result<T, RuntimeError> {
+    let line = lines.next().ok_or(err_parser(&format!("expected {}", key)))?;
+
+    match line.split_once(' ') {
+        Some((name, value)) if name == key => value.trim().parse()
+            .map_err(|_| err_parser(&format!("bad value for {}", key))),
+        _ => Err(err_parser(&format!("expected {}", key))),
+    }
+}
+
+fn parse_line<T>(
+    lines: &mut Lines,
+    parse: fn(&str, &mut SplitWhitespace) -> Result<T, RuntimeError>,
+) -> Result<T, RuntimeError> {
+    let line = lines.next().ok_or(err_parser("image ends early"))?;
+    let mut tokens = line.split_whitespace();
+    let head = tokens.next().ok_or(err_parser("empty line in image"))?;
+    let parsed = parse(head, &mut tokens)?;
+
+    if tokens.next().is_some() {
+        return Err(err_parser("trailing tokens on line"));
+    }
+    Ok(parsed)
+}
+
+fn parse_object(token: &str, tokens: &mut SplitWhitespace) -> Result<Object, RuntimeError> {
+    let (head, rest) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+    let count = || rest.parse::<Nat>()
+        .map_err(|_| err_parser("expected a number after object tag"));
+
+    Ok(match head {
+        "u" => Object::Unit,
+        "n" => Object::Nat(count()?),
+        "w" => Object::Words((0..count()?)
+            .map(|_| number(tokens))
+            .collect::<Result<_, _>>()?),
+        "s" => Object::Sum(count()?, number(tokens)?),
+        "p" => Object::Product(number(tokens)?, number(tokens)?),
+        "-" => Object::Negative(number(tokens)?),
+        "f" => Object::Fraction(number(tokens)?, count()?),
+        "l" => Object::List((0..count()?)
+            .map(|_| number(tokens))
+            .collect::<Result<_, _>>()?),
+        _ => return Err(err_parser("unknown object tag")),
+    })
+}
+
+fn parse_frame(head: &str, tokens: &mut SplitWhitespace) -> Result<Frame, RuntimeError> {
+    Ok(match head {
+        "nil" => Frame::Nil,
+        "first" => Frame::First {
+            snd_op_index: number(tokens)?,
+            snd_val: number(tokens)?,
+        },
+        "second" => Frame::Second {
+            fst_op_index: number(tokens)?,
+            fst_val: number(tokens)?,
+        },
+        "left" => Frame::Left {
+            right_op_index: number(tokens)?,
+            jump: number(tokens)?,
+            tag: number(tokens)?,
+        },
+        "right" => Frame::Right {
+            left_op_index: number(tokens)?,
+            jump: number(tokens)?,
+            tag: number(tokens)?,
+        },
+        "call" => Frame::Call { not: false, ret: number(tokens)? },
+        "uncall" => Frame::Call { not: true, ret: number(tokens)? },
+        _ => return Err(err_parser("unknown context")),
+    })
+}
+
+fn number<T: FromStr>(tokens: &mut SplitWhitespace) -> Result<T, RuntimeError> {
+    tokens.next()
+        .and_then(|token| token.parse().ok())
+        .ok_or(err_parser("expected a number"))
+}
@@ -1,11 +1,12 @@
 use crate::alloc::api::AllocObject;
 
 /* Type Enum */
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum IType {
     Zero,
     Unit,
     Nat,
+    BigNat,
     Frac(Box<IType>),
     Neg(Box<IType>),
     Sum {
@@ -16,6 +17,33 @@ pub enum IType {
         fst: Box<IType>,
         snd: Box<IType>,
     },
+    Ind(Box<IType>),
 }
 
 impl AllocObject for IType {}
+
+impl IType {
+    pub fn sum(left: IType, right: IType) -> IType {
+        IType::Sum { left: Box::new(left), right: Box::new(right) }
+    }
+
+    pub fn prod(fst: IType, snd: IType) -> IType {
+        IType::Prod { fst: Box::new(fst), snd: Box::new(snd) }
+    }
+
+    pub fn frac(inner: IType) -> IType { IType::Frac(Box::new(inner)) }
+    pub fn negative(inner: IType) -> IType { IType::Neg(Box::new(inner)) }
+    pub fn ind(inner: IType) -> IType { IType::Ind(Box::new(inner)) }
+
+    // a sum is one tagged object holding its left (tag 0) or right
+    // (tag 1) side, so a sum of sums nests, as ZEROI builds them. S-type
+    // instructions over more than two variants make a single flat sum,
+    // which has no IType of its own.
+    pub fn variant(&self, tag: u32) -> Option<&IType> {
+        match (self, tag) {
+            (IType::Sum { left, .. }, 0) => Some(left),
+            (IType::Sum { right, .. }, 1) => Some(right),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::alloc::api::AllocObject;
+use crate::array::{ArraySize, Container, IndexedContainer, SliceableContainer, StackContainer};
+use crate::data::*;
+use crate::error::{RuntimeError, ErrorKind};
+use crate::memory::{MutatorScope, MutatorView};
+use crate::safeptr::{CellPtr, ScopedPtr, UntypedScopedPtr};
+use crate::types::IType;
+
+/*
+ * Type-directed operations over heap values
+ *
+ * Values carry no type information at runtime, so each of these walks
+ * the value graph alongside its IType.
+ */
+pub fn deep_copy<'guard>(
+    mem: &'guard MutatorView,
+    ty: &IType,
+    val: UntypedScopedPtr<'guard>,
+) -> Result<UntypedScopedPtr<'guard>, RuntimeError> {
+    match ty {
+        IType::Zero => Err(RuntimeError::new(ErrorKind::TypeError)),
+        IType::Unit => Ok(mem.alloc(Unit::new())?.as_untyped(mem)),
+        IType::Nat => {
+            let nat = unsafe { val.cast::<Nat>(mem) };
+            Ok(mem.alloc(*nat)?.as_untyped(mem))
+        },
+        IType::BigNat => {
+            let nat = unsafe { val.cast::<BigNat>(mem) };
+            Ok(BigNat::alloc_clone(mem, nat)?.as_untyped(mem))
+        },
+        IType::Frac(inner) => {
+            let frac = unsafe { val.cast::<Fraction>(mem) };
+            let copy = deep_copy(mem, inner, frac.ptr().get(mem))?;
+
+            Ok(mem.alloc(Fraction::new(
+                CellPtr::new_with(copy),
+                frac.size()
+            ))?.as_untyped(mem))
+        },
+        IType::Neg(inner) => {
+            let neg = unsafe { val.cast::<Negative<()>>(mem) };
+            let copy = deep_copy(mem, inner, neg.data(mem))?;
+
+            Ok(mem.alloc(Negative::new(CellPtr::new_with(copy)))?
+                .as_untyped(mem))
+        },
+        IType::Sum { .. } => {
+            let sum = unsafe { val.cast::<Sum<()>>(mem) };
+            let copy = deep_copy(mem, variant(ty, sum.tag())?, sum.data(mem))?;
+
+            Ok(mem.alloc(Sum::new(sum.tag(), CellPtr::new_with(copy)))?
+                .as_untyped(mem))
+        },
+        IType::Prod { fst, snd } => {
+            let prod = unsafe { val.cast::<Product<(), ()>>(mem) };
+            let fst_copy = deep_copy(mem, fst, prod.fst(mem))?;
+            let snd_copy = deep_copy(mem, snd, prod.snd(mem))?;
+
+            Ok(mem.alloc(Product::new(
+                CellPtr::new_with(fst_copy),
+                CellPtr::new_with(snd_copy)
+            ))?.as_untyped(mem))
+        },
+        IType::Ind(inner) => {
+            let ind = unsafe { val.cast::<Inductive<()>>(mem) };
+            let copy = Inductive::<()>::alloc(mem)?;
+
+            for index in 0..ind.length() {
+                let item = ind.get(mem, index)?.get(mem);
+                copy.push(mem, CellPtr::new_with(deep_copy(mem, inner, item)?))?;
+            }
+
+            Ok(copy.as_untyped(mem))
+        },
+    }
+}
+
+pub fn structural_eq<'guard>(
+    guard: &'guard dyn MutatorScope,
+    ty: &IType,
+    lhs: UntypedScopedPtr<'guard>,
+    rhs: UntypedScopedPtr<'guard>,
+) -> Result<bool, RuntimeError> {
+    match ty {
+        IType::Zero => Err(RuntimeError::new(ErrorKind::TypeError)),
+        IType::Unit => Ok(true),
+        IType::Nat => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<Nat>(guard), rhs.cast::<Nat>(guard))
+            };
+            Ok(*lhs == *rhs)
+        },
+        IType::BigNat => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<BigNat>(guard), rhs.cast::<BigNat>(guard))
+            };
+
+            // bignats are normalized, so equal values have equal limbs
+            if lhs.length() != rhs.length() {
+                return Ok(false);
+            }
+
+            for index in 0..lhs.length() {
+                if lhs.get(guard, index)? != rhs.get(guard, index)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        },
+        IType::Frac(inner) => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<Fraction>(guard), rhs.cast::<Fraction>(guard))
+            };
+            structural_eq(guard, inner, lhs.ptr().get(guard), rhs.ptr().get(guard))
+        },
+        IType::Neg(inner) => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<Negative<()>>(guard), rhs.cast::<Negative<()>>(guard))
+            };
+            structural_eq(guard, inner, lhs.data(guard), rhs.data(guard))
+        },
+        IType::Sum { .. } => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<Sum<()>>(guard), rhs.cast::<Sum<()>>(guard))
+            };
+
+            if lhs.tag() != rhs.tag() {
+                Ok(false)
+            } else {
+                structural_eq(
+                    guard,
+                    variant(ty, lhs.tag())?,
+                    lhs.data(guard),
+                    rhs.data(guard)
+                )
+            }
+        },
+        IType::Prod { fst, snd } => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<Product<(), ()>>(guard), rhs.cast::<Product<(), ()>>(guard))
+            };
+
+            Ok(structural_eq(guard, fst, lhs.fst(guard), rhs.fst(guard))?
+               && structural_eq(guard, snd, lhs.snd(guard), rhs.snd(guard))?)
+        },
+        IType::Ind(inner) => {
+            let (lhs, rhs) = unsafe {
+                (lhs.cast::<Inductive<()>>(guard), rhs.cast::<Inductive<()>>(guard))
+            };
+
+            if lhs.length() != rhs.length() {
+                return Ok(false);
+            }
+
+            for index in 0..lhs.length() {
+                let lhs_item = lhs.get(guard, index)?.get(guard);
+                let rhs_item = rhs.get(guard, index)?.get(guard);
+
+                if !structural_eq(guard, inner, lhs_item, rhs_item)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        },
+    }
+}
+
+pub fn structural_hash<'guard>(
+    guard: &'guard dyn MutatorScope,
+    ty: &IType,
+    val: UntypedScopedPtr<'guard>,
+) -> Result<u64, RuntimeError> {
+    let mut hasher = DefaultHasher::new();
+    hash_into(guard, ty, val, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+fn hash_into<'guard, H: Hasher>(
+    guard: &'guard dyn MutatorScope,
+    ty: &IType,
+    val: UntypedScopedPtr<'guard>,
+    state: &mut H,
+) -> Result<(), RuntimeError> {
+    match ty {
+        IType::Zero => return Err(RuntimeError::new(ErrorKind::TypeError)),
+        IType::Unit => {},
+        IType::Nat => {
+            let nat = unsafe { val.cast::<Nat>(guard) };
+            nat.hash(state);
+        },
+        IType::BigNat => {
+            let nat = unsafe { val.cast::<BigNat>(guard) };
+            nat.access_slice(guard, |limbs| limbs.hash(state));
+        },
+        IType::Frac(inner) => {
+            let frac = unsafe { val.cast::<Fraction>(guard) };
+            hash_into(guard, inner, frac.ptr().get(guard), state)?;
+        },
+        IType::Neg(inner) => {
+            let neg = unsafe { val.cast::<Negative<()>>(guard) };
+            hash_into(guard, inner, neg.data(guard), state)?;
+        },
+        IType::Sum { .. } => {
+            let sum = unsafe { val.cast::<Sum<()>>(guard) };
+            sum.tag().hash(state);
+            hash_into(guard, variant(ty, sum.tag())?, sum.data(guard), state)?;
+        },
+        IType::Prod { fst, snd } => {
+            let prod = unsafe { val.cast::<Product<(), ()>>(guard) };
+            hash_into(guard, fst, prod.fst(guard), state)?;
+            hash_into(guard, snd, prod.snd(guard), state)?;
+        },
+        IType::Ind(inner) => {
+            let ind = unsafe { val.cast::<Inductive<()>>(guard) };
+            ind.length().hash(state);
+
+            for index in 0..ind.length() {
+                hash_into(guard, inner, ind.get(guard, index)?.get(guard), state)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+pub fn free_tree<'guard>(
+    mem: &'guard MutatorView,
+    ty: &IType,
+    val: UntypedScopedPtr<'guard>,
+) -> Result<(), RuntimeError> {
+    match ty {
+        IType::Zero => Err(RuntimeError::new(ErrorKind::TypeError)),
+        IType::Unit => mem.dealloc(unsafe { val.cast::<Unit>(mem) }),
+        IType::Nat => mem.dealloc(unsafe { val.cast::<Nat>(mem) }),
+        IType::BigNat => {
+            let nat = unsafe { val.cast::<BigNat>(mem) };
+            nat.dealloc_data(mem)?;
+            mem.dealloc(nat)
+        },
+        IType::Frac(inner) => {
+            let frac = unsafe { val.cast::<Fraction>(mem) };
+            free_tree(mem, inner, frac.ptr().get(mem))?;
+            mem.dealloc(frac)
+        },
+        IType::Neg(inner) => {
+            let neg = unsafe { val.cast::<Negative<()>>(mem) };
+            free_tree(mem, inner, neg.data(mem))?;
+            mem.dealloc(neg)
+        },
+        IType::Sum { .. } => {
+            let sum = unsafe { val.cast::<Sum<()>>(mem) };
+            free_tree(mem, variant(ty, sum.tag())?, sum.data(mem))?;
+            mem.dealloc(sum)
+        },
+        IType::Prod { fst, snd } => {
+            let prod = unsafe { val.cast::<Product<(), ()>>(mem) };
+            free_tree(mem, fst, prod.fst(mem))?;
+            free_tree(mem, snd, prod.snd(mem))?;
+            mem.dealloc(prod)
+        },
+        IType::Ind(inner) => {
+            let ind = unsafe { val.cast::<Inductive<()>>(mem) };
+
+            for index in 0..ind.length() {
+                free_tree(mem, inner, ind.get(mem, index)?.get(mem))?;
+            }
+
+            ind.dealloc_data(mem)?;
+            mem.dealloc(ind)
+        },
+    }
+}
+
+/*
+ * Host values
+ *
+ * A value owned by the host rather than the heap, for passing data in
+ * and out of threads, channels and compiled code.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Unit,
+    Nat(u32),
+    BigNat(Vec<u32>),
+    Sum(u32, Box<Value>),
+    Product(Box<Value>, Box<Value>),
+    Negative(Box<Value>),
+    Inductive(Vec<Value>),
+}
+
+impl Value {
+    // reads a heap value of the given type, checking every object it
+    // reads against its type tag if the heap keeps them
+    pub fn from_heap<'guard>(
+        mem: &'guard MutatorView,
+        ty: &IType,
+        val: UntypedScopedPtr<'guard>,
+    ) -> Result<Value, RuntimeError> {
+        match ty {
+            IType::Unit => Ok(Value::Unit),
+            IType::Nat => Ok(Value::Nat(*load::<Nat>(mem, val)?)),
+            IType::BigNat => {
+                let nat = load::<BigNat>(mem, val)?;
+                let mut limbs = Vec::with_capacity(nat.length() as usize);
+
+                for index in 0..nat.length() {
+                    limbs.push(nat.get(mem, index)?);
+                }
+
+                Ok(Value::BigNat(limbs))
+            },
+            IType::Sum { .. } => {
+                let sum = load::<Sum<()>>(mem, val)?;
+
+                Ok(Value::Sum(
+                    sum.tag(),
+                    Box::new(Value::from_heap(mem, variant(ty, sum.tag())?, sum.data(mem))?)
+                ))
+            },
+            IType::Prod { fst, snd } => {
+                let prod = load::<Product<(), ()>>(mem, val)?;
+
+                Ok(Value::Product(
+                    Box::new(Value::from_heap(mem, fst, prod.fst(mem))?),
+                    Box::new(Value::from_heap(mem, snd, prod.snd(mem))?),
+                ))
+            },
+            IType::Neg(inner) => {
+                let neg = load::<Negative<()>>(mem, val)?;
+                Ok(Value::Negative(Box::new(
+                    Value::from_heap(mem, inner, neg.data(mem))?
+                )))
+            },
+            IType::Ind(inner) => {
+                let ind = load::<Inductive<()>>(mem, val)?;
+                let mut items = Vec::with_capacity(ind.length() as usize);
+
+                for index in 0..ind.length() {
+                    let item = ind.get(mem, index)?.get(mem);
+                    items.push(Value::from_heap(mem, inner, item)?);
+                }
+
+                Ok(Value::Inductive(items))
+            },
+            IType::Zero | IType::Frac(_) => Err(RuntimeError::new(ErrorKind::TypeError)),
+        }
+    }
+
+    // checks every object from_heap would read, without building the
+    // value; says nothing unless the heap was built with type tags
+    pub fn check_heap<'guard>(
+        mem: &'guard MutatorView,
+        ty: &IType,
+        val: UntypedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        match ty {
+            IType::Unit => Ok(()),
+            IType::Nat => load::<Nat>(mem, val).map(|_| ()),
+            IType::BigNat => load::<BigNat>(mem, val).map(|_| ()),
+            IType::Sum { .. } => {
+                let sum = load::<Sum<()>>(mem, val)?;
+                Value::check_heap(mem, variant(ty, sum.tag())?, sum.data(mem))
+            },
+            IType::Prod { fst, snd } => {
+                let prod = load::<Product<(), ()>>(mem, val)?;
+
+                Value::check_heap(mem, fst, prod.fst(mem))?;
+                Value::check_heap(mem, snd, prod.snd(mem))
+            },
+            IType::Neg(inner) => {
+                let neg = load::<Negative<()>>(mem, val)?;
+                Value::check_heap(mem, inner, neg.data(mem))
+            },
+            IType::Ind(inner) => {
+                let ind = load::<Inductive<()>>(mem, val)?;
+
+                for index in 0..ind.length() {
+                    Value::check_heap(mem, inner, ind.get(mem, index)?.get(mem))?;
+                }
+                Ok(())
+            },
+            IType::Zero | IType::Frac(_) => Err(RuntimeError::new(ErrorKind::TypeError)),
+        }
+    }
+
+    pub fn alloc<'guard>(&self, mem: &'guard MutatorView)
+        -> Result<UntypedScopedPtr<'guard>, RuntimeError>
+    {
+        Ok(match self {
+            Value::Unit => mem.alloc(Unit::new())?.as_untyped(mem),
+            Value::Nat(nat) => mem.alloc(*nat)?.as_untyped(mem),
+            Value::BigNat(limbs) => {
+                let nat = BigNat::alloc_with_capacity(mem, limbs.len() as ArraySize)?;
+                for limb in limbs {
+                    nat.push(mem, *limb)?;
+                }
+                nat.as_untyped(mem)
+            },
+            Value::Sum(tag, inner) => {
+                let inner = inner.alloc(mem)?;
+                mem.alloc(Sum::new(*tag, CellPtr::new_with(inner)))?.as_untyped(mem)
+            },
+            Value::Product(fst, snd) => {
+                let fst = fst.alloc(mem)?;
+                let snd = snd.alloc(mem)?;
+                mem.alloc(Product::new(
+                    CellPtr::new_with(fst),
+                    CellPtr::new_with(snd),
+                ))?.as_untyped(mem)
+            },
+            Value::Negative(inner) => {
+                let inner = inner.alloc(mem)?;
+                mem.alloc(Negative::new(CellPtr::new_with(inner)))?.as_untyped(mem)
+            },
+            Value::Inductive(items) => {
+                let ind = Inductive::<()>::alloc_with_capacity(mem, items.len() as ArraySize)?;
+                for item in items {
+                    ind.push(mem, CellPtr::new_with(item.alloc(mem)?))?;
+                }
+                ind.as_untyped(mem)
+            },
+        })
+    }
+
+    // whether a heap value holds exactly this, found by following the
+    // value itself rather than a type
+    pub fn matches_heap<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        val: UntypedScopedPtr<'guard>,
+    ) -> Result<bool, RuntimeError> {
+        Ok(match self {
+            Value::Unit => true,
+            Value::Nat(n) => *unsafe { val.cast::<Nat>(guard) } == *n,
+            Value::BigNat(limbs) => {
+                let nat = unsafe { val.cast::<BigNat>(guard) };
+                if nat.length() as usize != limbs.len() {
+                    return Ok(false);
+                }
+
+                for (index, limb) in limbs.iter().enumerate() {
+                    if nat.get(guard, index as ArraySize)? != *limb {
+                        return Ok(false);
+                    }
+                }
+                true
+            },
+            Value::Sum(tag, inner) => {
+                let sum = unsafe { val.cast::<Sum<()>>(guard) };
+                sum.tag() == *tag && inner.matches_heap(guard, sum.data(guard))?
+            },
+            Value::Product(fst, snd) => {
+                let prod = unsafe { val.cast::<Product<(), ()>>(guard) };
+                fst.matches_heap(guard, prod.fst(guard))?
+                    && snd.matches_heap(guard, prod.snd(guard))?
+            },
+            Value::Negative(inner) => {
+                let neg = unsafe { val.cast::<Negative<()>>(guard) };
+                inner.matches_heap(guard, neg.data(guard))?
+            },
+            Value::Inductive(items) => {
+                let ind = unsafe { val.cast::<Inductive<()>>(guard) };
+                if ind.length() as usize != items.len() {
+                    return Ok(false);
+                }
+
+                for (index, item) in items.iter().enumerate() {
+                    let val = ind.get(guard, index as ArraySize)?.get(guard);
+                    if !item.matches_heap(guard, val)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            },
+        })
+    }
+
+    // frees a heap value which matches this one
+    pub fn free_heap<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        val: UntypedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        match self {
+            Value::Unit => mem.dealloc(unsafe { val.cast::<Unit>(mem) }),
+            Value::Nat(_) => mem.dealloc(unsafe { val.cast::<Nat>(mem) }),
+            Value::BigNat(_) => {
+                let nat = unsafe { val.cast::<BigNat>(mem) };
+                nat.dealloc_data(mem)?;
+                mem.dealloc(nat)
+            },
+            Value::Sum(_, inner) => {
+                let sum = unsafe { val.cast::<Sum<()>>(mem) };
+                inner.free_heap(mem, sum.data(mem))?;
+                mem.dealloc(sum)
+            },
+            Value::Product(fst, snd) => {
+                let prod = unsafe { val.cast::<Product<(), ()>>(mem) };
+                fst.free_heap(mem, prod.fst(mem))?;
+                snd.free_heap(mem, prod.snd(mem))?;
+                mem.dealloc(prod)
+            },
+            Value::Negative(inner) => {
+                let neg = unsafe { val.cast::<Negative<()>>(mem) };
+                inner.free_heap(mem, neg.data(mem))?;
+                mem.dealloc(neg)
+            },
+            Value::Inductive(items) => {
+                let ind = unsafe { val.cast::<Inductive<()>>(mem) };
+
+                for (index, item) in items.iter().enumerate() {
+                    item.free_heap(mem, ind.get(mem, index as ArraySize)?.get(mem))?;
+                }
+
+                ind.dealloc_data(mem)?;
+                mem.dealloc(ind)
+            },
+        }
+    }
+}
+
+/* Helper functions */
+fn variant(ty: &IType, tag: Nat) -> Result<&IType, RuntimeError> {
+    ty.variant(tag).ok_or(RuntimeError::new(ErrorKind::TypeError))
+}
+
+// casts a value which might not really be a T, checking it's live and
+// tagged as one first
+fn load<'guard, T>(mem: &'guard MutatorView, val: UntypedScopedPtr<'guard>)
+    -> Result<ScopedPtr<'guard, T>, RuntimeError>
+    where T: AllocObject
+{
+    mem.check_access(val)?;
+    mem.check_tag::<_, T>(val)?;
+    Ok(unsafe { val.cast::<T>(mem) })
+}
@@ -1,13 +1,20 @@
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+
 use crate::alloc::api::AllocObject;
-use crate::array::{Array, ArraySize, StackContainer};
+use crate::value::Value;
+use crate::array::ArraySize;
 use crate::bytecode::*;
+use crate::channel::{Capabilities, Channel, Channels, Event, Opener, Replay, ReplayLog, CHANNEL_DYNAMIC};
 use crate::constants::*;
 use crate::context::{Context, ContextStack};
 use crate::data::*;
 use crate::error::{RuntimeError, ErrorKind};
-use crate::memory::{MutatorView, MutatorScope};
+use crate::memory::{HeapSite, MutatorView, MutatorScope};
 use crate::op::*;
 use crate::safeptr::*;
+use crate::suspend::{Image, Parts};
+use crate::value::free_tree;
 
 #[derive(PartialEq)]
 pub enum EvalStatus {
@@ -16,10 +23,34 @@ pub enum EvalStatus {
     Err,
 }
 
+// where execution goes after an instruction
+#[derive(Copy, Clone, PartialEq)]
+enum Flow {
+    Next,
+    // the instruction turned execution around
+    Reversed,
+    // the outermost END was reached
+    Done,
+}
+
 pub struct Thread {
     continuation: CellPtr<Continuation>,
     cxt_stack: CellPtr<ContextStack>,
     data: UntypedCellPtr,
+    count_allocs: Cell<bool>,
+    alloc_counts: [Cell<usize>; OPCODE_COUNT],
+    channels: RefCell<Channels>,
+    // None leaves every channel open to the thread
+    capabilities: RefCell<Option<Capabilities>>,
+    interaction: RefCell<Interaction>,
+    steps: Cell<u64>,
+}
+
+// where READ and WRITE get and check their values
+enum Interaction {
+    Live,
+    Recording(ReplayLog),
+    Replaying(Replay),
 }
 
 impl AllocObject for Thread {}
@@ -31,14 +62,52 @@ impl Thread {
     )
         -> Result<ScopedPtr<'guard, Thread>, RuntimeError>
     {
-        let cont = Continuation::alloc(mem, data.fst(mem))?;
-        let cxts = Array::<Context>::alloc_with_capacity(mem, 256)?;
+        let program = Program::load(mem, data.fst(mem))?;
+        Thread::alloc_with_program(mem, program, data.snd(mem))
+    }
+
+    // starts a thread on a program which is already loaded, so threads
+    // running the same function share its streams
+    pub fn alloc_with_program<'guard>(
+        mem: &'guard MutatorView,
+        program: ScopedPtr<'guard, Program>,
+        data: UntypedScopedPtr<'guard>,
+    )
+        -> Result<ScopedPtr<'guard, Thread>, RuntimeError>
+    {
+        let cont = Continuation::alloc(mem, program)?;
+        let cxts = ContextStack::alloc_with_capacity(mem, 256)?;
         cxts.push(mem, Context::Nil)?;
 
+        Thread::alloc_from(mem, Parts {
+            continuation: cont,
+            cxt_stack: cxts,
+            data,
+        }, 0)
+    }
+
+    // picks up a thread written out by suspend, in a fresh Memory or
+    // the same one, with no channels attached
+    pub fn resume<'guard>(mem: &'guard MutatorView, path: impl AsRef<Path>)
+        -> Result<ScopedPtr<'guard, Thread>, RuntimeError>
+    {
+        let image = Image::load(path)?;
+        Thread::alloc_from(mem, image.restore(mem)?, image.steps())
+    }
+
+    fn alloc_from<'guard>(mem: &'guard MutatorView, parts: Parts<'guard>, steps: u64)
+        -> Result<ScopedPtr<'guard, Thread>, RuntimeError>
+    {
         mem.alloc(Thread {
-            continuation: CellPtr::new_with(cont),
-            cxt_stack: CellPtr::new_with(cxts),
-            data: CellPtr::new_with(data.snd(mem)),
+            continuation: CellPtr::new_with(parts.continuation),
+            cxt_stack: CellPtr::new_with(parts.cxt_stack),
+            data: CellPtr::new_with(parts.data),
+            count_allocs: Cell::new(false),
+            alloc_counts: std::array::from_fn(|_| Cell::new(0)),
+            channels: RefCell::new(Channels::new()),
+            capabilities: RefCell::new(None),
+            interaction: RefCell::new(Interaction::Live),
+            steps: Cell::new(steps),
         })
     }
 
@@ -69,8 +138,142 @@ impl Thread {
         }
     }
 
-    fn eval_context<'guard>(&self, mem: &'guard MutatorView)
-        -> Result<(), RuntimeError>
+    fn call<const BACKWARD: bool>(
+        &self,
+        mem: &MutatorView,
+        not: bool,
+    ) -> Result<(), RuntimeError> {
+        let cont = self.continuation.get(mem);
+        let cxt_stack = self.cxt_stack.get(mem);
+        let LoadedArg { fst: start, snd: end } = cont.fetch_arg(mem)?;
+
+        // a call right before END would only return to another return,
+        // so it takes over the caller's context instead of adding one;
+        // both inversions still happen on the way out
+        let is_tail = cont.peek_op::<BACKWARD>(mem)
+            .is_some_and(|op| (op & OP_MASK) as u8 == OP_END);
+        let new_cxt = match cxt_stack.top(mem)? {
+            Context::Call { not: outer, ret } if is_tail => {
+                cxt_stack.pop(mem)?;
+                Context::Call { not: outer ^ not, ret }
+            },
+            _ => Context::Call { not, ret: cont.ip() },
+        };
+
+        self.call_func(mem, start, end, not);
+        cxt_stack.push(mem, new_cxt)
+    }
+
+    // ?a <-> (?b * ?a), with ?b coming from the channel
+    fn read(
+        &self,
+        mem: &MutatorView,
+        imm: Nat,
+        undo: bool,
+    ) -> Result<(), RuntimeError> {
+        let step = self.steps.get();
+        let (id, arg) = resolve_channel(mem, imm, self.data.get(mem))?;
+        self.check_access(id, true, undo)?;
+        let value = match &mut *self.interaction.borrow_mut() {
+            Interaction::Replaying(replay) => replay.next(step, id, true, undo)?.clone(),
+            Interaction::Recording(log) => {
+                let value = self.read_channel(mem, id, arg, undo)?;
+                log.push(Event { step, channel: id, read: true, undo, value: value.clone() });
+                value
+            },
+            Interaction::Live => self.read_channel(mem, id, arg, undo)?,
+        };
+
+        let prod = mem.alloc(Product::new(
+            CellPtr::new_with(value.alloc(mem)?),
+            CellPtr::new_with(self.data.get(mem)),
+        ))?;
+
+        self.data.set(prod.as_untyped(mem));
+        Ok(())
+    }
+
+    // (?b * ?a) <-> ?a, handing ?b over to the channel
+    fn write(
+        &self,
+        mem: &MutatorView,
+        imm: Nat,
+        undo: bool,
+    ) -> Result<(), RuntimeError> {
+        let step = self.steps.get();
+        let prod = checked_cast::<_, Product<(), ()>>(mem, self.data.get(mem))?;
+        let (id, arg) = resolve_channel(mem, imm, prod.snd(mem))?;
+        self.check_access(id, false, undo)?;
+
+        match &mut *self.interaction.borrow_mut() {
+            Interaction::Replaying(replay) => {
+                let expected = replay.next(step, id, false, undo)?;
+                if !expected.matches_heap(mem, prod.fst(mem))? {
+                    return Err(RuntimeError::new(ErrorKind::ReplayDiverged(step)));
+                }
+                expected.free_heap(mem, prod.fst(mem))?;
+            },
+            Interaction::Recording(log) => {
+                let value = self.write_channel(mem, id, prod.fst(mem), arg, undo)?;
+                log.push(Event { step, channel: id, read: false, undo, value });
+            },
+            Interaction::Live => { self.write_channel(mem, id, prod.fst(mem), arg, undo)?; },
+        }
+
+        self.data.set(prod.snd(mem));
+        mem.dealloc(prod)
+    }
+
+    fn check_access(&self, id: Nat, read: bool, undo: bool) -> Result<(), RuntimeError> {
+        match &*self.capabilities.borrow() {
+            Some(caps) => caps.check(id, self.channels.borrow().kind(id), read, undo),
+            None => Ok(()),
+        }
+    }
+
+    fn read_channel(
+        &self,
+        mem: &MutatorView,
+        id: Nat,
+        arg: UntypedScopedPtr,
+        undo: bool,
+    ) -> Result<Value, RuntimeError> {
+        let mut channels = self.channels.borrow_mut();
+        let arg = channels.arg_type(id)?
+            .map(|ty| Value::from_heap(mem, &ty, arg))
+            .transpose()?;
+        channels.read(id, arg, undo)
+    }
+
+    // returns what was written, which is freed from the heap since the
+    // channel has its own copy
+    fn write_channel<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        id: Nat,
+        val: UntypedScopedPtr<'guard>,
+        arg: UntypedScopedPtr<'guard>,
+        undo: bool,
+    ) -> Result<Value, RuntimeError> {
+        let mut channels = self.channels.borrow_mut();
+        let ty = channels.value_type(id)?;
+        let value = Value::from_heap(mem, &ty, val)?;
+        let arg = channels.arg_type(id)?
+            .map(|ty| Value::from_heap(mem, &ty, arg))
+            .transpose()?;
+        channels.write(id, value.clone(), arg, undo)?;
+
+        free_tree(mem, &ty, val)?;
+        Ok(value)
+    }
+
+    // returns true if the context stack changed, in which case the
+    // new top context may also need to be evaluated
+    //
+    // everything from here to run_for is instantiated once per
+    // direction, so the checks of BACKWARD cost nothing at runtime
+    pub(crate) fn eval_context<const BACKWARD: bool>(&self, mem: &MutatorView)
+        -> Result<bool, RuntimeError>
     {
         let cxt_stack = self.cxt_stack.get(mem);
         let cont = self.continuation.get(mem);
@@ -88,9 +291,8 @@ impl Thread {
                 // if executing in reverse, will exit combinator
                 // once PRODE is encountered
                 // else, check if moving into second part
-                if ip == snd_op_index && !cont.direction() {
-                    root_val.get(mem).set_fst(self.data.get(mem));
-                    self.data.set(snd_val.get(mem));
+                if ip == snd_op_index && !BACKWARD {
+                    mem.load(&root_val)?.set_fst(self.data.get(mem));
                     
                     // push Second onto context stack
                     let new_cxt = Context::Second {
@@ -98,9 +300,11 @@ impl Thread {
                         fst_val: CellPtr::new_with(self.data.get(mem)),
                         root_val,
                     };
+                    self.data.set(mem.load(&snd_val)?);
 
                     cxt_stack.pop(mem)?;
                     cxt_stack.push(mem, new_cxt)?;
+                    return Ok(true);
                 }
             },
             Context::Second {
@@ -113,9 +317,8 @@ impl Thread {
                 // if executing forwards, will exit combinator
                 // once PRODE is encountered
                 // else, check if moving into first part
-                if cont.direction() && ip == fst_op_index {
-                    root_val.get(mem).set_snd(self.data.get(mem));
-                    self.data.set(fst_val.get(mem));
+                if BACKWARD && ip == fst_op_index {
+                    mem.load(&root_val)?.set_snd(self.data.get(mem));
                     
                     // push First onto context stack
                     let new_cxt = Context::First {
@@ -123,9 +326,11 @@ impl Thread {
                         snd_val: CellPtr::new_with(self.data.get(mem)),
                         root_val,
                     };
+                    self.data.set(mem.load(&fst_val)?);
 
                     cxt_stack.pop(mem)?;
                     cxt_stack.push(mem, new_cxt)?;
+                    return Ok(true);
                 }
             },
             Context::Left {
@@ -138,12 +343,14 @@ impl Thread {
                 // if executing backwards, will exit combinator
                 // once SUME is encountered
                 // else, check if moving out of left part
-                if !cont.direction() && ip == right_op_index {
+                if !BACKWARD && ip == right_op_index {
                     // exit combinator
                     cxt_stack.pop(mem)?;
-                    cont.jump(jump + 1);
-                    root_val.get(mem).set_data(self.data.get(mem));
-                    self.data.set(root_val.get(mem).as_untyped(mem));
+                    cont.jump_in::<BACKWARD>(jump + 1);
+                    let root = mem.load(&root_val)?;
+                    root.set_data(self.data.get(mem));
+                    self.data.set(root.as_untyped(mem));
+                    return Ok(true);
                 }
             },
             Context::Right {
@@ -156,36 +363,61 @@ impl Thread {
                 // if executing forwards, will exit combinator
                 // once SUME is encountered
                 // else, check if moving out of right part
-                if cont.direction() && ip == left_op_index {
+                if BACKWARD && ip == left_op_index {
                     // exit combinator
                     cxt_stack.pop(mem)?;
-                    cont.jump(jump + 1);
-                    root_val.get(mem).set_data(self.data.get(mem));
-                    self.data.set(root_val.get(mem).as_untyped(mem));
+                    cont.jump_in::<BACKWARD>(jump + 1);
+                    let root = mem.load(&root_val)?;
+                    root.set_data(self.data.get(mem));
+                    self.data.set(root.as_untyped(mem));
+                    return Ok(true);
                 }
             },
         }
 
-        Ok(())
+        Ok(false)
     }
 
-    pub fn eval_next_instr<'guard>(&self, mem: &'guard MutatorView)
+    pub fn eval_next_instr(&self, mem: &MutatorView)
         -> Result<EvalStatus, RuntimeError>
     {
+        let flow = if !self.continuation.get(mem).direction() {
+            self.step::<false>(mem)?
+        } else {
+            self.step::<true>(mem)?
+        };
+
+        match flow {
+            Flow::Done => Ok(EvalStatus::Ok),
+            _ => Ok(EvalStatus::Pending),
+        }
+    }
+
+    fn step<const BACKWARD: bool>(&self, mem: &MutatorView) -> Result<Flow, RuntimeError> {
         // check the context stack for any necessary state changes
-        self.eval_context(mem)?;
+        while self.eval_context::<BACKWARD>(mem)? {}
 
-        let cont = self.continuation.get(mem)
-            .as_ref(mem);
-        let cxt_stack = self.cxt_stack.get(mem);
-        let data = self.data.get(mem);
+        // the stream holds the opcode already resolved for this direction
+        let op = self.continuation.get(mem).fetch::<BACKWARD>(mem)?;
+        let opcode = (op & OP_MASK) as u8;
 
-        // get instruction
-        // TODO: Optimize (way too many indirections)
-        let instruction = cont.fetch_instr(mem)?;
-        let op = *(instruction.fst(mem));
-        let arg = instruction.snd(mem);
-        let opcode = get_opcode(op, cont.direction());
+        if is_data_op(opcode) {
+            self.exec_data_op::<BACKWARD>(mem, opcode, op)?;
+            Ok(Flow::Next)
+        } else {
+            self.exec_control_op::<BACKWARD>(mem, opcode, op)
+        }
+    }
+
+    // executes an instruction which only transforms the current data
+    pub(crate) fn exec_data_op<const BACKWARD: bool>(
+        &self,
+        mem: &MutatorView,
+        opcode: u8,
+        op: Opcode,
+    ) -> Result<(), RuntimeError> {
+        let data = self.data.get(mem);
+        let allocs_before = self.begin_instr(mem, opcode)?;
 
         match opcode {
             OP_ID | OP_ID_R => {}, // identity
@@ -194,8 +426,13 @@ impl Thread {
                 self.data.set(new_data.as_untyped(mem));
             },
             OP_ZEROE => {
-                let cast_ptr = unsafe { data.cast::<Sum<()>>(mem) };
-                let inner = zeroe(cast_ptr, mem);
+                let cast_ptr = checked_cast::<_, Sum<()>>(mem, data)?;
+                // the left of 0 + a has no values, so only a partial
+                // function like an injection run backwards can get here
+                if cast_ptr.tag() == 0 {
+                    return Err(RuntimeError::new(ErrorKind::ExpectedZero));
+                }
+                let inner = zeroe(cast_ptr, mem)?;
 
                 self.data.set(inner);
                 mem.dealloc(cast_ptr)?;
@@ -205,193 +442,167 @@ impl Thread {
                 self.data.set(new_data.as_untyped(mem));
             },
             OP_UNITE => {
-                let cast_ptr = unsafe { data.cast::<Product<Unit, ()>>(mem) };
-                let inner = unite(cast_ptr, mem);
+                let cast_ptr = checked_cast::<_, Product<Unit, ()>>(mem, data)?;
+                let inner = unite(cast_ptr, mem)?;
 
                 self.data.set(inner.as_untyped(mem));
                 mem.dealloc(cast_ptr.fst(mem))?;
                 mem.dealloc(cast_ptr)?;
             },
             OP_SWAPP | OP_SWAPP_R => {
-                let cast_ptr = unsafe { data.cast::<Product<(), ()>>(mem) };
+                let cast_ptr = checked_cast::<_, Product<(), ()>>(mem, data)?;
 
-                swapp(&cast_ptr, mem);
+                swapp(&cast_ptr, mem)?;
             },
             OP_ASSRP => {
-                let cast_ptr = unsafe {
-                    data.cast::<Product<(), ()>>(mem)
-                };
+                let cast_ptr = checked_cast::<_, Product<(), ()>>(mem, data)?;
 
-                assrp(&cast_ptr, mem);
+                assrp(&cast_ptr, mem)?;
             },
             OP_ASSLP => {
-                let cast_ptr = unsafe {
-                    data.cast::<Product<(), ()>>(mem)
-                };
+                let cast_ptr = checked_cast::<_, Product<(), ()>>(mem, data)?;
 
-                asslp(&cast_ptr, mem);
+                asslp(&cast_ptr, mem)?;
             },
             OP_SWAPS | OP_SWAPS_R => {
                 let (lc, rc) = decode_s(op);
-                let cast_ptr = unsafe {
-                    data.cast::<Sum<()>>(mem)
-                };
+                let cast_ptr = checked_cast::<_, Sum<()>>(mem, data)?;
 
                 swaps(&cast_ptr, lc, rc, mem);
             },
             OP_ASSRS | OP_ASSLS => {}, // op-equivalent to ID
             OP_DIST => {
                 let (lc, rc) = decode_s(op);
-                let cast_ptr = unsafe {
-                    data.cast::<Product<Sum<()>, ()>>(mem)
-                };
+                let cast_ptr = checked_cast::<_, Product<Sum<()>, ()>>(mem, data)?;
 
                 let sum = dist(cast_ptr, lc, rc, mem)?;
                 self.data.set(sum.as_untyped(mem));
             },
             OP_FACT => {
                 let (lc, rc) = decode_s(op);
-                let cast_ptr = unsafe {
-                    data.cast::<Sum<Product<(), ()>>>(mem)
-                };
+                let cast_ptr = checked_cast::<_, Sum<Product<(), ()>>>(mem, data)?;
 
                 let prod = fact(cast_ptr, lc, rc, mem)?;
                 self.data.set(prod.as_untyped(mem));
             },
             OP_FOLD => {
-                let is_nat = decode_i(op);
-
-                if is_nat == 0 {
-                    let cast_ptr = unsafe {
-                        data.cast::<Sum<Nat>>(mem)
-                    };
+                let cast_ptr = checked_cast::<_, Sum<()>>(mem, data)?;
 
-                    let new_val = fold_nat(cast_ptr, mem)?;
-                    self.data.set(new_val.as_untyped(mem));
-                } else {
-                    let cast_ptr = unsafe {
-                        data.cast::<Sum<()>>(mem)
-                    };
-
-                    let new_val = fold(cast_ptr, mem)?;
-                    self.data.set(new_val.as_untyped(mem));
-                }
+                let new_val = match decode_i(op) {
+                    FOLD_NAT => fold_nat(cast_ptr, mem)?.as_untyped(mem),
+                    FOLD_BIGNAT => fold_bignat(cast_ptr, mem)?.as_untyped(mem),
+                    _ => fold(cast_ptr, mem)?.as_untyped(mem),
+                };
+                self.data.set(new_val);
             },
             OP_UFOLD => {
-                let is_nat = decode_i(op);
+                let new_val = match decode_i(op) {
+                    FOLD_NAT => {
+                        let cast_ptr = checked_cast::<_, Nat>(mem, data)?;
+                        unfold_nat(cast_ptr, mem)?
+                    },
+                    FOLD_BIGNAT => {
+                        let cast_ptr = checked_cast::<_, BigNat>(mem, data)?;
+                        unfold_bignat(cast_ptr, mem)?
+                    },
+                    _ => {
+                        let cast_ptr = checked_cast::<_, Inductive<()>>(mem, data)?;
+                        unfold(cast_ptr, mem)?
+                    },
+                };
+                self.data.set(new_val.as_untyped(mem));
+            },
+            OP_EXPF => {}, // TODO: reimplement
+            OP_COLF => {}, // TODO: reimplement
+            OP_START => {}, // op-equivalent to ID
+            _ => {},
+        }
 
-                if is_nat == 0 {
-                    let cast_ptr = unsafe {
-                        data.cast::<Nat>(mem)
-                    };
+        self.end_instr::<BACKWARD>(mem, opcode, allocs_before, Flow::Next);
+        Ok(())
+    }
 
-                    let new_val = unfold_nat(cast_ptr, mem)?;
-                    self.data.set(new_val.as_untyped(mem));
-                } else {
-                    let cast_ptr = unsafe {
-                        data.cast::<Inductive<()>>(mem)
-                    };
+    // executes an instruction which moves the instruction pointer or
+    // changes the context stack
+    fn exec_control_op<const BACKWARD: bool>(
+        &self,
+        mem: &MutatorView,
+        opcode: u8,
+        op: Opcode,
+    ) -> Result<Flow, RuntimeError> {
+        let cont = self.continuation.get(mem)
+            .as_ref(mem);
+        let cxt_stack = self.cxt_stack.get(mem);
+        let data = self.data.get(mem);
+        let allocs_before = self.begin_instr(mem, opcode)?;
+        let mut flow = Flow::Next;
 
-                    let new_val = unfold(cast_ptr, mem)?;
-                    self.data.set(new_val.as_untyped(mem));
-                }
-            },
+        match opcode {
             OP_EXPN => {
                 let div = decode_i(op);
-                if cont.direction() {
-                    let cast_ptr = unsafe {
-                        data.cast::<Sum<()>>(mem)
-                    };
+                if BACKWARD {
+                    let cast_ptr = checked_cast::<_, Sum<()>>(mem, data)?;
 
                     let new = expn(cast_ptr, div, mem)?;
                     self.data.set(new.as_untyped(mem));
                     cont.reverse();
+                    flow = Flow::Reversed;
                 } else {
                     return Err(RuntimeError::new(ErrorKind::ExpectedZero));
                 }
             },
             OP_COLN => {
                 let div = decode_i(op);
-                if !cont.direction() {
-                    let cast_ptr = unsafe {
-                        data.cast::<Sum<()>>(mem)
-                    };
+                if !BACKWARD {
+                    let cast_ptr = checked_cast::<_, Sum<()>>(mem, data)?;
 
                     // expn and coln are basically the same function, only
                     // one runs forwards and the other backwards
                     let new = expn(cast_ptr, div, mem)?;
                     self.data.set(new.as_untyped(mem));
                     cont.reverse();
+                    flow = Flow::Reversed;
                 } else {
                     return Err(RuntimeError::new(ErrorKind::ExpectedZero));
                 }
             },
-            OP_EXPF => {}, // TODO: reimplement
-            OP_COLF => {}, // TODO: reimplement
+            // a call met backwards runs its callee backwards, which
+            // turns execution around; an uncall does the opposite
             OP_CALL => {
-                let dir = cont.direction();
-                let not = if !dir { false } else { true };
-                let new_cxt = Context::Call {
-                    not,
-                    ret: if dir { cont.ip() - 1 } else { cont.ip() + 1 },
-                };
-                
-                let cast_arg = unsafe {
-                    arg.cast::<Sum<Product<Nat, Nat>>>(mem)
-                };
-                let start_end = cast_arg.data(mem);
-                let start = start_end.fst(mem);
-                let end = start_end.snd(mem);
-
-                self.call_func(mem, *start, *end, not);
-                cxt_stack.push(mem, new_cxt)?;
+                self.call::<BACKWARD>(mem, BACKWARD)?;
+                if BACKWARD { flow = Flow::Reversed; }
             },
             OP_UNCALL => {
-                let dir = cont.direction();
-                let not = if dir { false } else { true };
-                let new_cxt = Context::Call {
-                    not,
-                    ret: if dir { cont.ip() - 1 } else { cont.ip() + 1 },
-                };
-
-                let cast_arg = unsafe {
-                    arg.cast::<Sum<Product<Nat, Nat>>>(mem)
-                };
-                let start_end = cast_arg.data(mem);
-                let start = start_end.fst(mem);
-                let end = start_end.snd(mem);
-
-                self.call_func(mem, *start, *end, not);
-                cxt_stack.push(mem, new_cxt)?;
+                self.call::<BACKWARD>(mem, !BACKWARD)?;
+                if !BACKWARD { flow = Flow::Reversed; }
             },
-            OP_START => {}, // op-equivalent to ID
             OP_END => {
                 match cxt_stack.top(mem)? {
                     Context::Call { not, ret } => {
-                        if not { cont.reverse(); }
+                        cxt_stack.pop(mem)?;
+                        if not {
+                            cont.reverse();
+                            flow = Flow::Reversed;
+                        }
                         cont.set_ip(ret);
                     },
-                    Context::Nil => return Ok(EvalStatus::Ok),
+                    Context::Nil => return Ok(Flow::Done),
                     _ => return Err(RuntimeError::new(ErrorKind::BadContext)),
                 }
             },
-            OP_READ => {}, // TODO: FFI
-            OP_WRITE => {}, // TODO: FFI
+            // run backwards, each of these undoes the other
+            OP_READ => self.read(mem, decode_i(op), BACKWARD)?,
+            OP_WRITE => self.write(mem, decode_i(op), BACKWARD)?,
             OP_SUMS => {
                 let div = decode_i(op);
-                let cast_ptr = unsafe { data.cast::<Sum<()>>(mem) };
-                let cast_arg = unsafe {
-                    arg.cast::<Sum<Product<Nat, Nat>>>(mem)
-                };
-                let lc_rc = cast_arg.data(mem);
-                let lc = lc_rc.fst(mem);
-                let rc = lc_rc.snd(mem);
+                let cast_ptr = checked_cast::<_, Sum<()>>(mem, data)?;
+                let LoadedArg { fst: lc, snd: rc } = cont.fetch_arg(mem)?;
 
                 if cast_ptr.tag() < div as u32 {
-                    if !cont.direction() {
+                    if !BACKWARD {
                         let new_cxt = Context::Left {
-                            right_op_index: cont.ip() + (*lc + 1),
-                            jump: *rc,
+                            right_op_index: cont.ip() + (lc + 1),
+                            jump: rc,
                             root_val: CellPtr::new_with(cast_ptr),
                         };
 
@@ -399,30 +610,30 @@ impl Thread {
                         self.data.set(cast_ptr.data(mem));
                     } else {
                         let new_cxt = Context::Left {
-                            right_op_index: cont.ip() - *rc,
-                            jump: *rc,
+                            right_op_index: cont.ip() - rc,
+                            jump: rc,
                             root_val: CellPtr::new_with(cast_ptr),
                         };
 
-                        cont.jump(*rc + 1); // ip - rc+1
+                        cont.jump_in::<BACKWARD>(rc); // ip - rc
                         cxt_stack.push(mem, new_cxt)?;
                         self.data.set(cast_ptr.data(mem));
                     }
                 } else {
-                    if !cont.direction() {
+                    if !BACKWARD {
                         let new_cxt = Context::Right {
-                            left_op_index: cont.ip() + *lc,
-                            jump: *lc,
+                            left_op_index: cont.ip() + lc,
+                            jump: lc,
                             root_val: CellPtr::new_with(cast_ptr),
                         };
 
-                        cont.jump(*lc + 1); // ip + lc+1
+                        cont.jump_in::<BACKWARD>(lc); // ip + lc
                         cxt_stack.push(mem, new_cxt)?;
                         self.data.set(cast_ptr.data(mem));
                     } else {
                         let new_cxt = Context::Right {
-                            left_op_index: cont.ip() - (*rc + 1),
-                            jump: *lc,
+                            left_op_index: cont.ip() - (rc + 1),
+                            jump: lc,
                             root_val: CellPtr::new_with(cast_ptr),
                         };
 
@@ -436,24 +647,25 @@ impl Thread {
 
                 match sum_cxt {
                     Context::Left { root_val, .. } => {
-                        root_val.get(mem).set_data(self.data.get(mem));
-                        self.data.set(root_val.get(mem).as_untyped(mem));
+                        let root = mem.load(&root_val)?;
+                        root.set_data(self.data.get(mem));
+                        self.data.set(root.as_untyped(mem));
                     },
                     Context::Right { root_val, .. } => {
-                        root_val.get(mem).set_data(self.data.get(mem));
-                        self.data.set(root_val.get(mem).as_untyped(mem));
+                        let root = mem.load(&root_val)?;
+                        root.set_data(self.data.get(mem));
+                        self.data.set(root.as_untyped(mem));
                     },
                     _ => return Err(RuntimeError::new(ErrorKind::BadContext)),
                 }
             },
             OP_PRODS => {
-                let cast_ptr = unsafe { data.cast::<Product<(), ()>>(mem) };
-                let cast_arg = unsafe { arg.cast::<Sum<Nat>>(mem) };
-                let jmp = cast_arg.data(mem);
+                let cast_ptr = checked_cast::<_, Product<(), ()>>(mem, data)?;
+                let jmp = cont.fetch_arg(mem)?.fst;
 
-                if !cont.direction() {
+                if !BACKWARD {
                     let new_cxt = Context::First {
-                        snd_op_index: *jmp + cont.ip(),
+                        snd_op_index: jmp + cont.ip(),
                         snd_val: CellPtr::new_with(cast_ptr.snd(mem)),
                         root_val: CellPtr::new_with(cast_ptr),
                     };
@@ -462,7 +674,7 @@ impl Thread {
                     self.data.set(cast_ptr.fst(mem));
                 } else {
                     let new_cxt = Context::Second {
-                        fst_op_index: *jmp + cont.ip(),
+                        fst_op_index: cont.ip() - jmp,
                         fst_val: CellPtr::new_with(cast_ptr.fst(mem)),
                         root_val: CellPtr::new_with(cast_ptr),
                     };
@@ -476,12 +688,14 @@ impl Thread {
 
                 match sum_cxt {
                     Context::First { root_val, .. } => {
-                        root_val.get(mem).set_fst(self.data.get(mem));
-                        self.data.set(root_val.get(mem).as_untyped(mem));
+                        let root = mem.load(&root_val)?;
+                        root.set_fst(self.data.get(mem));
+                        self.data.set(root.as_untyped(mem));
                     },
                     Context::Second { root_val, .. } => {
-                        root_val.get(mem).set_snd(self.data.get(mem));
-                        self.data.set(root_val.get(mem).as_untyped(mem));
+                        let root = mem.load(&root_val)?;
+                        root.set_snd(self.data.get(mem));
+                        self.data.set(root.as_untyped(mem));
                     },
                     _ => return Err(RuntimeError::new(ErrorKind::BadContext)),
                 }
@@ -489,8 +703,229 @@ impl Thread {
             _ => {},
         }
 
+        self.end_instr::<BACKWARD>(mem, opcode, allocs_before, flow);
+        Ok(flow)
+    }
+
+    // returns the allocation count before the instruction runs
+    fn begin_instr(&self, mem: &MutatorView, opcode: u8)
+        -> Result<usize, RuntimeError>
+    {
+        let ip = self.continuation.get(mem).ip();
+        mem.set_site(Some(HeapSite { opcode, ip }));
+        mem.check_access(self.data.get(mem))?;
+
+        if self.count_allocs.get() {
+            Ok(mem.alloc_count())
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn end_instr<const BACKWARD: bool>(
+        &self,
+        mem: &MutatorView,
+        opcode: u8,
+        allocs_before: usize,
+        flow: Flow,
+    ) {
+        self.record_allocs(opcode, mem.alloc_count() - allocs_before);
+
+        // move on to the next instruction in the current direction,
+        // which is only not BACKWARD if the instruction reversed it
+        self.add_steps(1);
+        mem.set_site(None);
+        let cont = self.continuation.get(mem);
+        match flow {
+            Flow::Reversed => cont.jump(1),
+            _ => cont.jump_in::<BACKWARD>(1),
+        }
+    }
+
+    // steps in one direction until the fuel runs out or execution
+    // turns around or finishes
+    fn run_in<const BACKWARD: bool>(&self, mem: &MutatorView, fuel: &mut u64)
+        -> Result<Flow, RuntimeError>
+    {
+        while *fuel > 0 {
+            *fuel -= 1;
+            match self.step::<BACKWARD>(mem)? {
+                Flow::Next => {},
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    pub fn run(&self, mem: &MutatorView)
+        -> Result<(), RuntimeError>
+    {
+        while self.run_for(mem, u64::MAX)? == EvalStatus::Pending {}
+        Ok(())
+    }
+
+    // runs at most `fuel` instructions, returning Pending if the thread
+    // isn't finished by then
+    pub fn run_for(&self, mem: &MutatorView, mut fuel: u64)
+        -> Result<EvalStatus, RuntimeError>
+    {
+        while fuel > 0 {
+            let flow = if !self.continuation.get(mem).direction() {
+                self.run_in::<false>(mem, &mut fuel)?
+            } else {
+                self.run_in::<true>(mem, &mut fuel)?
+            };
+
+            if flow == Flow::Done {
+                return Ok(EvalStatus::Ok);
+            }
+        }
         Ok(EvalStatus::Pending)
     }
 
+    // writes the thread out to `path` between two instructions, for
+    // resuming later; the heap must have been built with type tags, as
+    // by HeapOptions::suspendable()
+    pub fn suspend(&self, mem: &MutatorView, path: impl AsRef<Path>)
+        -> Result<(), RuntimeError>
+    {
+        self.image(mem)?.save(path)
+    }
+
+    pub fn image(&self, mem: &MutatorView) -> Result<Image, RuntimeError> {
+        Image::capture(
+            mem,
+            self.continuation.get(mem),
+            self.cxt_stack.get(mem),
+            self.data.get(mem),
+            self.steps.get(),
+        )
+    }
+
+    // flips the direction of execution, e.g. to undo a finished run
+    pub fn reverse(&self, mem: &dyn MutatorScope) {
+        self.continuation.get(mem).reverse();
+    }
+
+    // connects a channel to READ/WRITE with the given id, returning
+    // whatever was attached there before
+    pub fn attach_channel(&self, id: Nat, channel: Box<dyn Channel>)
+        -> Option<Box<dyn Channel>>
+    {
+        self.channels.borrow_mut().attach(id, channel)
+    }
+
+    pub fn detach_channel(&self, id: Nat) -> Option<Box<dyn Channel>> {
+        self.channels.borrow_mut().detach(id)
+    }
+
+    // drops every channel, the opener, the capabilities and any log
+    // being recorded or replayed. The heap never runs a Thread's
+    // destructor, so whoever attaches channels must close the thread
+    // once they're done with it, or they're never flushed or closed.
+    // The thread can still be run afterwards, with nothing attached.
+    pub fn close(&self) {
+        drop(self.channels.take());
+        drop(self.capabilities.take());
+        drop(self.interaction.replace(Interaction::Live));
+    }
+
+    // lets READ 0 open channels, turning its descriptors into them
+    pub fn set_opener(&self, opener: Box<dyn Opener>) {
+        self.channels.borrow_mut().set_opener(opener);
+    }
+
+    // whether READ/WRITE can currently reach a channel with this id
+    pub fn channel_open(&self, id: Nat) -> bool {
+        self.channels.borrow().is_open(id)
+    }
+
+    // sandboxes the thread, after which READ/WRITE can only reach the
+    // channels granted
+    pub fn restrict(&self, capabilities: Capabilities) {
+        *self.capabilities.borrow_mut() = Some(capabilities);
+    }
+
+    // bits erased through the thread's channels, less any brought back
+    pub fn erased_bits(&self) -> u64 {
+        self.channels.borrow().erased_bits()
+    }
+
+    // instructions executed so far, in either direction
+    pub fn steps(&self) -> u64 { self.steps.get() }
+
+    pub(crate) fn add_steps(&self, steps: u64) {
+        self.steps.set(self.steps.get() + steps);
+    }
+
+    // logs every channel interaction from here on
+    pub fn record(&self) {
+        *self.interaction.borrow_mut() = Interaction::Recording(ReplayLog::new());
+    }
+
+    // plays back a recorded log in place of the real channels, which
+    // must be started from the same state as the recording was
+    pub fn replay(&self, log: ReplayLog) {
+        *self.interaction.borrow_mut() = Interaction::Replaying(Replay::new(log));
+    }
+
+    // whether every interaction in the log being replayed has happened
+    pub fn replay_finished(&self) -> bool {
+        match &*self.interaction.borrow() {
+            Interaction::Replaying(replay) => replay.finished(),
+            _ => false,
+        }
+    }
+
+    // goes back to the real channels, returning the log if recording
+    pub fn take_log(&self) -> Option<ReplayLog> {
+        match self.interaction.replace(Interaction::Live) {
+            Interaction::Recording(log) => Some(log),
+            _ => None,
+        }
+    }
+
+    // number of contexts on the stack, including the Nil at its root
+    pub fn context_depth(&self, guard: &dyn MutatorScope) -> ArraySize {
+        self.cxt_stack.get(guard).depth()
+    }
+
+    // the context stack stops growing at `max` contexts, or MAX_DEPTH if
+    // that's lower, after which a push fails with a StackOverflow error;
+    // until this is called, it grows as far as MAX_DEPTH
+    pub fn set_max_depth(&self, guard: &dyn MutatorScope, max: ArraySize) {
+        self.cxt_stack.get(guard).set_max_depth(max);
+    }
+
+    pub fn count_allocs(&self, enable: bool) { self.count_allocs.set(enable); }
+
+    pub(crate) fn record_allocs(&self, opcode: u8, allocs: usize) {
+        if self.count_allocs.get() {
+            let counter = &self.alloc_counts[opcode as usize];
+            counter.set(counter.get() + allocs);
+        }
+    }
+
+    // allocations made by each opcode, as executed (i.e. after
+    // accounting for the direction of execution)
+    pub fn alloc_counts(&self) -> [usize; OPCODE_COUNT] {
+        std::array::from_fn(|opcode| self.alloc_counts[opcode].get())
+    }
+
     pub fn data(&self) -> &UntypedCellPtr { &self.data }
+    pub fn continuation(&self) -> &CellPtr<Continuation> { &self.continuation }
+}
+
+/* Helper functions */
+// a dynamic READ/WRITE takes its channel id from the front of ?a,
+// which must be (nat * ?a'), and the channel gets ?a' as its argument
+fn resolve_channel<'guard>(mem: &'guard MutatorView, imm: Nat, a: UntypedScopedPtr<'guard>)
+    -> Result<(Nat, UntypedScopedPtr<'guard>), RuntimeError>
+{
+    if imm != CHANNEL_DYNAMIC {
+        return Ok((imm, a));
+    }
+
+    let prod = checked_cast::<_, Product<Nat, ()>>(mem, a)?;
+    Ok((*prod.fst(mem), prod.snd(mem)))
 }
@@ -0,0 +1,342 @@
+use std::fmt::Write;
+use std::fs;
+use std::process::Command;
+
+use iris::aot;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::error::ErrorKind;
+use iris::memory::{Memory, MutatorView};
+use iris::stdlib::Library;
+use iris::types::IType;
+use iris::value::{structural_eq, Value};
+
+mod common;
+use common::*;
+
+struct Case {
+    instrs: Vec<DecodedInstr>,
+    input: Value,
+    in_ty: IType,
+    out_ty: IType,
+}
+
+// runs a case forwards and then backwards in the interpreter, giving
+// the lines the compiled program is expected to print
+fn interpret(case: &Case) -> Vec<String> {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_thread(&mem, &case.instrs, case.input.alloc(&mem).unwrap());
+
+    thread.run(&mem).unwrap();
+    let output = Value::from_heap(&mem, &case.out_ty, thread.data().get(&mem)).unwrap();
+
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    let input = Value::from_heap(&mem, &case.in_ty, thread.data().get(&mem)).unwrap();
+
+    vec![
+        format!("{:?}", Ok::<_, ()>(output)),
+        format!("{:?}", Ok::<_, ()>(input)),
+    ]
+}
+
+// compiles every case into one Rust program, builds it with the rustc
+// cargo is using and returns what it prints
+fn run_compiled(name: &str, cases: &[Case]) -> Vec<String> {
+    let mut program = String::from("#![allow(dead_code)]\n");
+    let mut main = String::from("fn main() {\n");
+
+    for (index, case) in cases.iter().enumerate() {
+        let module = aot::compile(&case.instrs, &case.in_ty).unwrap();
+        write!(program, "mod p{} {{\n{}\n}}\n", index, module).unwrap();
+        write!(
+            main,
+            "    {{\n        use p{0}::*;\n        \
+             let out = run_forward({1});\n        \
+             println!(\"{{:?}}\", out);\n        \
+             println!(\"{{:?}}\", out.and_then(run_backward));\n    }}\n",
+            index,
+            case.input.to_rust()
+        ).unwrap();
+    }
+    program.push_str(&main);
+    program.push_str("}\n");
+
+    let dir = env!("CARGO_TARGET_TMPDIR");
+    let source = format!("{}/aot_{}.rs", dir, name);
+    let binary = format!("{}/aot_{}", dir, name);
+    fs::write(&source, program).unwrap();
+
+    let status = Command::new(env!("IRIS_RUSTC"))
+        .args(["--edition", "2021", "-O", "-o", &binary, &source])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout).unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+fn assert_same(name: &str, cases: &[Case]) {
+    let expected: Vec<String> = cases.iter().flat_map(interpret).collect();
+    assert!(run_compiled(name, cases) == expected);
+}
+
+fn nat_sum(tag: u32, n: u32) -> Value {
+    Value::Sum(tag, Box::new(Value::Nat(n)))
+}
+
+#[test]
+fn test_compiled_programs() {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let sum_instrs = vec![
+        op(OP_START),
+        DecodedInstr::with_pair(sums, 1, 2),
+        op(OP_UNITI),
+        op(OP_UNITI),
+        op(OP_SWAPP),
+        DecodedInstr::with_pair(sume, 1, 2),
+        op(OP_END),
+    ];
+    let sum_out = IType::sum(
+        IType::prod(IType::Unit, IType::Nat),
+        IType::prod(IType::Nat, IType::Unit),
+    );
+
+    let cases = [
+        // plain data ops
+        Case {
+            instrs: vec![
+                op(OP_START),
+                op(OP_UNITI),
+                op(OP_UNITI),
+                op(OP_ASSLP),
+                op(OP_SWAPP),
+                op(OP_SWAPP),
+                op(OP_ASSRP),
+                op(OP_UNITE),
+                op(OP_UNITE),
+                op(OP_ZEROI),
+                op(OP_ZEROE),
+                DecodedInstr::new(encode_i(OP_UFOLD, FOLD_NAT).unwrap()),
+                DecodedInstr::new(encode_i(OP_FOLD, FOLD_NAT).unwrap()),
+                op(OP_END),
+            ],
+            input: Value::Nat(1337),
+            in_ty: IType::Nat,
+            out_ty: IType::Nat,
+        },
+        // both branches of a sum combinator
+        Case {
+            instrs: sum_instrs.clone(),
+            input: nat_sum(0, 4),
+            in_ty: IType::sum(IType::Nat, IType::Nat),
+            out_ty: sum_out.clone(),
+        },
+        Case {
+            instrs: sum_instrs,
+            input: nat_sum(1, 20),
+            in_ty: IType::sum(IType::Nat, IType::Nat),
+            out_ty: sum_out,
+        },
+        // product combinator
+        Case {
+            instrs: vec![
+                op(OP_START),
+                DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), 2),
+                op(OP_UNITI),
+                op(OP_ZEROI),
+                op(OP_UNITI),
+                DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 3),
+                op(OP_END),
+            ],
+            input: pair(nat(420), nat(69)),
+            in_ty: IType::prod(IType::Nat, IType::Nat),
+            out_ty: IType::prod(
+                IType::prod(IType::Unit, IType::Nat),
+                IType::prod(IType::Unit, IType::sum(IType::Zero, IType::Nat)),
+            ),
+        },
+        // calls and uncalls
+        Case {
+            instrs: vec![
+                op(OP_START),
+                DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 4, 7),
+                DecodedInstr::with_pair(encode_i(OP_UNCALL, 0).unwrap(), 8, 10),
+                op(OP_END),
+                op(OP_START),
+                op(OP_ZEROI),
+                op(OP_UNITI),
+                op(OP_END),
+                op(OP_START),
+                op(OP_UNITI),
+                op(OP_END),
+            ],
+            input: Value::Nat(1337),
+            in_ty: IType::Nat,
+            out_ty: IType::sum(IType::Zero, IType::Nat),
+        },
+        // distribution, sum swapping and factoring
+        Case {
+            instrs: vec![
+                op(OP_START),
+                DecodedInstr::new(encode_s(OP_DIST, 1, 1).unwrap()),
+                DecodedInstr::new(encode_s(OP_SWAPS, 1, 1).unwrap()),
+                DecodedInstr::new(encode_s(OP_FACT, 1, 1).unwrap()),
+                op(OP_END),
+            ],
+            input: Value::Product(
+                Box::new(Value::Sum(1, Box::new(nat_sum(1, 7)))),
+                Box::new(Value::Nat(5)),
+            ),
+            in_ty: IType::prod(
+                IType::sum(IType::Nat, IType::sum(IType::Nat, IType::Nat)),
+                IType::Nat,
+            ),
+            out_ty: IType::prod(
+                IType::sum(IType::sum(IType::Nat, IType::Nat), IType::Nat),
+                IType::Nat,
+            ),
+        },
+        // lists and big nats
+        Case {
+            instrs: vec![
+                op(OP_START),
+                DecodedInstr::new(encode_i(OP_UFOLD, FOLD_IND).unwrap()),
+                op(OP_END),
+            ],
+            input: Value::Inductive(vec![Value::Nat(1), Value::Nat(2)]),
+            in_ty: IType::ind(IType::Nat),
+            out_ty: IType::sum(
+                IType::Unit,
+                IType::prod(IType::Nat, IType::ind(IType::Nat)),
+            ),
+        },
+        Case {
+            instrs: vec![
+                op(OP_START),
+                DecodedInstr::new(encode_i(OP_FOLD, FOLD_BIGNAT).unwrap()),
+                op(OP_END),
+            ],
+            input: Value::Sum(1, Box::new(Value::BigNat(vec![u32::MAX]))),
+            in_ty: IType::sum(IType::Unit, IType::BigNat),
+            out_ty: IType::BigNat,
+        },
+    ];
+
+    assert_same("programs", &cases);
+}
+
+#[test]
+fn test_compiled_overflow() {
+    let case = Case {
+        instrs: vec![
+            op(OP_START),
+            DecodedInstr::new(encode_i(OP_FOLD, FOLD_NAT).unwrap()),
+            op(OP_END),
+        ],
+        input: nat_sum(1, u32::MAX),
+        in_ty: IType::sum(IType::Unit, IType::Nat),
+        out_ty: IType::Nat,
+    };
+
+    let output = run_compiled("overflow", &[case]);
+    assert!(output == ["Err(Overflow)", "Err(Overflow)"]);
+}
+
+fn assert_rejected(instrs: &[DecodedInstr], input: &IType) {
+    match aot::compile(instrs, input) {
+        Err(e) => assert!(matches!(e.error_kind(), ErrorKind::CompileError(_))),
+        Ok(_) => panic!("compiled a program with no static translation"),
+    }
+}
+
+#[test]
+fn test_reject_unsupported() {
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::new(encode_i(OP_EXPN, 0).unwrap()),
+        op(OP_END),
+    ];
+
+    assert_rejected(&instrs, &IType::Nat);
+}
+
+#[test]
+fn test_reject_fraction() {
+    let id = [op(OP_START), op(OP_ID), op(OP_END)];
+    assert_rejected(&id, &IType::frac(IType::Nat));
+    assert_rejected(&id, &IType::prod(IType::Nat, IType::frac(IType::Unit)));
+
+    let expf = [
+        op(OP_START),
+        DecodedInstr::new(encode_i(OP_EXPF, 0).unwrap()),
+        op(OP_END),
+    ];
+    assert_rejected(&expf, &IType::Nat);
+}
+
+#[test]
+fn test_reject_ill_typed() {
+    // a nat isn't a product with a unit in it
+    assert_rejected(&[op(OP_START), op(OP_UNITE), op(OP_END)], &IType::Nat);
+
+    // only 0 + a can lose its zero
+    let zeroe = [op(OP_START), op(OP_ZEROE), op(OP_END)];
+    assert_rejected(&zeroe, &IType::sum(IType::Nat, IType::Nat));
+
+    // a sum of a sum is still only two variants, not three
+    let swaps = [
+        op(OP_START),
+        DecodedInstr::new(encode_s(OP_SWAPS, 1, 2).unwrap()),
+        op(OP_END),
+    ];
+    assert_rejected(&swaps, &IType::sum(IType::Nat, IType::sum(IType::Nat, IType::Unit)));
+}
+
+#[test]
+fn test_compiled_stdlib() {
+    let lib = Library::new();
+    let int = IType::sum(IType::Nat, IType::Nat);
+    let neg = |input| Case {
+        instrs: lib.program("neg").unwrap(),
+        input,
+        in_ty: int.clone(),
+        out_ty: int.clone(),
+    };
+
+    assert_same("stdlib", &[neg(nat_sum(0, 7)), neg(nat_sum(0, 0)), neg(nat_sum(1, 4))]);
+
+    // inc and dec fold a 0 + nat, and everything else loops, through
+    // either CALL or the trace
+    for iso in lib.isos().iter().filter(|iso| iso.name != "neg") {
+        assert_rejected(&lib.program(iso.name).unwrap(), &iso.signature.input);
+    }
+}
+
+#[test]
+fn test_value_round_trip() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let ty = IType::prod(
+        IType::sum(IType::Unit, IType::ind(IType::Nat)),
+        IType::negative(IType::BigNat),
+    );
+    let value = Value::Product(
+        Box::new(Value::Sum(1, Box::new(Value::Inductive(vec![Value::Nat(3)])))),
+        Box::new(Value::Negative(Box::new(Value::BigNat(vec![0, 1])))),
+    );
+
+    let ptr = value.alloc(&mem).unwrap();
+    let copy = value.alloc(&mem).unwrap();
+    assert!(structural_eq(&mem, &ty, ptr, copy).unwrap());
+    assert!(Value::from_heap(&mem, &ty, ptr).unwrap() == value);
+}
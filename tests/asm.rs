@@ -0,0 +1,100 @@
+use iris::asm::*;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::RuntimeError;
+use iris::memory::{Memory, MutatorView};
+use iris::safeptr::*;
+use iris::types::IType;
+use iris::value::Value;
+use iris::vm::Thread;
+
+mod common;
+use common::{nat, pair};
+
+// runs the first function of the block on `input`
+fn run(block: &Block, out_ty: &IType, input: &Value) -> Result<Value, RuntimeError> {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let function = alloc_function(&mem, &block.instrs)?;
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(function),
+        CellPtr::new_with(input.alloc(&mem)?),
+    ))?;
+    let thread = Thread::alloc_with_arg(&mem, arg)?;
+
+    thread.run(&mem)?;
+    Value::from_heap(&mem, out_ty, thread.data().get(&mem))
+}
+
+fn assert_iso(body: Vec<Asm>, in_ty: IType, out_ty: IType, input: Value, output: Value) {
+    let forward = assemble(vec![("main", call("f")), ("f", body.clone())]).unwrap();
+    let backward = assemble(vec![("main", uncall("f")), ("f", body)]).unwrap();
+
+    assert!(run(&forward, &out_ty, &input).unwrap() == output);
+    assert!(run(&backward, &in_ty, &output).unwrap() == input);
+}
+
+#[test]
+fn test_assemble_calls() {
+    let block = assemble(vec![
+        ("main", [call("inc"), uncall("inc")].concat()),
+        ("inc", [inr(), fold()].concat()),
+    ]).unwrap();
+
+    assert!(block.bounds == [(0, 3), (4, 7)]);
+    assert!(block.instrs[1].opcode() == OP_CALL);
+    assert!(block.instrs[1].arg == InstrArg::Pair(4, 7));
+    assert!(block.instrs[2].opcode() == OP_UNCALL);
+    assert!(block.instrs[2].arg == InstrArg::Pair(4, 7));
+    verify_function(&block.instrs).unwrap();
+}
+
+#[test]
+fn test_assemble_undefined() {
+    assert!(assemble(vec![("main", call("missing"))]).is_err());
+    assert!(assemble(vec![("main", vec![]), ("main", vec![])]).is_err());
+}
+
+#[test]
+fn test_repeat() {
+    // (n, x) <-> (n, x + n)
+    let ty = IType::prod(IType::Nat, IType::Nat);
+    let inc = [inr(), fold()].concat();
+
+    let (three, none) = (pair(nat(3), nat(5)), pair(nat(0), nat(5)));
+
+    assert_iso(repeat(inc.clone()), ty.clone(), ty.clone(), three, pair(nat(3), nat(8)));
+    assert_iso(repeat(inc), ty.clone(), ty, none.clone(), none);
+}
+
+#[test]
+fn test_times() {
+    // (n, x) <-> (n, x + 0 + 1 + ... + n - 1), adding each index to x
+    // one at a time
+    let ty = IType::prod(IType::Nat, IType::Nat);
+    let add = times(repeat([inr(), fold()].concat()));
+
+    assert_iso(add, ty.clone(), ty, pair(nat(4), nat(1)), pair(nat(4), nat(7)));
+}
+
+#[test]
+fn test_count() {
+    // counting down: n <-> n - 1 + (), until there's nothing left
+    let step = [unfold(), op_s(OP_SWAPS, 1, 1)].concat();
+    let out_ty = IType::prod(IType::Nat, IType::Unit);
+
+    for n in [0, 1, 5] {
+        let output = pair(nat(n), Value::Unit);
+        assert_iso(count(step.clone()), IType::Nat, out_ty.clone(), nat(n), output);
+    }
+}
+
+#[test]
+fn test_trace() {
+    // a body which swaps sends its input round the loop once, and out
+    // the second time it comes through
+    let body = op_s(OP_SWAPS, 1, 1);
+
+    assert_iso(trace(body), IType::Nat, IType::Nat, nat(7), nat(7));
+}
@@ -1,5 +1,6 @@
 use iris::bytecode::*;
 use iris::constants::*;
+use iris::memory::{Memory, MutatorView};
 
 #[test]
 fn test_get_opcode() {
@@ -28,3 +29,44 @@ fn test_stype() {
     assert!(OP_SWAPS == get_opcode(instr, false));
     assert!((6, 9) == decode_s(instr));
 }
+
+#[test]
+fn test_streams() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let function = alloc_function(&mem, &[
+        DecodedInstr::new(encode_i(OP_START, 0).unwrap()),
+        DecodedInstr::new(encode_s(OP_DIST, 2, 3).unwrap()),
+        DecodedInstr::new(encode_i(OP_FOLD, FOLD_BIGNAT).unwrap()),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 4, 5),
+        DecodedInstr::new(encode_i(OP_END, 0).unwrap()),
+        DecodedInstr::new(encode_i(OP_START, 0).unwrap()),
+        DecodedInstr::new(encode_i(OP_END, 0).unwrap()),
+    ]).unwrap();
+    let program = Program::load(&mem, function).unwrap();
+    let cont = Continuation::alloc(&mem, program).unwrap();
+
+    cont.set_ip(1);
+    assert!(cont.fetch_op(&mem).unwrap() == encode_s(OP_DIST, 2, 3).unwrap());
+
+    // reversing switches to the other stream, keeping immediates
+    cont.reverse();
+    assert!(cont.fetch_op(&mem).unwrap() == encode_s(OP_FACT, 2, 3).unwrap());
+    cont.set_ip(2);
+    assert!(cont.fetch_op(&mem).unwrap() == encode_i(OP_UFOLD, FOLD_BIGNAT).unwrap());
+
+    cont.reverse();
+    assert!(cont.fetch_op(&mem).unwrap() == encode_i(OP_FOLD, FOLD_BIGNAT).unwrap());
+
+    // control ops find their argument without going to the function
+    cont.set_ip(3);
+    assert!(cont.fetch::<false>(&mem).unwrap() == encode_i(OP_CALL, 0).unwrap());
+    assert!(cont.fetch::<true>(&mem).unwrap() == encode_i(OP_UNCALL, 0).unwrap());
+    let arg = cont.fetch_arg(&mem).unwrap();
+    assert!((arg.fst, arg.snd) == (4, 5));
+
+    // another continuation on the same program shares its streams
+    let other = Continuation::alloc(&mem, program).unwrap();
+    other.set_ip(3);
+    assert!(other.fetch::<false>(&mem).unwrap() == encode_i(OP_CALL, 0).unwrap());
+}
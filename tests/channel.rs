@@ -0,0 +1,694 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use iris::value::Value;
+use iris::asm::{assemble, fold, inl, op_s, trace};
+use iris::bytecode::*;
+use iris::channel::*;
+use iris::constants::*;
+use iris::error::{ErrorKind, RuntimeError};
+use iris::memory::{Memory, MutatorView};
+use iris::types::IType;
+
+mod common;
+use common::*;
+
+// output which the test can still look at once the console is attached
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+fn text(s: &str) -> Value {
+    bytes_to_list(s.as_bytes())
+}
+
+fn line_ty() -> IType {
+    IType::prod(IType::ind(IType::Nat), IType::Unit)
+}
+
+fn text_ty() -> IType {
+    IType::prod(IType::ind(IType::Nat), IType::ind(IType::Nat))
+}
+
+// opens a console reading the descriptor's text
+struct Lines(SharedBuf);
+
+impl Opener for Lines {
+    fn descriptor_type(&self) -> IType { IType::ind(IType::Nat) }
+
+    fn open(&mut self, descriptor: Value) -> Result<Box<dyn Channel>, RuntimeError> {
+        let input = Cursor::new(list_to_bytes(&descriptor)?);
+        Ok(Box::new(Console::new(input, self.0.clone())))
+    }
+}
+
+// opens a console with nothing to read, whatever it's given
+struct Sinks;
+
+impl Opener for Sinks {
+    fn descriptor_type(&self) -> IType { IType::Unit }
+
+    fn open(&mut self, _: Value) -> Result<Box<dyn Channel>, RuntimeError> {
+        Ok(Box::new(Console::new(io::empty(), io::sink())))
+    }
+}
+
+// an empty directory of its own for each test
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("iris-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_echo() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let out = SharedBuf::default();
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_READ, CHANNEL_CONSOLE), io(OP_WRITE, CHANNEL_CONSOLE)],
+        &Value::Unit,
+    );
+
+    let console = Console::new("hello\nworld\n".as_bytes(), out.clone());
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(console));
+    thread.run(&mem).unwrap();
+
+    assert!(*out.0.borrow() == b"hello\n");
+    assert!(Value::from_heap(&mem, &IType::Unit, thread.data().get(&mem)).unwrap() == Value::Unit);
+}
+
+#[test]
+fn test_close() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let out = SharedBuf::default();
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, CHANNEL_CONSOLE)],
+        &pair(text("hi\n"), Value::Unit),
+    );
+
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(Console::new(io::empty(), out.clone())));
+    thread.run(&mem).unwrap();
+    assert!(Rc::strong_count(&out.0) == 2);
+
+    // the console goes, along with its handle on the output
+    thread.close();
+    assert!(Rc::strong_count(&out.0) == 1);
+    assert!(!thread.channel_open(CHANNEL_CONSOLE));
+    assert!(*out.0.borrow() == b"hi\n");
+}
+
+#[test]
+fn test_undo_read() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CONSOLE)], &Value::Unit);
+
+    let console = Console::new("first\nsecond\n".as_bytes(), io::sink());
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(console));
+    thread.run(&mem).unwrap();
+
+    let read = Value::from_heap(&mem, &line_ty(), thread.data().get(&mem)).unwrap();
+    assert!(read == pair(text("first\n"), Value::Unit));
+
+    // the line goes back to the console, and is read again next time
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    let read = Value::from_heap(&mem, &line_ty(), thread.data().get(&mem)).unwrap();
+    assert!(read == pair(text("first\n"), Value::Unit));
+}
+
+#[test]
+fn test_undo_write() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let out = SharedBuf::default();
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, CHANNEL_CONSOLE)],
+        &pair(text("ok\n"), Value::Unit),
+    );
+
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(Console::new(io::empty(), out.clone())));
+    thread.run(&mem).unwrap();
+    assert!(*out.0.borrow() == b"ok\n");
+
+    // running backwards rebuilds the written value from the log
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    let undone = Value::from_heap(&mem, &line_ty(), thread.data().get(&mem)).unwrap();
+    assert!(undone == pair(text("ok\n"), Value::Unit));
+}
+
+#[test]
+fn test_end_of_input() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CONSOLE)], &Value::Unit);
+
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(Console::new(io::empty(), io::sink())));
+    thread.run(&mem).unwrap();
+
+    let read = Value::from_heap(&mem, &line_ty(), thread.data().get(&mem)).unwrap();
+    assert!(read == pair(text(""), Value::Unit));
+}
+
+#[test]
+fn test_channel_errors() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CONSOLE)], &Value::Unit);
+    let err = thread.run(&mem).unwrap_err();
+    assert!(*err.error_kind() == ErrorKind::UnknownChannel(CHANNEL_CONSOLE));
+
+    // only bytes can be printed
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, CHANNEL_CONSOLE)],
+        &pair(Value::Inductive(vec![Value::Nat(256)]), Value::Unit),
+    );
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(Console::new(io::empty(), io::sink())));
+    let err = thread.run(&mem).unwrap_err();
+    assert!(*err.error_kind() == ErrorKind::IntOverflow);
+}
+
+#[test]
+fn test_storage_journal() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let dir = scratch_dir("journal");
+    let file = dir.join("notes");
+
+    let write = |contents: &str| {
+        let thread = alloc_body_thread(
+            &mem,
+            &[io(OP_WRITE, CHANNEL_STORAGE)],
+            &pair(text(contents), text("notes")),
+        );
+        thread.attach_channel(CHANNEL_STORAGE, Box::new(Storage::open(&dir).unwrap()));
+        thread.run(&mem).unwrap();
+        thread
+    };
+
+    let first = write("one");
+    let second = write("two");
+    assert!(fs::read(&file).unwrap() == b"two");
+    assert!(Storage::open(&dir).unwrap().journal_len() == 2);
+
+    // each undo finds its entry in the journal, even through a new Storage
+    second.attach_channel(CHANNEL_STORAGE, Box::new(Storage::open(&dir).unwrap()));
+    second.reverse(&mem);
+    second.run(&mem).unwrap();
+    assert!(fs::read(&file).unwrap() == b"one");
+
+    let undone = Value::from_heap(&mem, &text_ty(), second.data().get(&mem)).unwrap();
+    assert!(undone == pair(text("two"), text("notes")));
+
+    first.reverse(&mem);
+    first.run(&mem).unwrap();
+    assert!(!file.exists());
+    assert!(Storage::open(&dir).unwrap().journal_len() == 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_storage_undo_read() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let dir = scratch_dir("read");
+    fs::write(dir.join("data"), b"hello").unwrap();
+
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_STORAGE)], &text("data"));
+    thread.attach_channel(CHANNEL_STORAGE, Box::new(Storage::open(&dir).unwrap()));
+    thread.run(&mem).unwrap();
+
+    let read = Value::from_heap(&mem, &text_ty(), thread.data().get(&mem)).unwrap();
+    assert!(read == pair(text("hello"), text("data")));
+
+    // the value read can only be given up while the file still agrees
+    fs::write(dir.join("data"), b"changed").unwrap();
+    thread.reverse(&mem);
+    assert!(matches!(thread.run(&mem).unwrap_err().error_kind(), ErrorKind::IOError(_)));
+
+    fs::write(dir.join("data"), b"hello").unwrap();
+    thread.run(&mem).unwrap();
+    assert!(Value::from_heap(&mem, &IType::ind(IType::Nat), thread.data().get(&mem)).unwrap() == text("data"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_storage_names() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let dir = scratch_dir("names");
+
+    for name in ["", "../escape", ".journal", "a/b"] {
+        let thread = alloc_body_thread(
+            &mem,
+            &[io(OP_WRITE, CHANNEL_STORAGE)],
+            &pair(text("x"), text(name)),
+        );
+        thread.attach_channel(CHANNEL_STORAGE, Box::new(Storage::open(&dir).unwrap()));
+        assert!(matches!(thread.run(&mem).unwrap_err().error_kind(), ErrorKind::IOError(_)));
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_record_replay() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let echo = [io(OP_READ, CHANNEL_CONSOLE), io(OP_WRITE, CHANNEL_CONSOLE)];
+
+    let thread = alloc_body_thread(&mem, &echo, &Value::Unit);
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(Console::new("hi\n".as_bytes(), io::sink())));
+    thread.record();
+    thread.run(&mem).unwrap();
+
+    let log = thread.take_log().unwrap();
+    assert!(log.events().len() == 2);
+    assert!(log.events()[0].step == 1 && log.events()[1].step == 2);
+    assert!(ReplayLog::parse(&log.to_string()).unwrap() == log);
+
+    // no console this time; everything comes from the log
+    let replayed = alloc_body_thread(&mem, &echo, &Value::Unit);
+    replayed.replay(log);
+    replayed.run(&mem).unwrap();
+    assert!(replayed.replay_finished());
+    assert!(Value::from_heap(&mem, &IType::Unit, replayed.data().get(&mem)).unwrap() == Value::Unit);
+}
+
+#[test]
+fn test_replay_backwards() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CONSOLE)], &Value::Unit);
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(Console::new("hi\n".as_bytes(), io::sink())));
+    thread.record();
+    thread.run(&mem).unwrap();
+    let log = thread.take_log().unwrap();
+
+    // the recording only went forwards, but the replay can turn around
+    let replayed = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CONSOLE)], &Value::Unit);
+    replayed.replay(log);
+    replayed.run(&mem).unwrap();
+    replayed.reverse(&mem);
+    replayed.run(&mem).unwrap();
+    assert!(!replayed.replay_finished());
+    assert!(Value::from_heap(&mem, &IType::Unit, replayed.data().get(&mem)).unwrap() == Value::Unit);
+
+    replayed.reverse(&mem);
+    replayed.run(&mem).unwrap();
+    let read = Value::from_heap(&mem, &line_ty(), replayed.data().get(&mem)).unwrap();
+    assert!(read == pair(text("hi\n"), Value::Unit));
+}
+
+#[test]
+fn test_replay_diverged() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let log = ReplayLog::parse("2 4 write fwd i1 n33\n").unwrap();
+
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, CHANNEL_CONSOLE)],
+        &pair(text("?"), Value::Unit),
+    );
+    thread.replay(log);
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::ReplayDiverged(1));
+
+    assert!(ReplayLog::parse("1 4 read sideways u").is_err());
+}
+
+#[test]
+fn test_open_close() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let out = SharedBuf::default();
+    let thread = alloc_body_thread(
+        &mem,
+        &[
+            io(OP_READ, CHANNEL_OPEN),
+            io(OP_READ, CHANNEL_DYNAMIC),
+            io(OP_WRITE, CHANNEL_DYNAMIC),
+            io(OP_WRITE, CHANNEL_OPEN),
+        ],
+        &text("hello\n"),
+    );
+
+    thread.set_opener(Box::new(Lines(out.clone())));
+    thread.run(&mem).unwrap();
+    assert!(*out.0.borrow() == b"hello\n");
+    assert!(!thread.channel_open(FIRST_DYNAMIC));
+
+    // undoing the close brings back the same console, which can then
+    // take back what it wrote and unread its line
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    assert!(!thread.channel_open(FIRST_DYNAMIC));
+    assert!(Value::from_heap(&mem, &IType::ind(IType::Nat), thread.data().get(&mem)).unwrap() == text("hello\n"));
+}
+
+#[test]
+fn test_dynamic_ids() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_READ, CHANNEL_OPEN), io(OP_READ, CHANNEL_OPEN)],
+        &Value::Unit,
+    );
+
+    // ids already taken are skipped
+    thread.set_opener(Box::new(Sinks));
+    thread.attach_channel(FIRST_DYNAMIC, Box::new(Console::new(io::empty(), io::sink())));
+    thread.run(&mem).unwrap();
+
+    let ty = IType::prod(IType::Nat, IType::prod(IType::Nat, IType::Unit));
+    let opened = Value::from_heap(&mem, &ty, thread.data().get(&mem)).unwrap();
+    assert!(opened == pair(
+        Value::Nat(FIRST_DYNAMIC + 2),
+        pair(Value::Nat(FIRST_DYNAMIC + 1), Value::Unit),
+    ));
+
+    // without an opener, channel 0 isn't there
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_OPEN)], &text("x"));
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::UnknownChannel(CHANNEL_OPEN));
+
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_DYNAMIC)], &pair(Value::Nat(7), Value::Unit));
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::UnknownChannel(7));
+}
+
+#[test]
+fn test_capabilities() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let echo = [io(OP_READ, CHANNEL_CONSOLE), io(OP_WRITE, CHANNEL_CONSOLE)];
+    let console = || Box::new(Console::new("hi\n".as_bytes(), io::sink()));
+
+    // read-only lets the line in but not back out
+    let thread = alloc_body_thread(&mem, &echo, &Value::Unit);
+    let mut caps = Capabilities::new();
+    caps.grant_id(CHANNEL_CONSOLE, Access::Read);
+    thread.attach_channel(CHANNEL_CONSOLE, console());
+    thread.restrict(caps.clone());
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::ChannelDenied(CHANNEL_CONSOLE));
+
+    caps.grant_id(CHANNEL_CONSOLE, Access::ReadWrite);
+    let thread = alloc_body_thread(&mem, &echo, &Value::Unit);
+    thread.attach_channel(CHANNEL_CONSOLE, console());
+    thread.restrict(caps);
+    thread.run(&mem).unwrap();
+
+    // nothing granted, not even opening channels
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_OPEN)], &Value::Unit);
+    thread.set_opener(Box::new(Sinks));
+    thread.restrict(Capabilities::new());
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::ChannelDenied(CHANNEL_OPEN));
+}
+
+#[test]
+fn test_capability_kinds() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let dir = scratch_dir("kinds");
+
+    // storage under any id is still storage
+    let thread = alloc_body_thread(&mem, &[io(OP_WRITE, 9)], &pair(text("x"), text("out")));
+    let mut caps = Capabilities::new();
+    caps.grant_kind(CHANNEL_STORAGE, Access::ReadWrite);
+    thread.attach_channel(9, Box::new(Storage::open(&dir).unwrap()));
+    thread.restrict(caps.clone());
+    thread.run(&mem).unwrap();
+    assert!(fs::read(dir.join("out")).unwrap() == b"x");
+
+    // and undoing the write needs the same access as doing it
+    caps.grant_id(9, Access::Read);
+    caps.grant_kind(CHANNEL_STORAGE, Access::Read);
+    thread.restrict(caps);
+    thread.reverse(&mem);
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::ChannelDenied(9));
+    assert!(dir.join("out").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// a function value running body between START and END
+fn plugin(body: &[DecodedInstr]) -> Value {
+    function_to_value(&function(body))
+}
+
+fn swap_code() -> Box<Code> {
+    Box::new(Code::new(
+        IType::prod(IType::Nat, IType::Unit),
+        IType::prod(IType::Unit, IType::Nat),
+    ))
+}
+
+#[test]
+fn test_code_channel() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let call = pair(plugin(&[op(OP_SWAPP)]), pair(Value::Nat(5), Value::Unit));
+    let arg_ty = IType::prod(function_type(), IType::prod(IType::Nat, IType::Unit));
+
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CODE)], &call);
+    thread.attach_channel(CHANNEL_CODE, swap_code());
+    thread.run(&mem).unwrap();
+
+    let ty = IType::prod(IType::prod(IType::Unit, IType::Nat), arg_ty.clone());
+    let result = Value::from_heap(&mem, &ty, thread.data().get(&mem)).unwrap();
+    assert!(result == pair(pair(Value::Unit, Value::Nat(5)), call.clone()));
+
+    // giving the output back checks it against another run
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    assert!(Value::from_heap(&mem, &arg_ty, thread.data().get(&mem)).unwrap() == call);
+
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, CHANNEL_CODE)],
+        &pair(pair(Value::Unit, Value::Nat(6)), call),
+    );
+    thread.attach_channel(CHANNEL_CODE, swap_code());
+    assert!(matches!(thread.run(&mem).unwrap_err().error_kind(), ErrorKind::IOError(_)));
+}
+
+#[test]
+fn test_code_checks() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let input = pair(Value::Nat(5), Value::Unit);
+    let run = |function: Value, code: Box<Code>| {
+        let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CODE)], &pair(function, input.clone()));
+        thread.attach_channel(CHANNEL_CODE, code);
+        thread.run(&mem).unwrap_err()
+    };
+
+    // the output isn't what the channel promised
+    assert!(*run(plugin(&[]), swap_code()).error_kind() == ErrorKind::TypeError);
+
+    // a sum combinator with no end
+    let broken = plugin(&[DecodedInstr::with_pair(encode_i(OP_SUMS, 1).unwrap(), 0, 0)]);
+    assert!(*run(broken, swap_code()).error_kind() == ErrorKind::TypeError);
+
+    // a nat where ASSRP expects a product
+    let nats = IType::prod(IType::Nat, IType::Nat);
+    let assrp = plugin(&[op(OP_ASSRP)]);
+    let thread = alloc_body_thread(
+        &mem,
+        &[io(OP_READ, CHANNEL_CODE)],
+        &pair(assrp, pair(Value::Nat(1), Value::Nat(2))),
+    );
+    thread.attach_channel(CHANNEL_CODE, Box::new(Code::new(nats.clone(), nats)));
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::TypeError);
+
+    // plugins get no channels of their own
+    let console = plugin(&[io(OP_READ, CHANNEL_CONSOLE), op(OP_SWAPP)]);
+    assert!(*run(console, swap_code()).error_kind() == ErrorKind::ChannelDenied(CHANNEL_CONSOLE));
+
+    let mut disabled = swap_code();
+    disabled.set_enabled(false);
+    assert!(*run(plugin(&[op(OP_SWAPP)]), disabled).error_kind() == ErrorKind::ChannelDenied(CHANNEL_CODE));
+
+    assert!(value_to_function(&function_to_value(&[op(OP_SWAPP)])).unwrap() == vec![op(OP_SWAPP)]);
+}
+
+#[test]
+fn test_code_fuel() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    // 1 + n <-> n + 0 never gives anything on the right, so the trace
+    // goes round forever counting up
+    let body = [op_s(OP_SWAPS, 1, 1), fold(), inl()].concat();
+    let forever = function_to_value(&assemble(vec![("main", trace(body))]).unwrap().instrs);
+
+    let mut code = Box::new(Code::new(IType::Unit, IType::Unit));
+    code.set_fuel(1000);
+    assert!(code.fuel() == 1000);
+
+    let thread = alloc_body_thread(&mem, &[io(OP_READ, CHANNEL_CODE)], &pair(forever, Value::Unit));
+    thread.attach_channel(CHANNEL_CODE, code);
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::OutOfFuel);
+}
+
+#[test]
+fn test_delete() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = IType::prod(IType::Nat, IType::ind(IType::Nat));
+    let value = pair(Value::Nat(7), text("ab"));
+    let delete = |policy| {
+        let thread = alloc_body_thread(&mem, &[io(OP_WRITE, CHANNEL_DELETE)], &pair(value.clone(), Value::Unit));
+        thread.attach_channel(CHANNEL_DELETE, Box::new(Delete::new(ty.clone(), policy)));
+        thread
+    };
+
+    // a nat, then a list of two nats with its length
+    let thread = delete(ErasurePolicy::Allow);
+    thread.run(&mem).unwrap();
+    assert!(thread.erased_bits() == 32 + 32 * 3);
+    assert!(Value::from_heap(&mem, &IType::Unit, thread.data().get(&mem)).unwrap() == Value::Unit);
+
+    thread.reverse(&mem);
+    assert!(matches!(thread.run(&mem).unwrap_err().error_kind(), ErrorKind::IOError(_)));
+
+    // journaled erasure can be taken back
+    let thread = delete(ErasurePolicy::Journal);
+    thread.run(&mem).unwrap();
+    assert!(thread.erased_bits() == 128);
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    assert!(thread.erased_bits() == 0);
+    let restored = Value::from_heap(&mem, &IType::prod(ty.clone(), IType::Unit), thread.data().get(&mem)).unwrap();
+    assert!(restored == pair(value.clone(), Value::Unit));
+
+    let thread = delete(ErasurePolicy::Forbid);
+    assert!(*thread.run(&mem).unwrap_err().error_kind() == ErrorKind::ErasureForbidden);
+    assert!(thread.erased_bits() == 0);
+}
+
+#[test]
+fn test_erased_bits() {
+    let three = IType::sum(IType::Unit, IType::sum(IType::Nat, IType::Unit));
+    // a bit for each sum the value is in
+    assert!(bits(&three, &Value::Sum(0, Box::new(Value::Unit))).unwrap() == 1);
+    assert!(bits(&three, &Value::Sum(1, Box::new(Value::Sum(0, Box::new(Value::Nat(1)))))).unwrap() == 34);
+    assert!(bits(&three, &Value::Sum(1, Box::new(Value::Nat(1)))).is_err());
+    assert!(bits(&IType::sum(IType::Unit, IType::Unit), &Value::Sum(1, Box::new(Value::Unit))).unwrap() == 1);
+    assert!(bits(&IType::Unit, &Value::Unit).unwrap() == 0);
+    assert!(bits(&IType::Nat, &Value::Unit).is_err());
+}
+
+#[test]
+fn test_loopback() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let (a, b) = Loopback::pair(IType::ind(IType::Nat));
+
+    let sender = alloc_body_thread(&mem, &[io(OP_WRITE, 6)], &pair(text("ping"), Value::Unit));
+    let receiver = alloc_body_thread(&mem, &[io(OP_READ, 6)], &Value::Unit);
+    sender.attach_channel(6, Box::new(a));
+    receiver.attach_channel(6, Box::new(b.clone()));
+
+    sender.run(&mem).unwrap();
+    assert!(b.pending() == 1);
+    receiver.run(&mem).unwrap();
+    assert!(b.pending() == 0);
+    assert!(Value::from_heap(&mem, &line_ty(), receiver.data().get(&mem)).unwrap() == pair(text("ping"), Value::Unit));
+
+    // the sender can't take it back until the receiver gives it up
+    sender.reverse(&mem);
+    assert!(matches!(sender.run(&mem).unwrap_err().error_kind(), ErrorKind::IOError(_)));
+
+    receiver.reverse(&mem);
+    receiver.run(&mem).unwrap();
+    assert!(b.pending() == 1);
+    sender.run(&mem).unwrap();
+    assert!(b.pending() == 0);
+    assert!(Value::from_heap(&mem, &line_ty(), sender.data().get(&mem)).unwrap() == pair(text("ping"), Value::Unit));
+}
+
+#[test]
+fn test_scripted() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let echo = [io(OP_READ, CHANNEL_CONSOLE), io(OP_WRITE, CHANNEL_CONSOLE)];
+
+    let script = Scripted::new(IType::ind(IType::Nat));
+    script.queue_read(text("hi"));
+    script.expect_write(text("hi"));
+    let thread = alloc_body_thread(&mem, &echo, &Value::Unit);
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(script.clone()));
+    thread.run(&mem).unwrap();
+    assert!(script.finished());
+
+    // and the script winds back with the thread
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    assert!(!script.finished());
+
+    let script = Scripted::new(IType::ind(IType::Nat));
+    script.queue_read(text("hi"));
+    script.expect_write(text("ho"));
+    let thread = alloc_body_thread(&mem, &echo, &Value::Unit);
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(script.clone()));
+    assert!(matches!(thread.run(&mem).unwrap_err().error_kind(), ErrorKind::IOError(_)));
+    assert!(!script.finished());
+}
+
+#[test]
+fn test_capture() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let echo = [io(OP_READ, CHANNEL_CONSOLE), io(OP_WRITE, CHANNEL_CONSOLE)];
+
+    let script = Scripted::new(IType::ind(IType::Nat));
+    script.queue_read(text("hi"));
+    script.expect_write(text("hi"));
+    let capture = Capture::new(Box::new(script));
+    let thread = alloc_body_thread(&mem, &echo, &Value::Unit);
+    thread.attach_channel(CHANNEL_CONSOLE, Box::new(capture.clone()));
+    thread.run(&mem).unwrap();
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    assert!(capture.to_string() == "\
+read fwd i2 n105 n104
+write fwd i2 n105 n104
+read undo i2 n105 n104
+write undo i2 n105 n104
+");
+
+    // a sink keeps what it's given
+    let sink = Capture::sink(IType::Nat);
+    let thread = alloc_body_thread(&mem, &[io(OP_WRITE, 6)], &pair(Value::Nat(3), Value::Unit));
+    thread.attach_channel(6, Box::new(sink.clone()));
+    thread.run(&mem).unwrap();
+    assert!(sink.entries() == vec![Captured { read: false, undo: false, value: Value::Nat(3) }]);
+}
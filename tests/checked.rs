@@ -0,0 +1,132 @@
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{HeapMode, HeapSite, Memory, MutatorView};
+use iris::safeptr::*;
+use iris::vm::Thread;
+
+mod common;
+use common::*;
+
+#[test]
+fn test_double_free() {
+    let binding = Memory::with_mode(HeapMode::Checked);
+    let mem = MutatorView::new(&binding);
+
+    let nat = mem.alloc(1337 as u32).unwrap();
+    mem.dealloc(nat).unwrap();
+
+    match mem.dealloc(nat) {
+        Err(e) => match e.error_kind() {
+            ErrorKind::DoubleFree(fault) => {
+                assert!(fault.alloc_site == None);
+                assert!(fault.free_site == None);
+            },
+            _ => panic!("expected double free, got {}", e),
+        },
+        Ok(_) => panic!("double free went undetected"),
+    }
+}
+
+#[test]
+fn test_poison_freed_memory() {
+    let binding = Memory::with_mode(HeapMode::Checked);
+    let mem = MutatorView::new(&binding);
+
+    let nat = mem.alloc(1337 as u32).unwrap();
+    let raw = nat.as_rawptr(&mem);
+    mem.dealloc(nat).unwrap();
+
+    assert!(*raw.as_ref() == 0xDEDEDEDE);
+    assert!(mem.check_access(nat).is_err());
+
+    // freed space is quarantined rather than reused
+    let other = mem.alloc(420 as u32).unwrap();
+    assert!(other.as_rawptr(&mem) != raw);
+}
+
+#[test]
+fn test_unchecked_mode() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let nat = mem.alloc(1337 as u32).unwrap();
+    mem.dealloc(nat).unwrap();
+
+    assert!(mem.check_access(nat).is_ok());
+}
+
+#[test]
+fn test_use_after_free_in_vm() {
+    let binding = Memory::with_mode(HeapMode::Checked);
+    let mem = MutatorView::new(&binding);
+    let test_fn = Function::alloc(&mem).unwrap();
+
+    push_op(&mem, test_fn, OP_START);
+    push_op(&mem, test_fn, OP_ZEROE);
+    push_op(&mem, test_fn, OP_END);
+
+    let sum = mem.alloc(Sum::new(
+        1,
+        CellPtr::new_with(mem.alloc(1337 as u32).unwrap())
+    )).unwrap();
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(sum.as_untyped(&mem)),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+    thread.run(&mem).unwrap();
+
+    // ZEROE freed the sum, so running the thread again on it must fail
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(sum.as_untyped(&mem)),
+    )).unwrap();
+    let stale = Thread::alloc_with_arg(&mem, arg).unwrap();
+
+    match stale.run(&mem) {
+        Err(e) => match e.error_kind() {
+            ErrorKind::UseAfterFree(fault) => {
+                assert!(fault.alloc_site == None);
+                assert!(fault.free_site == Some(HeapSite { opcode: OP_ZEROE, ip: 1 }));
+            },
+            _ => panic!("expected use after free, got {}", e),
+        },
+        Ok(_) => panic!("use after free went undetected"),
+    }
+}
+
+#[test]
+fn test_use_after_free_in_field() {
+    let binding = Memory::with_mode(HeapMode::Checked);
+    let mem = MutatorView::new(&binding);
+    let test_fn = Function::alloc(&mem).unwrap();
+
+    push_op(&mem, test_fn, OP_START);
+    push_op(&mem, test_fn, OP_SWAPP);
+    push_op(&mem, test_fn, OP_END);
+
+    // the product itself is live, only its second half is stale
+    let snd = mem.alloc(69 as u32).unwrap();
+    let prod = mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(420 as u32).unwrap()),
+        CellPtr::new_with(snd),
+    )).unwrap();
+    mem.dealloc(snd).unwrap();
+
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(prod.as_untyped(&mem)),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+
+    match thread.run(&mem) {
+        Err(e) => match e.error_kind() {
+            ErrorKind::UseAfterFree(_) => {},
+            _ => panic!("expected use after free, got {}", e),
+        },
+        Ok(_) => panic!("use after free went undetected"),
+    }
+}
+
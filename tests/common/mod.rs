@@ -0,0 +1,110 @@
+#![allow(dead_code)]
+
+use iris::array::StackContainer;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::{Nat, Product, Sum};
+use iris::memory::MutatorView;
+use iris::safeptr::*;
+use iris::value::Value;
+use iris::vm::Thread;
+
+/*
+ * Helpers shared by the integration tests, each of which uses only some
+ * of them
+ */
+pub fn op(op: u8) -> DecodedInstr {
+    DecodedInstr::new(encode_i(op, 0).unwrap())
+}
+
+// an instruction with an immediate, such as READ/WRITE's channel id
+pub fn io(op: u8, imm: Nat) -> DecodedInstr {
+    DecodedInstr::new(encode_i(op, imm).unwrap())
+}
+
+// a function of just `body`, between START and END
+pub fn function(body: &[DecodedInstr]) -> Vec<DecodedInstr> {
+    let mut instrs = vec![op(OP_START)];
+    instrs.extend_from_slice(body);
+    instrs.push(op(OP_END));
+    instrs
+}
+
+pub fn push_op(mem: &MutatorView, func: ScopedPtr<'_, Function>, op: u8) {
+    let instr = alloc_instr(mem, encode_i(op, 0).unwrap()).unwrap();
+    func.push(mem, CellPtr::new_with(instr)).unwrap();
+}
+
+// a thread about to run `function` on `data`
+pub fn alloc_function_thread<'guard>(
+    mem: &'guard MutatorView,
+    function: ScopedPtr<'guard, Function>,
+    data: UntypedScopedPtr<'guard>,
+) -> ScopedPtr<'guard, Thread> {
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(function),
+        CellPtr::new_with(data),
+    )).unwrap();
+
+    Thread::alloc_with_arg(mem, arg).unwrap()
+}
+
+// a thread about to run `instrs` on `data`
+pub fn alloc_thread<'guard>(
+    mem: &'guard MutatorView,
+    instrs: &[DecodedInstr],
+    data: UntypedScopedPtr<'guard>,
+) -> ScopedPtr<'guard, Thread> {
+    alloc_function_thread(mem, alloc_function(mem, instrs).unwrap(), data)
+}
+
+pub fn alloc_thread_with<'guard>(
+    mem: &'guard MutatorView,
+    instrs: &[DecodedInstr],
+    input: &Value,
+) -> ScopedPtr<'guard, Thread> {
+    alloc_thread(mem, instrs, input.alloc(mem).unwrap())
+}
+
+// a thread about to run a function of just `body` on `input`
+pub fn alloc_body_thread<'guard>(
+    mem: &'guard MutatorView,
+    body: &[DecodedInstr],
+    input: &Value,
+) -> ScopedPtr<'guard, Thread> {
+    alloc_thread_with(mem, &function(body), input)
+}
+
+/* Test Data */
+pub fn nat(n: u32) -> Value { Value::Nat(n) }
+
+pub fn pair(a: Value, b: Value) -> Value {
+    Value::Product(Box::new(a), Box::new(b))
+}
+
+// fixed inputs allocated straight onto the heap, for tests which run
+// the same program on several of them
+pub type AllocData = for<'guard> fn(&'guard MutatorView) -> UntypedScopedPtr<'guard>;
+
+pub fn alloc_nat<'guard>(mem: &'guard MutatorView) -> UntypedScopedPtr<'guard> {
+    mem.alloc(1337 as Nat).unwrap().as_untyped(mem)
+}
+
+pub fn alloc_left<'guard>(mem: &'guard MutatorView) -> UntypedScopedPtr<'guard> {
+    mem.alloc(Sum::new(0, CellPtr::new_with(mem.alloc(4 as Nat).unwrap())))
+        .unwrap()
+        .as_untyped(mem)
+}
+
+pub fn alloc_right<'guard>(mem: &'guard MutatorView) -> UntypedScopedPtr<'guard> {
+    mem.alloc(Sum::new(1, CellPtr::new_with(mem.alloc(20 as Nat).unwrap())))
+        .unwrap()
+        .as_untyped(mem)
+}
+
+pub fn alloc_pair<'guard>(mem: &'guard MutatorView) -> UntypedScopedPtr<'guard> {
+    mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(420 as Nat).unwrap()),
+        CellPtr::new_with(mem.alloc(69 as Nat).unwrap()),
+    )).unwrap().as_untyped(mem)
+}
@@ -0,0 +1,371 @@
+use iris::array::ArraySize;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::context::{Context, ContextStack, CHAIN_LIMIT, MAX_DEPTH};
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{Memory, MutatorView};
+use iris::safeptr::*;
+use iris::vm::{EvalStatus, Thread};
+
+mod common;
+use common::*;
+
+fn addr<T>(mem: &MutatorView, ptr: &CellPtr<T>) -> usize {
+    ptr.get(mem).as_rawptr(mem).as_word()
+}
+
+#[test]
+fn test_round_trip() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let unit = mem.alloc(Unit::new()).unwrap().as_untyped(&mem);
+    let prod = mem.alloc(Product::new(
+        CellPtr::new_with(unit),
+        CellPtr::new_with(unit),
+    )).unwrap();
+    let sum = mem.alloc(Sum::new(1, CellPtr::new_with(unit))).unwrap();
+    let prod: ScopedPtr<Product<(), ()>> = unsafe { prod.as_untyped(&mem).cast(&mem) };
+    let sum: ScopedPtr<Sum<()>> = unsafe { sum.as_untyped(&mem).cast(&mem) };
+
+    let cxts = ContextStack::alloc_with_capacity(&mem, 16).unwrap();
+    cxts.push(&mem, Context::Nil).unwrap();
+    cxts.push(&mem, Context::Call { not: true, ret: u32::MAX }).unwrap();
+    cxts.push(&mem, Context::First {
+        snd_op_index: (1 << 31) - 1,
+        snd_val: CellPtr::new_with(unit),
+        root_val: CellPtr::new_with(prod),
+    }).unwrap();
+    cxts.push(&mem, Context::Right {
+        left_op_index: 42,
+        jump: (1 << 30) - 1,
+        root_val: CellPtr::new_with(sum),
+    }).unwrap();
+    cxts.push(&mem, Context::Second {
+        fst_op_index: 7,
+        fst_val: CellPtr::new_with(unit),
+        root_val: CellPtr::new_with(prod),
+    }).unwrap();
+    cxts.push(&mem, Context::Left {
+        right_op_index: 3,
+        jump: 9,
+        root_val: CellPtr::new_with(sum),
+    }).unwrap();
+
+    // tagged words plus a root pointer per combinator
+    assert!(cxts.size() == 6 + 4);
+
+    let unit_addr = unit.as_rawptr(&mem).as_word();
+    let prod_addr = prod.as_rawptr(&mem).as_word();
+    let sum_addr = sum.as_rawptr(&mem).as_word();
+
+    match cxts.top(&mem).unwrap() {
+        Context::Left { right_op_index: 3, jump: 9, root_val } => {
+            assert!(addr(&mem, &root_val) == sum_addr);
+        },
+        _ => panic!("expected a Left context"),
+    }
+    match cxts.pop(&mem).unwrap() {
+        Context::Left { right_op_index: 3, jump: 9, .. } => {},
+        _ => panic!("expected a Left context"),
+    }
+    match cxts.pop(&mem).unwrap() {
+        Context::Second { fst_op_index: 7, fst_val, root_val } => {
+            assert!(addr(&mem, &fst_val) == unit_addr);
+            assert!(addr(&mem, &root_val) == prod_addr);
+        },
+        _ => panic!("expected a Second context"),
+    }
+    match cxts.pop(&mem).unwrap() {
+        Context::Right { left_op_index: 42, jump, root_val } => {
+            assert!(jump == (1 << 30) - 1);
+            assert!(addr(&mem, &root_val) == sum_addr);
+        },
+        _ => panic!("expected a Right context"),
+    }
+    match cxts.pop(&mem).unwrap() {
+        Context::First { snd_op_index, snd_val, root_val } => {
+            assert!(snd_op_index == (1 << 31) - 1);
+            assert!(addr(&mem, &snd_val) == unit_addr);
+            assert!(addr(&mem, &root_val) == prod_addr);
+        },
+        _ => panic!("expected a First context"),
+    }
+    match cxts.pop(&mem).unwrap() {
+        Context::Call { not: true, ret } => assert!(ret == u32::MAX),
+        _ => panic!("expected a Call context"),
+    }
+    assert!(matches!(cxts.pop(&mem).unwrap(), Context::Nil));
+    assert!(cxts.size() == 0);
+
+    match cxts.pop(&mem) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::BoundsError),
+        Ok(_) => panic!("popped from an empty context stack"),
+    }
+}
+
+#[test]
+fn test_field_overflow() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let unit = mem.alloc(Unit::new()).unwrap().as_untyped(&mem);
+    let sum = mem.alloc(Sum::new(0, CellPtr::new_with(unit))).unwrap();
+    let sum: ScopedPtr<Sum<()>> = unsafe { sum.as_untyped(&mem).cast(&mem) };
+
+    let cxts = ContextStack::alloc_with_capacity(&mem, 16).unwrap();
+    let overflows = [
+        Context::Left {
+            right_op_index: 1 << 31,
+            jump: 0,
+            root_val: CellPtr::new_with(sum),
+        },
+        Context::Right {
+            left_op_index: 0,
+            jump: 1 << 30,
+            root_val: CellPtr::new_with(sum),
+        },
+        Context::First {
+            snd_op_index: 1 << 31,
+            snd_val: CellPtr::new_with(unit),
+            root_val: unsafe { CellPtr::new_with(unit.cast(&mem)) },
+        },
+    ];
+
+    for cxt in overflows {
+        match cxts.push(&mem, cxt) {
+            Err(e) => assert!(*e.error_kind() == ErrorKind::IntOverflow),
+            Ok(_) => panic!("truncated a context field"),
+        }
+        // nothing is left behind by a rejected push
+        assert!(cxts.size() == 0);
+    }
+}
+
+#[test]
+fn test_value_outside_root() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let unit = mem.alloc(Unit::new()).unwrap().as_untyped(&mem);
+    let other = mem.alloc(Unit::new()).unwrap().as_untyped(&mem);
+    let prod = mem.alloc(Product::new(
+        CellPtr::new_with(unit),
+        CellPtr::new_with(unit),
+    )).unwrap();
+    let prod: ScopedPtr<Product<(), ()>> = unsafe { prod.as_untyped(&mem).cast(&mem) };
+
+    // only the root is stored, so a value which isn't its field can't
+    // be pushed
+    let cxts = ContextStack::alloc_with_capacity(&mem, 16).unwrap();
+    let cxt = Context::Second {
+        fst_op_index: 0,
+        fst_val: CellPtr::new_with(other),
+        root_val: CellPtr::new_with(prod),
+    };
+
+    match cxts.push(&mem, cxt) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::BadContext),
+        Ok(_) => panic!("pushed a value the root doesn't hold"),
+    }
+    assert!(cxts.size() == 0);
+}
+
+fn alloc_recursive<'guard>(mem: &'guard MutatorView) -> ScopedPtr<'guard, Thread> {
+    // a function which does nothing but call itself, with an ID after the
+    // call so that it isn't a tail call
+    alloc_thread(mem, &[
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 0, 3),
+        op(OP_ID),
+        op(OP_END),
+    ], mem.alloc(Unit::new()).unwrap().as_untyped(mem))
+}
+
+#[test]
+fn test_depth_limit() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_recursive(&mem);
+
+    assert!(thread.context_depth(&mem) == 1);
+    thread.set_max_depth(&mem, 8);
+
+    match thread.run(&mem) {
+        Err(e) => match e.error_kind() {
+            // every call but the one which overflowed is still in progress
+            ErrorKind::StackOverflow(chain) => {
+                assert!(chain.len() == 7);
+                assert!(chain.iter().all(|ret| *ret == chain[0]));
+            },
+            _ => panic!("expected a stack overflow, got {}", e),
+        },
+        Ok(_) => panic!("unbounded recursion returned"),
+    }
+    assert!(thread.context_depth(&mem) == 8);
+
+    // deeper recursion only reports the innermost calls
+    let thread = alloc_recursive(&mem);
+    thread.set_max_depth(&mem, 64);
+
+    match thread.run(&mem) {
+        Err(e) => match e.error_kind() {
+            ErrorKind::StackOverflow(chain) => assert!(chain.len() == CHAIN_LIMIT),
+            _ => panic!("expected a stack overflow, got {}", e),
+        },
+        Ok(_) => panic!("unbounded recursion returned"),
+    }
+}
+
+#[test]
+fn test_default_depth_limit() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_recursive(&mem);
+
+    match thread.run(&mem) {
+        Err(e) => assert!(matches!(e.error_kind(), ErrorKind::StackOverflow(_)), "{}", e),
+        Ok(_) => panic!("unbounded recursion returned"),
+    }
+    assert!(thread.context_depth(&mem) == MAX_DEPTH);
+}
+
+#[test]
+fn test_combinator_depth_limit() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    // products nested deeper than the limit, each working on a unit
+    // split off the one before; a context per level is the same limit a
+    // recursive call hits
+    let max = 512;
+    let mut body = vec![op(OP_ID)];
+    for _ in 0..max + 8 {
+        let jmp = body.len() as Nat + 1;
+        let mut level = vec![
+            op(OP_UNITI),
+            DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), jmp),
+        ];
+        level.extend(body);
+        level.extend([
+            op(OP_ID),
+            DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 2),
+            op(OP_UNITE),
+        ]);
+        body = level;
+    }
+    let mut instrs = vec![op(OP_START)];
+    instrs.extend(body);
+    instrs.push(op(OP_END));
+
+    let unit = mem.alloc(Unit::new()).unwrap().as_untyped(&mem);
+    let thread = alloc_thread(&mem, &instrs, unit);
+    thread.set_max_depth(&mem, max);
+
+    match thread.run(&mem) {
+        Err(e) => assert!(matches!(e.error_kind(), ErrorKind::StackOverflow(_)), "{}", e),
+        Ok(_) => panic!("nested past the limit"),
+    }
+    assert!(thread.context_depth(&mem) == max);
+}
+
+#[test]
+fn test_max_depth_clamped() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let unit = mem.alloc(Unit::new()).unwrap().as_untyped(&mem);
+    let sum = mem.alloc(Sum::new(0, CellPtr::new_with(unit))).unwrap();
+    let sum: ScopedPtr<Sum<()>> = unsafe { sum.as_untyped(&mem).cast(&mem) };
+
+    // however the stack's array grows, two-word contexts overflow the
+    // clamped limit rather than the heap block
+    for capacity in [16, 256, 2730] {
+        let cxts = ContextStack::alloc_with_capacity(&mem, capacity).unwrap();
+        cxts.set_max_depth(10_000);
+        assert!(cxts.max_depth() == MAX_DEPTH);
+
+        let result = (0..=MAX_DEPTH).try_for_each(|_| cxts.push(&mem, Context::Left {
+            right_op_index: 0,
+            jump: 0,
+            root_val: CellPtr::new_with(sum),
+        }));
+        match result {
+            Err(e) => assert!(matches!(e.error_kind(), ErrorKind::StackOverflow(_)), "{}", e),
+            Ok(_) => panic!("pushed past the maximum depth"),
+        }
+        assert!(cxts.depth() == MAX_DEPTH);
+    }
+}
+
+// steps through a thread until it finishes, returning the deepest the
+// context stack got
+fn run_max_depth(mem: &MutatorView, thread: ScopedPtr<'_, Thread>) -> ArraySize {
+    let mut max = thread.context_depth(mem);
+    while thread.eval_next_instr(mem).unwrap() == EvalStatus::Pending {
+        max = max.max(thread.context_depth(mem));
+    }
+    max
+}
+
+#[test]
+fn test_tail_calls() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    // the entry calls f, which uncalls g in tail position, which calls
+    // h in tail position; h adds a unit, so the whole program removes one
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 4, 6),
+        op(OP_ID),
+        op(OP_END),
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_UNCALL, 0).unwrap(), 7, 9),
+        op(OP_END),
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 10, 12),
+        op(OP_END),
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_END),
+    ];
+    let input = mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(Unit::new()).unwrap().as_untyped(&mem)),
+        CellPtr::new_with(mem.alloc(1337 as Nat).unwrap().as_untyped(&mem)),
+    )).unwrap();
+    let thread = alloc_thread(&mem, &instrs, input.as_untyped(&mem));
+
+    // f, g and h all share one context
+    assert!(run_max_depth(&mem, thread) == 2);
+    assert!(thread.context_depth(&mem) == 1);
+    let output = unsafe { thread.data().get(&mem).cast::<Nat>(&mem) };
+    assert!(*output.as_ref(&mem) == 1337);
+
+    thread.reverse(&mem);
+    assert!(run_max_depth(&mem, thread) == 2);
+    let output = unsafe {
+        thread.data().get(&mem).cast::<Product<Unit, Nat>>(&mem)
+    };
+    assert!(*output.snd(&mem) == 1337);
+}
+
+#[test]
+fn test_tail_recursion() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    // calls itself forever, but never needs more than one call context
+    let thread = alloc_thread(&mem, &[
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 0, 2),
+        op(OP_END),
+    ], mem.alloc(Unit::new()).unwrap().as_untyped(&mem));
+    thread.set_max_depth(&mem, 2);
+
+    for _ in 0..10000 {
+        assert!(thread.eval_next_instr(&mem).unwrap() == EvalStatus::Pending);
+    }
+    assert!(thread.context_depth(&mem) == 2);
+}
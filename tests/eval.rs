@@ -0,0 +1,181 @@
+use iris::array::StackContainer;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{Memory, MutatorView};
+use iris::op::expn;
+use iris::safeptr::*;
+
+mod common;
+use common::*;
+
+fn push_nat(mem: &MutatorView, func: ScopedPtr<'_, Function>, op: u8, nat: Nat) {
+    let instr = alloc_instr_nat(mem, encode_i(op, 0).unwrap(), nat).unwrap();
+    func.push(mem, CellPtr::new_with(instr)).unwrap();
+}
+
+fn push_pair(mem: &MutatorView, func: ScopedPtr<'_, Function>, op: Opcode, fst: Nat, snd: Nat) {
+    let instr = alloc_instr_pair(mem, op, fst, snd).unwrap();
+    func.push(mem, CellPtr::new_with(instr)).unwrap();
+}
+
+fn nat<'guard>(mem: &'guard MutatorView, n: Nat) -> UntypedScopedPtr<'guard> {
+    mem.alloc(n).unwrap().as_untyped(mem)
+}
+
+#[test]
+fn test_call_and_return() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let func = Function::alloc(&mem).unwrap();
+
+    // the caller has an instruction after the call, which only runs if
+    // the call returns to the right place
+    push_op(&mem, func, OP_START);
+    push_pair(&mem, func, encode_i(OP_CALL, 0).unwrap(), 5, 7);
+    push_op(&mem, func, OP_ZEROI);
+    push_op(&mem, func, OP_END);
+    push_op(&mem, func, OP_ID);
+    push_op(&mem, func, OP_START);
+    push_op(&mem, func, OP_UNITI);
+    push_op(&mem, func, OP_END);
+
+    let thread = alloc_function_thread(&mem, func, nat(&mem, 1337));
+    thread.run(&mem).unwrap();
+
+    let sum = unsafe { thread.data().get(&mem).cast::<Sum<Product<Unit, Nat>>>(&mem) };
+    assert!(sum.tag() == 1);
+    assert!(*sum.data(&mem).snd(&mem) == 1337);
+
+    // the return popped the call, so the way back finds Nil again
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    assert!(*unsafe { thread.data().get(&mem).cast::<Nat>(&mem) } == 1337);
+}
+
+#[test]
+fn test_sum_combinator() {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+
+    for tag in 0..2 {
+        let binding = Memory::new();
+        let mem = MutatorView::new(&binding);
+        let func = Function::alloc(&mem).unwrap();
+
+        // one instruction on the left, two on the right
+        push_op(&mem, func, OP_START);
+        push_pair(&mem, func, sums, 1, 2);
+        push_op(&mem, func, OP_UNITI);
+        push_op(&mem, func, OP_ZEROI);
+        push_op(&mem, func, OP_ZEROI);
+        push_pair(&mem, func, sume, 1, 2);
+        push_op(&mem, func, OP_END);
+
+        let input = mem.alloc(Sum::new(tag, CellPtr::new_with(nat(&mem, 7)))).unwrap();
+        let thread = alloc_function_thread(&mem, func, input.as_untyped(&mem));
+        thread.run(&mem).unwrap();
+
+        let output = unsafe { thread.data().get(&mem).cast::<Sum<()>>(&mem) };
+        assert!(output.tag() == tag);
+        if tag == 0 {
+            let prod = unsafe { output.data(&mem).cast::<Product<Unit, Nat>>(&mem) };
+            assert!(*prod.snd(&mem) == 7);
+        } else {
+            let outer = unsafe { output.data(&mem).cast::<Sum<Sum<Nat>>>(&mem) };
+            assert!(outer.tag() == 1 && outer.data(&mem).tag() == 1);
+            assert!(*outer.data(&mem).data(&mem) == 7);
+        }
+
+        thread.reverse(&mem);
+        thread.run(&mem).unwrap();
+        let undone = unsafe { thread.data().get(&mem).cast::<Sum<Nat>>(&mem) };
+        assert!(undone.tag() == tag);
+        assert!(*undone.data(&mem) == 7);
+    }
+}
+
+#[test]
+fn test_product_combinator() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let func = Function::alloc(&mem).unwrap();
+
+    // one instruction on the first half, two on the second
+    push_op(&mem, func, OP_START);
+    push_nat(&mem, func, OP_PRODS, 2);
+    push_op(&mem, func, OP_UNITI);
+    push_op(&mem, func, OP_ZEROI);
+    push_op(&mem, func, OP_ZEROI);
+    push_nat(&mem, func, OP_PRODE, 3);
+    push_op(&mem, func, OP_END);
+
+    let input = mem.alloc(Product::new(
+        CellPtr::new_with(nat(&mem, 420)),
+        CellPtr::new_with(nat(&mem, 69)),
+    )).unwrap();
+    let thread = alloc_function_thread(&mem, func, input.as_untyped(&mem));
+    thread.run(&mem).unwrap();
+
+    let output = unsafe {
+        thread.data().get(&mem).cast::<Product<Product<Unit, Nat>, Sum<Sum<Nat>>>>(&mem)
+    };
+    assert!(*output.fst(&mem).snd(&mem) == 420);
+    assert!(*output.snd(&mem).data(&mem).data(&mem) == 69);
+
+    // backwards, the second half runs first and hands over to the first
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    let undone = unsafe { thread.data().get(&mem).cast::<Product<Nat, Nat>>(&mem) };
+    assert!(*undone.fst(&mem) == 420);
+    assert!(*undone.snd(&mem) == 69);
+}
+
+#[test]
+fn test_zeroe_rejects_left() {
+    for tag in 0..2 {
+        let binding = Memory::new();
+        let mem = MutatorView::new(&binding);
+        let func = Function::alloc(&mem).unwrap();
+
+        push_op(&mem, func, OP_START);
+        push_op(&mem, func, OP_ZEROE);
+        push_op(&mem, func, OP_END);
+
+        let input = mem.alloc(Sum::new(tag, CellPtr::new_with(nat(&mem, 7)))).unwrap();
+        let thread = alloc_function_thread(&mem, func, input.as_untyped(&mem));
+
+        // 0 + a only has values on the right
+        match thread.run(&mem) {
+            Ok(_) => {
+                assert!(tag == 1);
+                assert!(*unsafe { thread.data().get(&mem).cast::<Nat>(&mem) } == 7);
+            },
+            Err(e) => {
+                assert!(tag == 0);
+                assert!(*e.error_kind() == ErrorKind::ExpectedZero);
+            },
+        }
+    }
+}
+
+#[test]
+fn test_expn_round_trip() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let val = mem.alloc(Sum::new(1, CellPtr::new_with(nat(&mem, 7)))).unwrap();
+    let val = unsafe { val.cast::<Sum<()>>(&mem) };
+
+    // right v becomes left -v
+    let neg = expn(val, 0, &mem).unwrap();
+    assert!(neg.tag() == 0);
+
+    // and back again, freeing the negative it was wrapped in
+    let frees = mem.stats().frees;
+    let pos = expn(neg, 0, &mem).unwrap();
+    assert!(pos.tag() == 1);
+    assert!(*unsafe { pos.data(&mem).cast::<Nat>(&mem) } == 7);
+    assert!(mem.stats().frees == frees + 1);
+}
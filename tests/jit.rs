@@ -0,0 +1,320 @@
+#![cfg(feature = "jit")]
+
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::jit::Jit;
+use iris::memory::{HeapMode, HeapOptions, Memory, MutatorView};
+use iris::types::IType;
+use iris::value::structural_eq;
+
+mod common;
+use common::*;
+
+// runs a program forwards and then backwards, both in the interpreter
+// and as native code, checking that the results always agree
+fn assert_same(
+    instrs: &[DecodedInstr],
+    alloc_data: AllocData,
+    in_ty: &IType,
+    out_ty: &IType,
+) {
+    assert_same_in(Memory::new(), instrs, alloc_data, in_ty, out_ty)
+}
+
+fn assert_same_in(
+    binding: Memory,
+    instrs: &[DecodedInstr],
+    alloc_data: AllocData,
+    in_ty: &IType,
+    out_ty: &IType,
+) {
+    let mem = MutatorView::new(&binding);
+    let interp = alloc_thread(&mem, instrs, alloc_data(&mem));
+    let native = alloc_thread(&mem, instrs, alloc_data(&mem));
+
+    let mut jit = Jit::new().unwrap();
+    let function = native.continuation().get(&mem).function(&mem);
+    jit.compile(&mem, function).unwrap();
+    assert!(jit.is_compiled(&mem, function));
+
+    interp.count_allocs(true);
+    native.count_allocs(true);
+
+    interp.run(&mem).unwrap();
+    jit.run(&mem, &native).unwrap();
+    assert!(structural_eq(
+        &mem,
+        out_ty,
+        interp.data().get(&mem),
+        native.data().get(&mem)
+    ).unwrap());
+    assert!(interp.continuation().get(&mem).ip()
+            == native.continuation().get(&mem).ip());
+    assert!(interp.alloc_counts() == native.alloc_counts());
+    assert!(interp.steps() == native.steps());
+
+    interp.reverse(&mem);
+    native.reverse(&mem);
+
+    interp.run(&mem).unwrap();
+    jit.run(&mem, &native).unwrap();
+    assert!(structural_eq(
+        &mem,
+        in_ty,
+        interp.data().get(&mem),
+        native.data().get(&mem)
+    ).unwrap());
+    assert!(structural_eq(
+        &mem,
+        in_ty,
+        alloc_data(&mem),
+        native.data().get(&mem)
+    ).unwrap());
+}
+
+#[test]
+fn test_data_ops() {
+    let instrs = [
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_SWAPP),
+        op(OP_SWAPP),
+        op(OP_UNITE),
+        op(OP_ZEROI),
+        DecodedInstr::new(encode_i(OP_FOLD, FOLD_NAT).unwrap()),
+        op(OP_END),
+    ];
+
+    assert_same(&instrs, alloc_nat, &IType::Nat, &IType::Nat);
+}
+
+#[test]
+fn test_sum_combinator() {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(sums, 1, 2),
+        op(OP_UNITI),
+        op(OP_UNITI),
+        op(OP_SWAPP),
+        DecodedInstr::with_pair(sume, 1, 2),
+        op(OP_END),
+    ];
+
+    let in_ty = IType::sum(IType::Nat, IType::Nat);
+    let out_ty = IType::sum(
+        IType::prod(IType::Unit, IType::Nat),
+        IType::prod(IType::Nat, IType::Unit),
+    );
+
+    assert_same(&instrs, alloc_left, &in_ty, &out_ty);
+    assert_same(&instrs, alloc_right, &in_ty, &out_ty);
+}
+
+#[test]
+fn test_product_combinator() {
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), 2),
+        op(OP_UNITI),
+        op(OP_ZEROI),
+        op(OP_UNITI),
+        DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 3),
+        op(OP_END),
+    ];
+
+    let in_ty = IType::prod(IType::Nat, IType::Nat);
+    let out_ty = IType::prod(
+        IType::prod(IType::Unit, IType::Nat),
+        IType::prod(IType::Unit, IType::sum(IType::Zero, IType::Nat)),
+    );
+
+    assert_same(&instrs, alloc_pair, &in_ty, &out_ty);
+}
+
+#[test]
+fn test_calls() {
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 5, 7),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 5, 7),
+        op(OP_END),
+        op(OP_START),
+        op(OP_ZEROI),
+        op(OP_END),
+    ];
+
+    let out_ty = IType::sum(
+        IType::Zero,
+        IType::prod(IType::Unit, IType::sum(IType::Zero, IType::Nat)),
+    );
+
+    assert_same(&instrs, alloc_nat, &IType::Nat, &out_ty);
+}
+
+#[test]
+fn test_same_errors() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let instrs = [op(OP_START), op(OP_ZEROE), op(OP_END)];
+
+    let interp = alloc_thread(&mem, &instrs, alloc_nat(&mem));
+    let native = alloc_thread(&mem, &instrs, alloc_nat(&mem));
+
+    let mut jit = Jit::new().unwrap();
+    jit.compile(&mem, native.continuation().get(&mem).function(&mem)).unwrap();
+
+    let interp_err = interp.run(&mem).unwrap_err();
+    let native_err = jit.run(&mem, &native).unwrap_err();
+    assert!(interp_err.error_kind() == native_err.error_kind());
+}
+
+#[test]
+fn test_reject_unverified() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    // sum combinator with no matching SUME
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_SUMS, 1).unwrap(), 1, 1),
+        op(OP_ID),
+        op(OP_ID),
+        op(OP_END),
+    ];
+    let thread = alloc_thread(&mem, &instrs, alloc_nat(&mem));
+
+    let mut jit = Jit::new().unwrap();
+    let function = thread.continuation().get(&mem).function(&mem);
+
+    match jit.compile(&mem, function) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::TypeError),
+        Ok(_) => panic!("compiled an invalid function"),
+    }
+    assert!(!jit.is_compiled(&mem, function));
+}
+
+#[test]
+fn test_interpreted_fallback() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let instrs = [op(OP_START), op(OP_ZEROI), op(OP_END)];
+
+    // nothing compiled, so everything runs through the interpreter
+    let jit = Jit::new().unwrap();
+    let thread = alloc_thread(&mem, &instrs, alloc_nat(&mem));
+    jit.run(&mem, &thread).unwrap();
+
+    let result = unsafe { thread.data().get(&mem).cast::<Sum<Nat>>(&mem) };
+    assert!(result.tag() == 1);
+    assert!(&1337 == result.data(&mem).as_ref(&mem));
+}
+
+#[test]
+fn test_lowered_ops() {
+    let swaps = encode_s(OP_SWAPS, 1, 1).unwrap();
+    let instrs = [
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_ASSLP),
+        op(OP_ASSRP),
+        op(OP_SWAPP),
+        op(OP_SWAPP),
+        op(OP_UNITE),
+        op(OP_ZEROI),
+        DecodedInstr::new(swaps),
+        DecodedInstr::new(swaps),
+        op(OP_ZEROE),
+        op(OP_ID),
+        op(OP_END),
+    ];
+    let ty = IType::prod(IType::Nat, IType::Nat);
+
+    // with and without headers to check, and with a checked heap,
+    // which always goes through the interpreter
+    for (mode, type_tags) in [
+        (HeapMode::Unchecked, true),
+        (HeapMode::Unchecked, false),
+        (HeapMode::Checked, true),
+    ] {
+        let binding = Memory::with_options(HeapOptions { mode, type_tags });
+        assert_same_in(binding, &instrs, alloc_pair, &ty, &ty);
+    }
+}
+
+#[test]
+fn test_lowered_errors() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    // a left injection has no way out of ZEROE
+    let instrs = [op(OP_START), op(OP_ZEROE), op(OP_END)];
+    let interp = alloc_thread(&mem, &instrs, alloc_left(&mem));
+    let native = alloc_thread(&mem, &instrs, alloc_left(&mem));
+
+    let mut jit = Jit::new().unwrap();
+    jit.compile(&mem, native.continuation().get(&mem).function(&mem)).unwrap();
+
+    let interp_err = interp.run(&mem).unwrap_err();
+    let native_err = jit.run(&mem, &native).unwrap_err();
+    assert!(*native_err.error_kind() == ErrorKind::ExpectedZero);
+    assert!(interp_err.error_kind() == native_err.error_kind());
+    assert!(interp.continuation().get(&mem).ip() == native.continuation().get(&mem).ip());
+}
+
+#[test]
+fn test_type_annotation() {
+    let binding = Memory::with_options(HeapOptions {
+        mode: HeapMode::Unchecked,
+        type_tags: true,
+    });
+    let mem = MutatorView::new(&binding);
+    let instrs = [op(OP_START), op(OP_SWAPP), op(OP_END)];
+    let ty = IType::prod(IType::Nat, IType::Nat);
+
+    let good = alloc_thread(&mem, &instrs, alloc_pair(&mem));
+    let bad = alloc_thread(&mem, &instrs, alloc_left(&mem));
+
+    let mut jit = Jit::new().unwrap();
+    let function = good.continuation().get(&mem).function(&mem);
+    jit.compile_typed(&mem, function, ty.clone(), ty.clone()).unwrap();
+    jit.compile_typed(&mem, bad.continuation().get(&mem).function(&mem), ty.clone(), ty)
+        .unwrap();
+
+    jit.run(&mem, &good).unwrap();
+
+    // the input is rejected before anything runs
+    let err = jit.run(&mem, &bad).unwrap_err();
+    assert!(*err.error_kind() == ErrorKind::TypeError);
+    assert!(bad.steps() == 0);
+}
+
+#[test]
+fn test_free_compiled() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 3, 5),
+        op(OP_END),
+        op(OP_START),
+        op(OP_ZEROI),
+        op(OP_END),
+    ];
+
+    let before = mem.stats();
+    let function = alloc_function(&mem, &instrs).unwrap();
+    let allocated = mem.stats();
+
+    let mut jit = Jit::new().unwrap();
+    jit.compile(&mem, function).unwrap();
+    jit.free(&mem, function).unwrap();
+
+    // nothing is left behind for a later function at the same address
+    assert!(!jit.is_compiled(&mem, function));
+    assert!(mem.stats().frees - allocated.frees == allocated.allocs() - before.allocs());
+}
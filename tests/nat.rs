@@ -0,0 +1,133 @@
+use iris::array::{Container, IndexedContainer, StackContainer};
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{Memory, MutatorView};
+use iris::safeptr::*;
+use iris::types::IType;
+use iris::value::structural_eq;
+
+mod common;
+use common::*;
+
+fn alloc_bignat<'guard>(mem: &'guard MutatorView, limbs: &[Nat])
+    -> ScopedPtr<'guard, BigNat>
+{
+    let nat = BigNat::alloc(mem).unwrap();
+    for limb in limbs {
+        nat.push(mem, *limb).unwrap();
+    }
+    nat
+}
+
+#[test]
+fn test_fold_overflow() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let data = mem.alloc(Sum::new(
+        1,
+        CellPtr::new_with(mem.alloc(Nat::MAX).unwrap())
+    )).unwrap();
+    let thread = alloc_thread(&mem, &function(&[io(OP_FOLD, FOLD_NAT)]), data.as_untyped(&mem));
+
+    match thread.run(&mem) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::IntOverflow),
+        Ok(_) => panic!("nat overflow went undetected"),
+    }
+}
+
+#[test]
+fn test_unfold_zero() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let data = mem.alloc(0 as Nat).unwrap();
+    let thread = alloc_thread(&mem, &function(&[io(OP_UFOLD, FOLD_NAT)]), data.as_untyped(&mem));
+    thread.run(&mem).unwrap();
+
+    let result = unsafe { thread.data().get(&mem).cast::<Sum<Unit>>(&mem) };
+    assert!(result.tag() == 0);
+
+    // folding the unit case back up gives zero again
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    let result = thread.data().get(&mem);
+    assert!(&0 == unsafe { result.cast::<Nat>(&mem) }.as_ref(&mem));
+}
+
+#[test]
+fn test_fold_nat() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let data = mem.alloc(Sum::new(
+        1,
+        CellPtr::new_with(mem.alloc(68 as Nat).unwrap())
+    )).unwrap();
+    let thread = alloc_thread(&mem, &function(&[io(OP_FOLD, FOLD_NAT)]), data.as_untyped(&mem));
+    thread.run(&mem).unwrap();
+
+    let result = thread.data().get(&mem);
+    assert!(&69 == unsafe { result.cast::<Nat>(&mem) }.as_ref(&mem));
+
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    let result = unsafe { thread.data().get(&mem).cast::<Sum<Nat>>(&mem) };
+    assert!(result.tag() == 1);
+    assert!(&68 == result.data(&mem).as_ref(&mem));
+}
+
+#[test]
+fn test_fold_bignat_past_u32() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let data = mem.alloc(Sum::new(
+        1,
+        CellPtr::new_with(alloc_bignat(&mem, &[Nat::MAX]))
+    )).unwrap();
+    let thread = alloc_thread(&mem, &function(&[io(OP_FOLD, FOLD_BIGNAT)]), data.as_untyped(&mem));
+    thread.run(&mem).unwrap();
+
+    // 2^32 - 1 + 1 carries into a second limb
+    let result = unsafe { thread.data().get(&mem).cast::<BigNat>(&mem) };
+    assert!(result.length() == 2);
+    assert!(result.get(&mem, 0).unwrap() == 0);
+    assert!(result.get(&mem, 1).unwrap() == 1);
+
+    // unfolding borrows back down and drops the empty limb
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    let result = unsafe { thread.data().get(&mem).cast::<Sum<BigNat>>(&mem) };
+    let expected = alloc_bignat(&mem, &[Nat::MAX]);
+    assert!(result.tag() == 1);
+    assert!(structural_eq(
+        &mem,
+        &IType::BigNat,
+        result.data(&mem).as_untyped(&mem),
+        expected.as_untyped(&mem)
+    ).unwrap());
+}
+
+#[test]
+fn test_unfold_bignat_zero() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let data = alloc_bignat(&mem, &[]);
+    let thread = alloc_thread(&mem, &function(&[io(OP_UFOLD, FOLD_BIGNAT)]), data.as_untyped(&mem));
+    thread.run(&mem).unwrap();
+
+    let result = unsafe { thread.data().get(&mem).cast::<Sum<Unit>>(&mem) };
+    assert!(result.tag() == 0);
+
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+
+    let result = unsafe { thread.data().get(&mem).cast::<BigNat>(&mem) };
+    assert!(result.length() == 0);
+}
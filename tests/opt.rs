@@ -0,0 +1,469 @@
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::{ErrorKind, RuntimeError};
+use iris::memory::{Memory, MutatorView};
+use iris::opt::{inline, optimize};
+use iris::safeptr::*;
+use iris::types::IType;
+use iris::value::structural_eq;
+
+mod common;
+use common::*;
+
+type Pass = fn(&[DecodedInstr]) -> Result<Vec<DecodedInstr>, RuntimeError>;
+
+fn op_s(op: u8, lc: u16, rc: u16) -> DecodedInstr {
+    DecodedInstr::new(encode_s(op, lc, rc).unwrap())
+}
+
+// round-trip harness: the optimized program must give the same result
+// as the original going forwards, and undo it going backwards
+fn assert_round_trip(
+    pass: Pass,
+    instrs: &[DecodedInstr],
+    alloc_data: AllocData,
+    in_ty: &IType,
+    out_ty: &IType,
+) -> Vec<DecodedInstr> {
+    let optimized = pass(instrs).unwrap();
+    verify_function(&optimized).unwrap();
+
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let original = alloc_thread(&mem, instrs, alloc_data(&mem));
+    let rewritten = alloc_thread(&mem, &optimized, alloc_data(&mem));
+
+    original.run(&mem).unwrap();
+    rewritten.run(&mem).unwrap();
+    assert!(structural_eq(
+        &mem,
+        out_ty,
+        original.data().get(&mem),
+        rewritten.data().get(&mem)
+    ).unwrap());
+
+    original.reverse(&mem);
+    rewritten.reverse(&mem);
+
+    original.run(&mem).unwrap();
+    rewritten.run(&mem).unwrap();
+    assert!(structural_eq(
+        &mem,
+        in_ty,
+        alloc_data(&mem),
+        rewritten.data().get(&mem)
+    ).unwrap());
+
+    optimized
+}
+
+fn alloc_three<'guard>(mem: &'guard MutatorView) -> UntypedScopedPtr<'guard> {
+    let inner = mem.alloc(Sum::new(1, CellPtr::new_with(mem.alloc(7 as Nat).unwrap()))).unwrap();
+    mem.alloc(Sum::new(1, CellPtr::new_with(inner))).unwrap().as_untyped(mem)
+}
+
+#[test]
+fn test_cancel_inverse_pairs() {
+    let instrs = [
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_SWAPP),
+        op(OP_SWAPP),
+        op(OP_UNITI),
+        op(OP_ASSLP),
+        op(OP_ASSRP),
+        op(OP_UNITE),
+        op(OP_ID),
+        op(OP_ZEROI),
+        op(OP_ASSRS),
+        op(OP_ZEROE),
+        op(OP_END),
+    ];
+    let ty = IType::prod(IType::Nat, IType::Nat);
+
+    let optimized = assert_round_trip(optimize, &instrs, alloc_pair, &ty, &ty);
+    assert!(optimized == [op(OP_START), op(OP_END)]);
+}
+
+#[test]
+fn test_cancel_cascades() {
+    let instrs = [
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_SWAPP),
+        op(OP_SWAPP),
+        op(OP_UNITE),
+        op(OP_ZEROI),
+        op(OP_END),
+    ];
+    let in_ty = IType::prod(IType::Nat, IType::Nat);
+    let out_ty = IType::sum(IType::Zero, in_ty.clone());
+
+    let optimized = assert_round_trip(optimize, &instrs, alloc_pair, &in_ty, &out_ty);
+    assert!(optimized == [op(OP_START), op(OP_ZEROI), op(OP_END)]);
+}
+
+#[test]
+fn test_cancel_sum_ops() {
+    let instrs = [
+        op(OP_START),
+        op_s(OP_SWAPS, 1, 1),
+        op_s(OP_SWAPS, 1, 1),
+        op(OP_UNITI),
+        op(OP_SWAPP),
+        op_s(OP_DIST, 1, 1),
+        op_s(OP_FACT, 1, 1),
+        op(OP_SWAPP),
+        op(OP_UNITE),
+        op(OP_END),
+    ];
+    let ty = IType::sum(IType::Nat, IType::sum(IType::Nat, IType::Nat));
+
+    let optimized = assert_round_trip(optimize, &instrs, alloc_three, &ty, &ty);
+    assert!(optimized == [op(OP_START), op(OP_END)]);
+
+    // swapping the same way twice is not the identity
+    let instrs = [
+        op(OP_START),
+        op_s(OP_SWAPS, 1, 2),
+        op_s(OP_SWAPS, 1, 2),
+        op(OP_END),
+    ];
+    assert!(optimize(&instrs).unwrap() == instrs);
+}
+
+#[test]
+fn test_collapse_combinators() {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let instrs = [
+        op(OP_START),
+        op(OP_ZEROI),
+        DecodedInstr::with_pair(sums, 2, 1),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_ID),
+        DecodedInstr::with_pair(sume, 2, 1),
+        op(OP_ZEROE),
+        DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), 3),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_ID),
+        DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 2),
+        op(OP_END),
+    ];
+    let ty = IType::prod(IType::Nat, IType::Nat);
+
+    let optimized = assert_round_trip(optimize, &instrs, alloc_pair, &ty, &ty);
+    assert!(optimized == [op(OP_START), op(OP_END)]);
+}
+
+#[test]
+fn test_retarget_jumps() {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let call = encode_i(OP_CALL, 0).unwrap();
+    let instrs = [
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        DecodedInstr::with_pair(sums, 3, 1),
+        op(OP_UNITI),
+        op(OP_ID),
+        op(OP_SWAPP),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 3, 1),
+        DecodedInstr::with_pair(call, 11, 16),
+        op(OP_END),
+        op(OP_START),
+        op(OP_ID),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_UNITI),
+        op(OP_END),
+    ];
+    let in_ty = IType::sum(IType::Nat, IType::Nat);
+    let out_ty = IType::prod(
+        IType::Unit,
+        IType::sum(
+            IType::prod(IType::Nat, IType::Unit),
+            IType::prod(IType::Unit, IType::Nat),
+        ),
+    );
+
+    let optimized = assert_round_trip(optimize, &instrs, alloc_left, &in_ty, &out_ty);
+    assert_round_trip(optimize, &instrs, alloc_right, &in_ty, &out_ty);
+    assert!(optimized == [
+        op(OP_START),
+        DecodedInstr::with_pair(sums, 2, 1),
+        op(OP_UNITI),
+        op(OP_SWAPP),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 2, 1),
+        DecodedInstr::with_pair(call, 8, 10),
+        op(OP_END),
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_END),
+    ]);
+}
+
+#[test]
+fn test_keep_nat_overflow() {
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::new(encode_i(OP_FOLD, FOLD_NAT).unwrap()),
+        DecodedInstr::new(encode_i(OP_UFOLD, FOLD_NAT).unwrap()),
+        op(OP_END),
+    ];
+    let optimized = optimize(&instrs).unwrap();
+    assert!(optimized == instrs);
+
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let data = mem.alloc(Sum::new(
+        1,
+        CellPtr::new_with(mem.alloc(Nat::MAX).unwrap())
+    )).unwrap();
+    let thread = alloc_thread(&mem, &optimized, data.as_untyped(&mem));
+
+    match thread.run(&mem) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::IntOverflow),
+        Ok(_) => panic!("nat overflow went undetected"),
+    }
+}
+
+#[test]
+fn test_keep_missing_zero() {
+    let instrs = [
+        op(OP_START),
+        op(OP_ZEROE),
+        op(OP_ZEROI),
+        op(OP_END),
+    ];
+    let optimized = optimize(&instrs).unwrap();
+    assert!(optimized == instrs);
+
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_thread(&mem, &optimized, alloc_left(&mem));
+
+    match thread.run(&mem) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::ExpectedZero),
+        Ok(_) => panic!("removed a zero which wasn't there"),
+    }
+}
+
+fn inline_small(instrs: &[DecodedInstr]) -> Result<Vec<DecodedInstr>, RuntimeError> {
+    inline(instrs, 8)
+}
+
+// the recursion test's output type spells out each step down from 2
+fn alloc_two<'guard>(mem: &'guard MutatorView) -> UntypedScopedPtr<'guard> {
+    mem.alloc(2 as Nat).unwrap().as_untyped(mem)
+}
+
+#[test]
+fn test_inline_calls() {
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 4, 7),
+        DecodedInstr::with_pair(encode_i(OP_UNCALL, 0).unwrap(), 8, 10),
+        op(OP_END),
+        op(OP_START),
+        op(OP_ZEROI),
+        op(OP_UNITI),
+        op(OP_END),
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_END),
+    ];
+    let in_ty = IType::prod(IType::Nat, IType::Nat);
+    let out_ty = IType::sum(IType::Zero, in_ty.clone());
+
+    let inlined = assert_round_trip(inline_small, &instrs, alloc_pair, &in_ty, &out_ty);
+    assert!(inlined[..5] == [
+        op(OP_START),
+        op(OP_ZEROI),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_END),
+    ]);
+
+    // callees are kept after the entry function
+    assert!(inlined[5..] == instrs[4..]);
+}
+
+#[test]
+fn test_inline_inverted_combinators() {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_UNCALL, 0).unwrap(), 3, 11),
+        op(OP_END),
+        op(OP_START),
+        DecodedInstr::with_pair(sums, 1, 4),
+        op(OP_UNITI),
+        DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), 1),
+        op(OP_ZEROI),
+        op(OP_UNITI),
+        DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 3),
+        DecodedInstr::with_pair(sume, 1, 4),
+        op(OP_END),
+    ];
+
+    // uncalling takes the callee's outputs back to its inputs
+    let in_ty = IType::sum(
+        IType::prod(IType::Unit, IType::Nat),
+        IType::prod(IType::Nat, IType::prod(IType::Unit, IType::sum(IType::Zero, IType::Nat))),
+    );
+    let out_ty = IType::sum(IType::Nat, IType::prod(IType::Nat, IType::Nat));
+    let alloc_left: AllocData = |mem| {
+        let unit = mem.alloc(Unit::new()).unwrap();
+        let nat = mem.alloc(4 as Nat).unwrap();
+        let prod = mem.alloc(Product::new(
+            CellPtr::new_with(unit),
+            CellPtr::new_with(nat),
+        )).unwrap();
+
+        mem.alloc(Sum::new(0, CellPtr::new_with(prod))).unwrap().as_untyped(mem)
+    };
+    let alloc_right: AllocData = |mem| {
+        let unit = mem.alloc(Unit::new()).unwrap();
+        let snd = mem.alloc(Sum::new(1, CellPtr::new_with(mem.alloc(69 as Nat).unwrap())))
+            .unwrap();
+        let snd = mem.alloc(Product::new(
+            CellPtr::new_with(unit),
+            CellPtr::new_with(snd),
+        )).unwrap();
+        let prod = mem.alloc(Product::new(
+            CellPtr::new_with(mem.alloc(420 as Nat).unwrap()),
+            CellPtr::new_with(snd),
+        )).unwrap();
+
+        mem.alloc(Sum::new(1, CellPtr::new_with(prod))).unwrap().as_untyped(mem)
+    };
+
+    let inlined = assert_round_trip(inline_small, &instrs, alloc_left, &in_ty, &out_ty);
+    assert_round_trip(inline_small, &instrs, alloc_right, &in_ty, &out_ty);
+
+    // both ends of each combinator swap places, and each half runs in reverse
+    assert!(inlined[..9] == [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_SUMS, 1).unwrap(), 1, 4),
+        op(OP_UNITE),
+        DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), 1),
+        op(OP_UNITE),
+        op(OP_ZEROE),
+        DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 3),
+        DecodedInstr::with_pair(encode_i(OP_SUME, 1).unwrap(), 1, 4),
+        op(OP_END),
+    ]);
+}
+
+#[test]
+fn test_inline_recursion() {
+    let call = encode_i(OP_CALL, 0).unwrap();
+    let ufold = DecodedInstr::new(encode_i(OP_UFOLD, FOLD_NAT).unwrap());
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(call, 3, 9),
+        op(OP_END),
+        op(OP_START),
+        ufold,
+        DecodedInstr::with_pair(sums, 0, 2),
+        DecodedInstr::with_pair(call, 3, 9),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 0, 2),
+        op(OP_END),
+    ];
+    let out_ty = IType::sum(
+        IType::Unit,
+        IType::prod(
+            IType::Unit,
+            IType::sum(
+                IType::Unit,
+                IType::prod(IType::Unit, IType::sum(IType::Unit, IType::Unit)),
+            ),
+        ),
+    );
+
+    // the entry gets one copy of the body, and the recursive calls are
+    // left alone but re-targeted
+    let inlined = assert_round_trip(inline_small, &instrs, alloc_two, &IType::Nat, &out_ty);
+    assert!(inlined == [
+        op(OP_START),
+        ufold,
+        DecodedInstr::with_pair(sums, 0, 2),
+        DecodedInstr::with_pair(call, 7, 13),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 0, 2),
+        op(OP_END),
+        op(OP_START),
+        ufold,
+        DecodedInstr::with_pair(sums, 0, 2),
+        DecodedInstr::with_pair(call, 7, 13),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 0, 2),
+        op(OP_END),
+    ]);
+}
+
+#[test]
+fn test_inline_budget() {
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 3, 6),
+        op(OP_END),
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_ZEROI),
+        op(OP_END),
+    ];
+    assert!(inline(&instrs, 1).unwrap() == instrs);
+    assert!(inline(&instrs, 2).unwrap()[..4]
+            == [op(OP_START), op(OP_UNITI), op(OP_ZEROI), op(OP_END)]);
+
+    // the budget counts what a callee inlines in turn, so g, which
+    // calls h twice, is too large at 3
+    let call = encode_i(OP_CALL, 0).unwrap();
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(call, 3, 6),
+        op(OP_END),
+        op(OP_START),
+        DecodedInstr::with_pair(call, 7, 10),
+        DecodedInstr::with_pair(call, 7, 10),
+        op(OP_END),
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_END),
+    ];
+    let inlined = inline(&instrs, 3).unwrap();
+    assert!(inlined[1].opcode() == OP_CALL && inlined[2] == op(OP_END));
+    assert!(inline(&instrs, 4).unwrap()[..6] == [
+        op(OP_START),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_UNITI),
+        op(OP_UNITE),
+        op(OP_END),
+    ]);
+
+    // callees which turn execution around stay as calls
+    let instrs = [
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 3, 5),
+        op(OP_END),
+        op(OP_START),
+        DecodedInstr::new(encode_i(OP_EXPN, 0).unwrap()),
+        op(OP_END),
+    ];
+    assert!(inline(&instrs, 8).unwrap() == instrs);
+}
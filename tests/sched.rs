@@ -0,0 +1,87 @@
+use iris::value::Value;
+use iris::constants::*;
+use iris::error::ErrorKind;
+use iris::memory::{Memory, MutatorView};
+use iris::sched::{Scheduler, TaskState};
+use iris::types::IType;
+
+mod common;
+use common::*;
+
+fn data(mem: &MutatorView, sched: &Scheduler, id: usize, ty: &IType) -> Value {
+    Value::from_heap(mem, ty, sched.thread(mem, id).data().get(mem)).unwrap()
+}
+
+#[test]
+fn test_ping_pong() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let mut sched = Scheduler::new(1);
+
+    // b sends back whatever a sends it
+    let a = sched.spawn(alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, 6), io(OP_READ, 6)],
+        &pair(Value::Nat(9), Value::Unit),
+    ));
+    let b = sched.spawn(alloc_body_thread(&mem, &[io(OP_READ, 6), io(OP_WRITE, 6)], &Value::Unit));
+    sched.connect(&mem, (a, 6), (b, 6), IType::Nat);
+
+    sched.run(&mem).unwrap();
+    assert!(sched.state(a) == TaskState::Finished && sched.state(b) == TaskState::Finished);
+    assert!(data(&mem, &sched, a, &IType::prod(IType::Nat, IType::Unit)) == pair(Value::Nat(9), Value::Unit));
+    assert!(data(&mem, &sched, b, &IType::Unit) == Value::Unit);
+
+    // and back again, meeting in the opposite order
+    sched.reverse(&mem);
+    sched.run(&mem).unwrap();
+    assert!(data(&mem, &sched, a, &IType::prod(IType::Nat, IType::Unit)) == pair(Value::Nat(9), Value::Unit));
+    assert!(data(&mem, &sched, b, &IType::Unit) == Value::Unit);
+
+    // closing drops the pipe from both ends
+    let (a, b) = (sched.thread(&mem, a), sched.thread(&mem, b));
+    sched.close(&mem);
+    assert!(!a.channel_open(6) && !b.channel_open(6));
+}
+
+#[test]
+fn test_blocking() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let mut sched = Scheduler::new(1);
+
+    let reader = sched.spawn(alloc_body_thread(
+        &mem,
+        &[io(OP_READ, 6), io(OP_READ, 6), io(OP_READ, 6)],
+        &Value::Unit,
+    ));
+    let writer = sched.spawn(alloc_body_thread(
+        &mem,
+        &[io(OP_WRITE, 6), io(OP_WRITE, 6), io(OP_WRITE, 6)],
+        &pair(Value::Nat(1), pair(Value::Nat(2), pair(Value::Nat(3), Value::Unit))),
+    ));
+    sched.connect(&mem, (reader, 6), (writer, 6), IType::Nat);
+
+    // START, then nothing to read yet; the writer's offer waits too
+    assert!(sched.round(&mem).unwrap());
+    assert!(!sched.round(&mem).unwrap());
+    assert!(sched.state(reader) == TaskState::Blocked && sched.state(writer) == TaskState::Blocked);
+
+    sched.run(&mem).unwrap();
+    let ty = IType::prod(IType::Nat, IType::prod(IType::Nat, IType::prod(IType::Nat, IType::Unit)));
+    assert!(data(&mem, &sched, reader, &ty)
+        == pair(Value::Nat(3), pair(Value::Nat(2), pair(Value::Nat(1), Value::Unit))));
+}
+
+#[test]
+fn test_deadlock() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let mut sched = Scheduler::new(4);
+
+    let a = sched.spawn(alloc_body_thread(&mem, &[io(OP_READ, 6)], &Value::Unit));
+    let b = sched.spawn(alloc_body_thread(&mem, &[io(OP_READ, 6)], &Value::Unit));
+    sched.connect(&mem, (a, 6), (b, 6), IType::Nat);
+
+    assert!(*sched.run(&mem).unwrap_err().error_kind() == ErrorKind::Deadlock);
+}
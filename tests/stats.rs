@@ -0,0 +1,74 @@
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::memory::{Memory, MutatorView};
+use iris::safeptr::*;
+use iris::vm::Thread;
+
+mod common;
+use common::*;
+
+#[test]
+fn test_memory_stats() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+
+    let before = binding.stats();
+    let data = mem.alloc(Product::new(
+        CellPtr::new_with(mem.alloc(420 as u32).unwrap()),
+        CellPtr::new_with(mem.alloc(69 as u32).unwrap()),
+    )).unwrap();
+
+    let after = binding.stats();
+    assert!(after.small_allocs == before.small_allocs + 3);
+    assert!(after.live_bytes() > before.live_bytes());
+    assert!(after.peak_bytes >= after.live_bytes());
+    assert!(after.block_count() == 1);
+
+    mem.dealloc(data.fst(&mem)).unwrap();
+    mem.dealloc(data.snd(&mem)).unwrap();
+    mem.dealloc(data).unwrap();
+
+    let freed = binding.stats();
+    assert!(freed.frees == after.frees + 3);
+    assert!(freed.live_bytes() == before.live_bytes());
+    assert!(freed.peak_bytes == after.peak_bytes);
+}
+
+#[test]
+fn test_alloc_counts_per_opcode() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let test_fn = Function::alloc(&mem).unwrap();
+
+    push_op(&mem, test_fn, OP_START);
+    push_op(&mem, test_fn, OP_ZEROI);
+    push_op(&mem, test_fn, OP_ZEROE);
+    push_op(&mem, test_fn, OP_UNITI);
+    push_op(&mem, test_fn, OP_UNITE);
+    push_op(&mem, test_fn, OP_END);
+
+    let data = mem.alloc(1337 as u32).unwrap();
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(data.as_untyped(&mem)),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+
+    thread.count_allocs(true);
+    let before = mem.stats();
+    thread.run(&mem).unwrap();
+    let after = mem.stats();
+
+    let counts = thread.alloc_counts();
+    assert!(counts[OP_ZEROI as usize] == 1);
+    assert!(counts[OP_ZEROE as usize] == 0);
+    assert!(counts[OP_UNITI as usize] == 2);
+    assert!(counts[OP_UNITE as usize] == 0);
+
+    // the program is reversible, so it should be allocation-neutral
+    assert!(after.live_bytes() == before.live_bytes());
+
+    let result = thread.data().get(&mem);
+    assert!(&1337 == unsafe { result.cast::<Nat>(&mem) }.as_ref(&mem));
+}
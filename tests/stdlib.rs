@@ -0,0 +1,223 @@
+use iris::value::Value;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::{ErrorKind, RuntimeError};
+use iris::memory::{Memory, MutatorView};
+use iris::safeptr::*;
+use iris::stdlib::Library;
+use iris::types::IType;
+use iris::vm::Thread;
+
+mod common;
+use common::*;
+
+// runs the named function (or its inverse) on `input`
+fn run(lib: &Library, name: &str, invert: bool, input: &Value) -> Result<Value, RuntimeError> {
+    let iso = lib.get(name).unwrap();
+    let out_ty = if invert { &iso.signature.input } else { &iso.signature.output };
+
+    let mut instrs = vec![op(OP_START), lib.call(name, 3, invert).unwrap(), op(OP_END)];
+    instrs.extend(lib.link(3));
+
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let function = alloc_function(&mem, &instrs)?;
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(function),
+        CellPtr::new_with(input.alloc(&mem)?),
+    ))?;
+    let thread = Thread::alloc_with_arg(&mem, arg)?;
+
+    thread.run(&mem)?;
+    Value::from_heap(&mem, out_ty, thread.data().get(&mem))
+}
+
+// checks the function maps `input` to `output`, and back again
+fn assert_iso(lib: &Library, name: &str, input: Value, output: Value) {
+    assert!(run(lib, name, false, &input).unwrap() == output);
+    assert!(run(lib, name, true, &output).unwrap() == input);
+}
+
+fn assert_fails(result: Result<Value, RuntimeError>, kind: ErrorKind) {
+    match result {
+        Err(e) => assert!(*e.error_kind() == kind),
+        Ok(v) => panic!("expected {:?}, got {:?}", kind, v),
+    }
+}
+
+fn bool(b: bool) -> Value {
+    Value::Sum(b as u32, Box::new(Value::Unit))
+}
+
+fn int(i: i64) -> Value {
+    if i >= 0 {
+        Value::Sum(0, Box::new(nat(i as u32)))
+    } else {
+        Value::Sum(1, Box::new(nat((-i - 1) as u32)))
+    }
+}
+
+#[test]
+fn test_inc_dec() {
+    let lib = Library::new();
+
+    assert_iso(&lib, "inc", nat(41), nat(42));
+    assert_iso(&lib, "dec", nat(42), nat(41));
+    assert_fails(run(&lib, "dec", false, &nat(0)), ErrorKind::ExpectedZero);
+    assert_fails(run(&lib, "inc", true, &nat(0)), ErrorKind::ExpectedZero);
+    assert_fails(run(&lib, "inc", false, &nat(u32::MAX)), ErrorKind::IntOverflow);
+}
+
+#[test]
+fn test_add_sub() {
+    let lib = Library::new();
+
+    for a in 0..5 {
+        for b in 0..5 {
+            assert_iso(&lib, "add", pair(nat(a), nat(b)), pair(nat(a), nat(a + b)));
+        }
+    }
+    assert_iso(&lib, "sub", pair(nat(3), nat(7)), pair(nat(3), nat(4)));
+    assert_fails(run(&lib, "sub", false, &pair(nat(5), nat(2))), ErrorKind::ExpectedZero);
+}
+
+#[test]
+fn test_mul() {
+    let lib = Library::new();
+
+    for a in 0..4 {
+        for b in 0..4 {
+            assert_iso(
+                &lib,
+                "mul",
+                pair(nat(a), pair(nat(b), nat(5))),
+                pair(nat(a), pair(nat(b), nat(5 + a * b))),
+            );
+        }
+    }
+    // an accumulator smaller than the product can't have come from mul
+    assert_fails(
+        run(&lib, "mul", true, &pair(nat(3), pair(nat(4), nat(11)))),
+        ErrorKind::ExpectedZero,
+    );
+}
+
+#[test]
+fn test_triangle() {
+    let lib = Library::new();
+
+    for n in 0..6 {
+        let sum = (0..n).sum::<u32>();
+        assert_iso(&lib, "triangle", pair(nat(n), nat(2)), pair(nat(n), nat(2 + sum)));
+    }
+    assert_fails(
+        run(&lib, "triangle", true, &pair(nat(4), nat(5))),
+        ErrorKind::ExpectedZero,
+    );
+}
+
+#[test]
+fn test_loop_depth() {
+    let lib = Library::new();
+    let instrs = lib.program("add").unwrap();
+
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let function = alloc_function(&mem, &instrs).unwrap();
+    let input = pair(nat(3000), nat(1));
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(function),
+        CellPtr::new_with(input.alloc(&mem).unwrap()),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+
+    // a trace doesn't grow the context stack from one pass to the next
+    thread.set_max_depth(&mem, 16);
+    thread.run(&mem).unwrap();
+
+    let ty = &lib.get("add").unwrap().signature.output;
+    let output = Value::from_heap(&mem, ty, thread.data().get(&mem)).unwrap();
+    assert!(output == pair(nat(3000), nat(3001)));
+}
+
+#[test]
+fn test_lt() {
+    let lib = Library::new();
+
+    for a in 0..4 {
+        for b in 0..4 {
+            let input = pair(nat(a), nat(b));
+            assert_iso(&lib, "lt", input.clone(), pair(bool(a < b), input));
+        }
+    }
+    assert_fails(
+        run(&lib, "lt", true, &pair(bool(true), pair(nat(2), nat(1)))),
+        ErrorKind::ExpectedZero,
+    );
+    assert_fails(
+        run(&lib, "lt", true, &pair(bool(false), pair(nat(1), nat(2)))),
+        ErrorKind::ExpectedZero,
+    );
+}
+
+#[test]
+fn test_diff() {
+    let lib = Library::new();
+
+    for a in 0..4 {
+        for b in 0..4 {
+            let d = b as i64 - a as i64;
+            assert_iso(&lib, "diff", pair(nat(a), nat(b)), pair(nat(a.min(b)), int(d)));
+        }
+    }
+}
+
+#[test]
+fn test_neg() {
+    let lib = Library::new();
+
+    for i in -4..=4 {
+        assert_iso(&lib, "neg", int(i), int(-i));
+    }
+}
+
+#[test]
+fn test_cantor_pairing() {
+    let lib = Library::new();
+
+    for x in 0..4 {
+        for y in 0..4 {
+            let n = (x + y) * (x + y + 1) / 2 + y;
+            assert_iso(&lib, "pair", pair(nat(x), nat(y)), nat(n));
+            assert_iso(&lib, "unpair", nat(n), pair(nat(x), nat(y)));
+        }
+    }
+}
+
+#[test]
+fn test_large_inputs() {
+    let lib = Library::new();
+
+    // each of these goes round its loop far more times than the
+    // context stack could hold calls
+    let input = pair(nat(300), nat(400));
+    assert_iso(&lib, "lt", input.clone(), pair(bool(true), input));
+    assert_iso(&lib, "pair", pair(nat(20), nat(20)), nat(840));
+    assert_iso(&lib, "pair", pair(nat(40), nat(0)), nat(820));
+    assert_iso(&lib, "unpair", nat(1890), pair(nat(0), nat(60)));
+}
+
+#[test]
+fn test_link() {
+    let lib = Library::new();
+
+    for iso in lib.isos() {
+        let instrs = lib.program(iso.name).unwrap();
+        verify_function(&instrs).unwrap();
+        assert!(instrs[iso.start + 3].opcode() == OP_START);
+        assert!(instrs[iso.end + 3].opcode() == OP_END);
+    }
+    assert!(lib.program("sqrt").is_none());
+    assert!(lib.get("neg").unwrap().signature.input == IType::sum(IType::Nat, IType::Nat));
+}
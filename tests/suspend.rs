@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::PathBuf;
+
+use iris::value::Value;
+use iris::bytecode::*;
+use iris::channel::Scripted;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{HeapMode, HeapOptions, Memory, MutatorView};
+use iris::suspend::Image;
+use iris::types::IType;
+use iris::vm::{EvalStatus, Thread};
+
+mod common;
+use common::*;
+
+fn tagged_memory() -> Memory {
+    Memory::with_options(HeapOptions { mode: HeapMode::Checked, type_tags: true })
+}
+
+fn scratch_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("iris-{}-{}.image", name, std::process::id()))
+}
+
+// a call to a function made of a product combinator, whose first half
+// is a sum combinator
+fn program() -> Vec<DecodedInstr> {
+    let sums = encode_i(OP_SUMS, 1).unwrap();
+    let sume = encode_i(OP_SUME, 1).unwrap();
+
+    vec![
+        op(OP_START),
+        DecodedInstr::with_pair(encode_i(OP_CALL, 0).unwrap(), 4, 14),
+        op(OP_ID),
+        op(OP_END),
+        op(OP_START),
+        DecodedInstr::with_nat(encode_i(OP_PRODS, 0).unwrap(), 6),
+        DecodedInstr::with_pair(sums, 1, 2),
+        op(OP_UNITI),
+        op(OP_UNITI),
+        op(OP_UNITI),
+        DecodedInstr::with_pair(sume, 1, 2),
+        op(OP_ZEROI),
+        op(OP_UNITI),
+        DecodedInstr::with_nat(encode_i(OP_PRODE, 0).unwrap(), 3),
+        op(OP_END),
+    ]
+}
+
+fn input(tag: Nat) -> Value {
+    Value::Product(
+        Box::new(Value::Sum(tag, Box::new(Value::Nat(7)))),
+        Box::new(Value::BigNat(vec![1, 2])),
+    )
+}
+
+fn in_ty() -> IType {
+    IType::prod(IType::sum(IType::Nat, IType::Nat), IType::BigNat)
+}
+
+fn out_ty() -> IType {
+    IType::prod(
+        IType::sum(
+            IType::prod(IType::Unit, IType::Nat),
+            IType::prod(IType::Unit, IType::prod(IType::Unit, IType::Nat)),
+        ),
+        IType::prod(IType::Unit, IType::sum(IType::Zero, IType::BigNat)),
+    )
+}
+
+// runs `steps` instructions in one memory, then suspends and finishes
+// the run in another
+fn finish_elsewhere(tag: Nat, backward: bool, steps: u64) -> Value {
+    let path = scratch_file(&format!("resume-{}-{}-{}", tag, backward, steps));
+
+    {
+        let binding = tagged_memory();
+        let mem = MutatorView::new(&binding);
+        let thread = alloc_thread_with(&mem, &program(), &input(tag));
+
+        if backward {
+            thread.run(&mem).unwrap();
+            thread.reverse(&mem);
+        }
+        let before = thread.steps();
+        assert!(thread.run_for(&mem, steps).unwrap() == EvalStatus::Pending);
+        assert!(thread.steps() == before + steps);
+
+        let image = thread.image(&mem).unwrap();
+        assert!(Image::parse(&image.to_string()).unwrap() == image);
+        thread.suspend(&mem, &path).unwrap();
+    }
+
+    let binding = tagged_memory();
+    let mem = MutatorView::new(&binding);
+    let thread = Thread::resume(&mem, &path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    thread.run(&mem).unwrap();
+    let ty = if backward { in_ty() } else { out_ty() };
+    Value::from_heap(&mem, &ty, thread.data().get(&mem)).unwrap()
+}
+
+#[test]
+fn test_resume_anywhere() {
+    for tag in 0..2 {
+        let binding = tagged_memory();
+        let mem = MutatorView::new(&binding);
+        let thread = alloc_thread_with(&mem, &program(), &input(tag));
+        thread.run(&mem).unwrap();
+
+        let output = Value::from_heap(&mem, &out_ty(), thread.data().get(&mem)).unwrap();
+        let steps = thread.steps();
+
+        // every point in the run, inside the call and both combinators
+        for step in 0..steps {
+            assert!(finish_elsewhere(tag, false, step) == output);
+        }
+
+        // and undoing it
+        thread.reverse(&mem);
+        thread.run(&mem).unwrap();
+        let undo_steps = thread.steps() - steps;
+        for step in 0..undo_steps {
+            assert!(finish_elsewhere(tag, true, step) == input(tag));
+        }
+    }
+}
+
+#[test]
+fn test_resume_then_reverse() {
+    let path = scratch_file("reverse");
+
+    {
+        let binding = tagged_memory();
+        let mem = MutatorView::new(&binding);
+        let thread = alloc_thread_with(&mem, &program(), &input(1));
+        thread.run_for(&mem, 5).unwrap();
+        thread.suspend(&mem, &path).unwrap();
+    }
+
+    // a resumed thread can be undone back past where it was suspended
+    let binding = tagged_memory();
+    let mem = MutatorView::new(&binding);
+    let thread = Thread::resume(&mem, &path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(thread.steps() == 5);
+    thread.run(&mem).unwrap();
+    thread.reverse(&mem);
+    thread.run(&mem).unwrap();
+    assert!(Value::from_heap(&mem, &in_ty(), thread.data().get(&mem)).unwrap() == input(1));
+    assert!(thread.context_depth(&mem) == 1);
+}
+
+#[test]
+fn test_channels_reattached() {
+    let path = scratch_file("channels");
+    let instrs = [
+        op(OP_START),
+        op(OP_UNITI),
+        DecodedInstr::new(encode_i(OP_READ, 6).unwrap()),
+        op(OP_END),
+    ];
+
+    {
+        let binding = tagged_memory();
+        let mem = MutatorView::new(&binding);
+        let thread = alloc_thread_with(&mem, &instrs, &Value::Nat(3));
+        thread.run_for(&mem, 2).unwrap();
+        thread.suspend(&mem, &path).unwrap();
+    }
+
+    let binding = tagged_memory();
+    let mem = MutatorView::new(&binding);
+    let thread = Thread::resume(&mem, &path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    // channels stay behind, so the host attaches them again
+    assert!(!thread.channel_open(6));
+    let script = Scripted::new(IType::Nat);
+    script.queue_read(Value::Nat(5));
+    thread.attach_channel(6, Box::new(script.clone()));
+
+    thread.run(&mem).unwrap();
+    assert!(script.finished());
+    let ty = IType::prod(IType::Nat, IType::prod(IType::Unit, IType::Nat));
+    let output = Value::from_heap(&mem, &ty, thread.data().get(&mem)).unwrap();
+    assert!(output == Value::Product(
+        Box::new(Value::Nat(5)),
+        Box::new(Value::Product(Box::new(Value::Unit), Box::new(Value::Nat(3)))),
+    ));
+}
+
+#[test]
+fn test_untagged_heap() {
+    let binding = Memory::with_options(HeapOptions {
+        mode: HeapMode::Unchecked,
+        type_tags: false,
+    });
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_thread_with(&mem, &program(), &input(0));
+
+    // without tags there's no telling what the data is
+    match thread.image(&mem) {
+        Err(e) => assert!(matches!(e.error_kind(), ErrorKind::EvalError(_)), "{}", e),
+        Ok(_) => panic!("suspended a thread on an untagged heap"),
+    }
+}
+
+#[test]
+fn test_suspendable_options() {
+    // the defaults only have tags in debug builds, so a heap which may
+    // be suspended has to ask for them
+    assert!(HeapOptions::default().type_tags == cfg!(debug_assertions));
+    assert!(HeapOptions::suspendable().type_tags);
+    assert!(HeapOptions::suspendable().mode == HeapOptions::default().mode);
+
+    let binding = Memory::with_options(HeapOptions::suspendable());
+    let mem = MutatorView::new(&binding);
+    let thread = alloc_thread_with(&mem, &program(), &input(0));
+    thread.run_for(&mem, 3).unwrap();
+    assert!(thread.image(&mem).is_ok());
+}
@@ -0,0 +1,183 @@
+use iris::array::StackContainer;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{HeapMode, HeapOptions, Memory, MutatorView, TypeTag};
+use iris::safeptr::*;
+use iris::vm::Thread;
+
+mod common;
+use common::*;
+
+fn tagged_memory(mode: HeapMode) -> Memory {
+    Memory::with_options(HeapOptions { mode, type_tags: true })
+}
+
+#[test]
+fn test_read_tags() {
+    let binding = tagged_memory(HeapMode::Unchecked);
+    let mem = MutatorView::new(&binding);
+
+    let nat = mem.alloc(1337 as u32).unwrap();
+    let unit = mem.alloc(Unit::new()).unwrap();
+    let sum = mem.alloc(Sum::new(0, CellPtr::new_with(nat))).unwrap();
+    let prod = mem.alloc(Product::new(
+        CellPtr::new_with(unit),
+        CellPtr::new_with(nat),
+    )).unwrap();
+    let list = Inductive::<Nat>::alloc(&mem).unwrap();
+    let big = BigNat::alloc(&mem).unwrap();
+
+    assert!(mem.type_tag(nat) == Some(TypeTag::Nat));
+    assert!(mem.type_tag(unit) == Some(TypeTag::Unit));
+    assert!(mem.type_tag(sum) == Some(TypeTag::Sum));
+    assert!(mem.type_tag(prod) == Some(TypeTag::Product));
+    assert!(mem.type_tag(list) == Some(TypeTag::Array));
+    // arrays of plain words are told apart from lists
+    assert!(mem.type_tag(big) == Some(TypeTag::Words));
+    assert!(mem.check_tag::<_, Inductive<()>>(big).is_err());
+
+    assert!(mem.check_tag::<_, Sum<Nat>>(sum).is_ok());
+    assert!(mem.check_tag::<_, Product<(), ()>>(sum).is_err());
+}
+
+#[test]
+fn test_untagged_heap() {
+    let binding = Memory::with_options(HeapOptions {
+        mode: HeapMode::Unchecked,
+        type_tags: false,
+    });
+    let mem = MutatorView::new(&binding);
+
+    let nat = mem.alloc(1337 as u32).unwrap();
+    assert!(mem.type_tag(nat) == None);
+    assert!(mem.check_tag::<_, Sum<()>>(nat).is_ok());
+}
+
+#[test]
+fn test_bad_cast_in_vm() {
+    let binding = tagged_memory(HeapMode::Unchecked);
+    let mem = MutatorView::new(&binding);
+    let test_fn = Function::alloc(&mem).unwrap();
+
+    push_op(&mem, test_fn, OP_START);
+    push_op(&mem, test_fn, OP_ZEROE);
+    push_op(&mem, test_fn, OP_END);
+
+    // zeroe expects a sum, but is handed a nat
+    let data = mem.alloc(1337 as u32).unwrap();
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(data.as_untyped(&mem)),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+
+    match thread.run(&mem) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::TypeError),
+        Ok(_) => panic!("bad cast went undetected"),
+    }
+}
+
+#[test]
+fn test_tags_with_checked_heap() {
+    let binding = tagged_memory(HeapMode::Checked);
+    let mem = MutatorView::new(&binding);
+    let test_fn = Function::alloc(&mem).unwrap();
+
+    push_op(&mem, test_fn, OP_START);
+    push_op(&mem, test_fn, OP_UNITI);
+    push_op(&mem, test_fn, OP_UNITE);
+    push_op(&mem, test_fn, OP_END);
+
+    let data = mem.alloc(1337 as u32).unwrap();
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(data.as_untyped(&mem)),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(&mem, arg).unwrap();
+    thread.run(&mem).unwrap();
+
+    let result = thread.data().get(&mem);
+    assert!(mem.type_tag(result) == Some(TypeTag::Nat));
+    assert!(&1337 == unsafe { result.cast::<Nat>(&mem) }.as_ref(&mem));
+}
+
+// runs a single instruction on data that only has the wrong type inside
+fn assert_inner_type_error<'guard>(
+    mem: &'guard MutatorView,
+    op: Opcode,
+    data: UntypedScopedPtr<'guard>,
+) {
+    let test_fn = Function::alloc(mem).unwrap();
+
+    push_op(mem, test_fn, OP_START);
+    let instr = alloc_instr(mem, op).unwrap();
+    test_fn.push(mem, CellPtr::new_with(instr)).unwrap();
+    push_op(mem, test_fn, OP_END);
+
+    let arg = mem.alloc(Product::new(
+        CellPtr::new_with(test_fn),
+        CellPtr::new_with(data),
+    )).unwrap();
+    let thread = Thread::alloc_with_arg(mem, arg).unwrap();
+
+    match thread.run(mem) {
+        Err(e) => assert!(*e.error_kind() == ErrorKind::TypeError),
+        Ok(_) => panic!("bad inner cast went undetected"),
+    }
+}
+
+#[test]
+fn test_bad_inner_cast_in_vm() {
+    let binding = tagged_memory(HeapMode::Unchecked);
+    let mem = MutatorView::new(&binding);
+    let nat = |n: Nat| mem.alloc(n).unwrap().as_untyped(&mem);
+    let pair = |fst, snd| mem.alloc(Product::new(
+        CellPtr::new_with(fst),
+        CellPtr::new_with(snd),
+    )).unwrap().as_untyped(&mem);
+    let sum = |tag, data| mem.alloc(Sum::new(tag, CellPtr::new_with(data)))
+        .unwrap()
+        .as_untyped(&mem);
+
+    // unite expects a unit on the left
+    assert_inner_type_error(&mem, encode_i(OP_UNITE, 0).unwrap(), pair(nat(1), nat(2)));
+
+    // fold_nat expects a nat on the right
+    assert_inner_type_error(&mem, encode_i(OP_FOLD, FOLD_NAT).unwrap(), sum(1, pair(nat(1), nat(2))));
+
+    // dist expects a sum on the left, fact a product inside
+    assert_inner_type_error(&mem, encode_s(OP_DIST, 1, 1).unwrap(), pair(nat(1), nat(2)));
+    assert_inner_type_error(&mem, encode_s(OP_FACT, 1, 1).unwrap(), sum(0, nat(1)));
+
+    // lists and bignats are distinct
+    let list = Inductive::<Nat>::alloc(&mem).unwrap().as_untyped(&mem);
+    assert_inner_type_error(&mem, encode_i(OP_UFOLD, FOLD_BIGNAT).unwrap(), list);
+}
+
+#[test]
+fn test_bad_shuffle_in_checked_heap() {
+    let binding = tagged_memory(HeapMode::Checked);
+    let mem = MutatorView::new(&binding);
+    let nat = |n: Nat| mem.alloc(n).unwrap().as_untyped(&mem);
+    let pair = |fst, snd| mem.alloc(Product::new(
+        CellPtr::new_with(fst),
+        CellPtr::new_with(snd),
+    )).unwrap().as_untyped(&mem);
+    let sum = |tag, data| mem.alloc(Sum::new(tag, CellPtr::new_with(data)))
+        .unwrap()
+        .as_untyped(&mem);
+    let neg = |data| mem.alloc(Negative::new(CellPtr::new_with(data)))
+        .unwrap()
+        .as_untyped(&mem);
+
+    // the associativity ops expect a product on one side
+    assert_inner_type_error(&mem, encode_i(OP_ASSRP, 0).unwrap(), pair(nat(1), nat(2)));
+    assert_inner_type_error(&mem, encode_i(OP_ASSLP, 0).unwrap(), pair(nat(1), nat(2)));
+
+    // coln (expn run forwards) expects a negative on the left, and a
+    // sum inside it when the sum is split
+    assert_inner_type_error(&mem, encode_i(OP_COLN, 0).unwrap(), sum(0, nat(1)));
+    assert_inner_type_error(&mem, encode_i(OP_COLN, 1).unwrap(), sum(0, neg(nat(1))));
+}
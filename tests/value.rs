@@ -0,0 +1,196 @@
+use iris::array::StackContainer;
+use iris::bytecode::*;
+use iris::constants::*;
+use iris::data::*;
+use iris::error::ErrorKind;
+use iris::memory::{HeapMode, HeapOptions, Memory, MutatorView};
+use iris::safeptr::*;
+use iris::types::IType;
+use iris::value::*;
+
+mod common;
+use common::*;
+
+// (nat + (1 * nat)) * x.[nat]
+fn test_type() -> IType {
+    IType::prod(
+        IType::sum(IType::Nat, IType::prod(IType::Unit, IType::Nat)),
+        IType::ind(IType::Nat),
+    )
+}
+
+fn alloc_test_value<'guard>(
+    mem: &'guard MutatorView,
+    tag: Nat,
+    nat: Nat,
+    items: &[Nat],
+) -> UntypedScopedPtr<'guard> {
+    let variant = if tag == 0 {
+        mem.alloc(nat).unwrap().as_untyped(mem)
+    } else {
+        mem.alloc(Product::new(
+            CellPtr::new_with(mem.alloc(Unit::new()).unwrap()),
+            CellPtr::new_with(mem.alloc(nat).unwrap()),
+        )).unwrap().as_untyped(mem)
+    };
+    let sum = mem.alloc(Sum::new(tag, CellPtr::new_with(variant))).unwrap();
+
+    let list = Inductive::<()>::alloc(mem).unwrap();
+    for item in items {
+        let item = mem.alloc(*item).unwrap().as_untyped(mem);
+        list.push(mem, CellPtr::new_with(item)).unwrap();
+    }
+
+    mem.alloc(Product::new(
+        CellPtr::new_with(sum.as_untyped(mem)),
+        CellPtr::new_with(list.as_untyped(mem)),
+    )).unwrap().as_untyped(mem)
+}
+
+#[test]
+fn test_deep_copy() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = test_type();
+
+    let orig = alloc_test_value(&mem, 1, 1337, &[4, 20, 69]);
+    let copy = deep_copy(&mem, &ty, orig).unwrap();
+
+    assert!(structural_eq(&mem, &ty, orig, copy).unwrap());
+
+    // copy should share no cells with the original
+    let orig_prod = unsafe { orig.cast::<Product<Sum<()>, Inductive<Nat>>>(&mem) };
+    let copy_prod = unsafe { copy.cast::<Product<Sum<()>, Inductive<Nat>>>(&mem) };
+    assert!(orig_prod.fst(&mem).as_rawptr(&mem)
+            != copy_prod.fst(&mem).as_rawptr(&mem));
+    assert!(orig_prod.snd(&mem).as_rawptr(&mem)
+            != copy_prod.snd(&mem).as_rawptr(&mem));
+
+    let copy_inner = unsafe {
+        copy_prod.fst(&mem).data(&mem).cast::<Product<Unit, Nat>>(&mem)
+    };
+    let mut binding = copy_inner.snd(&mem).as_rawptr(&mem);
+    *binding.as_mut() = 42;
+
+    assert!(!structural_eq(&mem, &ty, orig, copy).unwrap());
+}
+
+#[test]
+fn test_structural_eq() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = test_type();
+
+    let a = alloc_test_value(&mem, 0, 1337, &[1, 2]);
+    let b = alloc_test_value(&mem, 0, 1337, &[1, 2]);
+    let other_tag = alloc_test_value(&mem, 1, 1337, &[1, 2]);
+    let other_list = alloc_test_value(&mem, 0, 1337, &[1, 2, 3]);
+
+    assert!(structural_eq(&mem, &ty, a, b).unwrap());
+    assert!(!structural_eq(&mem, &ty, a, other_tag).unwrap());
+    assert!(!structural_eq(&mem, &ty, a, other_list).unwrap());
+}
+
+#[test]
+fn test_structural_hash() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = test_type();
+
+    let a = alloc_test_value(&mem, 1, 69, &[420]);
+    let b = alloc_test_value(&mem, 1, 69, &[420]);
+    let c = alloc_test_value(&mem, 1, 69, &[]);
+
+    assert!(structural_hash(&mem, &ty, a).unwrap()
+            == structural_hash(&mem, &ty, b).unwrap());
+    assert!(structural_hash(&mem, &ty, a).unwrap()
+            != structural_hash(&mem, &ty, c).unwrap());
+}
+
+#[test]
+fn test_negative_and_fraction() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = IType::prod(IType::negative(IType::Nat), IType::frac(IType::Nat));
+
+    let neg = mem.alloc(Negative::new(
+        CellPtr::new_with(mem.alloc(7 as u32).unwrap())
+    )).unwrap();
+    let nat = mem.alloc(7 as u32).unwrap().as_untyped(&mem);
+    let frac = mem.alloc(Fraction::new(CellPtr::new_with(nat), 4)).unwrap();
+    let val = mem.alloc(Product::new(
+        CellPtr::new_with(neg.as_untyped(&mem)),
+        CellPtr::new_with(frac.as_untyped(&mem)),
+    )).unwrap().as_untyped(&mem);
+
+    let copy = deep_copy(&mem, &ty, val).unwrap();
+    let cast_copy = unsafe { copy.cast::<Product<Negative<Nat>, Fraction>>(&mem) };
+
+    assert!(structural_eq(&mem, &ty, val, copy).unwrap());
+    assert!(4 == cast_copy.snd(&mem).size());
+    assert!(cast_copy.snd(&mem).ptr().get(&mem).as_rawptr(&mem)
+            != nat.as_rawptr(&mem));
+}
+
+#[test]
+fn test_free_tree() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = test_type();
+
+    let orig = alloc_test_value(&mem, 1, 1337, &[4, 20, 69]);
+    let expect = alloc_test_value(&mem, 1, 1337, &[4, 20, 69]);
+
+    let before = mem.stats();
+    let copy = deep_copy(&mem, &ty, orig).unwrap();
+    let copied = mem.stats();
+    free_tree(&mem, &ty, copy).unwrap();
+    let freed = mem.stats();
+
+    // every object of the copy is freed, and nothing of the original
+    assert!(copied.allocs() > before.allocs());
+    assert!(freed.frees - copied.frees == copied.allocs() - before.allocs());
+    assert!(freed.bytes_freed - copied.bytes_freed
+            == copied.bytes_allocated - before.bytes_allocated);
+    assert!(structural_eq(&mem, &ty, orig, expect).unwrap());
+}
+
+#[test]
+fn test_bad_tag() {
+    let binding = Memory::new();
+    let mem = MutatorView::new(&binding);
+    let ty = IType::sum(IType::Unit, IType::Nat);
+
+    let val = mem.alloc(Sum::new(
+        2,
+        CellPtr::new_with(mem.alloc(0 as u32).unwrap())
+    )).unwrap().as_untyped(&mem);
+
+    assert!(deep_copy(&mem, &ty, val).is_err());
+}
+
+#[test]
+fn test_nested_sums() {
+    let binding = Memory::with_options(HeapOptions { mode: HeapMode::Checked, type_tags: true });
+    let mem = MutatorView::new(&binding);
+
+    // 5 : nat, into 0 + nat, into nat + 0, into 0 + (nat + 0)
+    let body = [op(OP_ZEROI), DecodedInstr::new(encode_s(OP_SWAPS, 1, 1).unwrap()), op(OP_ZEROI)];
+    let thread = alloc_body_thread(&mem, &body, &Value::Nat(5));
+    thread.run(&mem).unwrap();
+
+    let ty = IType::sum(IType::Zero, IType::sum(IType::Nat, IType::Zero));
+    let val = thread.data().get(&mem);
+    let expected = Value::Sum(1, Box::new(Value::Sum(0, Box::new(Value::Nat(5)))));
+    Value::check_heap(&mem, &ty, val).unwrap();
+    assert!(Value::from_heap(&mem, &ty, val).unwrap() == expected);
+
+    let copy = deep_copy(&mem, &ty, val).unwrap();
+    assert!(structural_eq(&mem, &ty, val, copy).unwrap());
+    free_tree(&mem, &ty, copy).unwrap();
+
+    // read as a flat sum, the nat is really a sum
+    let flat = IType::sum(IType::Zero, IType::Nat);
+    assert!(*Value::from_heap(&mem, &flat, val).unwrap_err().error_kind() == ErrorKind::TypeError);
+    assert!(*Value::check_heap(&mem, &flat, val).unwrap_err().error_kind() == ErrorKind::TypeError);
+}